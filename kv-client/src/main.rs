@@ -1,12 +1,41 @@
 use communication::replication_service_client::ReplicationServiceClient;
-use communication::PropagateDataRequest;
-use std::io::Write;
-use tonic::Request;
+use communication::{
+    ChangeOrigin, PropagateBatchRequest, PropagateDataRequest, SessionToken, SubscribeRequest,
+    WatchEvent, WatchRequest,
+};
+use std::{collections::HashMap, io::Write};
+use tonic::{
+    codegen::InterceptedService,
+    metadata::{Ascii, MetadataValue},
+    service::Interceptor,
+    transport::{Channel, Endpoint},
+    Request, Status,
+};
 
 pub mod communication {
     tonic::include_proto!("communication");
 }
 
+//sent with every request as x-cluster-id
+#[derive(Clone)]
+struct ClusterId(MetadataValue<Ascii>);
+
+impl Interceptor for ClusterId {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        request
+            .metadata_mut()
+            .insert("x-cluster-id", self.0.clone());
+        Ok(request)
+    }
+}
+
+type Client = ReplicationServiceClient<InterceptedService<Channel, ClusterId>>;
+
+async fn connect(node_addr: String, cluster: ClusterId) -> Result<Client, tonic::transport::Error> {
+    let channel = Endpoint::from_shared(node_addr)?.connect().await?;
+    Ok(ReplicationServiceClient::with_interceptor(channel, cluster))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut node_addr = String::new();
@@ -17,7 +46,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     std::io::stdin().read_line(&mut node_addr)?;
     let node_addr = String::from("http://") + node_addr.trim();
 
-    let mut client = ReplicationServiceClient::connect(node_addr.clone()).await?;
+    //nodes refuse requests meant for another cluster
+    let mut cluster_id = String::new();
+    print!("enter the cluster id of the node (empty if it has none): ");
+    std::io::stdout().flush().unwrap();
+    std::io::stdin().read_line(&mut cluster_id)?;
+    let cluster = ClusterId(MetadataValue::try_from(cluster_id.trim())?);
+
+    let mut client = connect(node_addr.clone(), cluster.clone()).await?;
     println!("connected to: {}", node_addr);
     println!(
         r#"
@@ -32,6 +68,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     "#
     );
 
+    //write ops collected between MULTI and EXEC, sent as one atomic batch
+    let mut queued: Option<Vec<PropagateDataRequest>> = None;
+    //versions of every key this session wrote or read, kept across CONNECTs so a read on
+    //another node never goes back in time
+    let mut session = SessionToken::default();
+
     loop {
         let mut user_query = String::new();
 
@@ -55,6 +97,603 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("CGET key");
             println!("CINC key amt");
             println!("CDEC key amt");
+            println!("GSET key value, GINC key amt (grow-only counter, read with CGET)");
+            println!("BSET key value, BINC key amt, BDEC key amt (counter that never goes below 0, read with CGET)");
+            println!("WSET key value, WINC key amt, WDEC key amt (counter with a 128 bit value, e.g., for money in cents, read with CGET)");
+            println!("MINC key path amt, MDEC key path amt (counter field of a map, e.g., MINC user:1 visits 1)");
+            println!("MSET key path value (register field), MADD key path member, MREM key path member (set field)");
+            println!(
+                "MDEL key path (remove a field), MGET key [path] (read the map or a field as JSON)"
+            );
+            println!(
+                "  a path goes into nested maps with dots, e.g., MSET user:1 address.city Pune"
+            );
+            println!(
+                "DSET key path json (set a value in a JSON document, path $ is the whole document)"
+            );
+            println!("DINS key path json (insert into a list at the index the path ends with), DPUSH key path json (append)");
+            println!("DDEL key path, DINC key path amt (counter in a document), DGET key [path] (read as JSON)");
+            println!("  e.g., DSET cfg $ {{\"servers\": [{{\"port\": 80}}]}}, then DSET cfg servers.0.port 8080");
+            println!("LPUSH key value, RPUSH key value, LINSERT key index value (the value ends up at index)");
+            println!("LDEL key index, LMOVE key from to, LRANGE key start stop (inclusive, -1 is the last element)");
+            println!("TSET key text (replace the text, only the changed part is sent as an edit), TGET key");
+            println!("TINS key pos text, TDEL key pos count (positions count characters)");
+            println!("ZADD key score member, ZINCRBY key amt member, ZREM key member (sorted set)");
+            println!("ZRANGE key start stop, ZREVRANGE key start stop (by rank, -1 is the last), ZRANGEBYSCORE key min max");
+            println!("ZRANK key member, ZSCORE key member");
+            println!("PFADD key element [element ...], PFCOUNT key [key ...] (distinct elements, estimated)");
+            println!("PFMERGE destkey sourcekey [sourcekey ...]");
+            println!("FCREATE key EW|DW (a disabled enable-wins or disable-wins flag), FENABLE key, FDISABLE key, FGET key");
+            println!(
+                "SCREATE key AW|2P|LWW (an empty add-wins, two-phase or last-writer-wins set)"
+            );
+            println!("SADD key member, SREM key member, SMEMBERS key");
+            println!("IMAX key n, IMIN key n (integer that only goes up or down), SMAX|SMIN key value [payload] (byte string)");
+            println!("XGET key (read a max or min register as JSON)");
+            println!("WATCH key [key ...] (a trailing * watches a prefix, e.g., WATCH user:*)");
+            println!("PUBLISH channel message");
+            println!("SUBSCRIBE channel [channel ...]");
+            println!("RETIRE node_id (fold a node id that is gone for good into the counters of this node)");
+            println!(
+                "MULTI, then write ops, then EXEC to apply them all or none (DISCARD drops them)"
+            );
+            println!(
+                "CONNECT addr (switch to another node of the same cluster, keeping this session)"
+            );
+            println!("SESSION (show the versions this session has seen)");
+            continue;
+        }
+
+        if cmd == "CONNECT" {
+            if parts.len() != 2 {
+                println!("usage: CONNECT addr");
+                continue;
+            }
+
+            let node_addr = String::from("http://") + parts[1];
+            match connect(node_addr.clone(), cluster.clone()).await {
+                Ok(new_client) => {
+                    client = new_client;
+                    println!("connected to: {}", node_addr);
+                }
+                Err(e) => println!("failed to connect to {}: {}", node_addr, e),
+            }
+            continue;
+        }
+
+        if cmd == "SESSION" {
+            let mut keys: Vec<&String> = session.keys.keys().collect();
+            keys.sort();
+            for key in keys {
+                println!(":: {} {:?}", key, session.keys[key].entries);
+            }
+            continue;
+        }
+
+        if cmd == "MULTI" {
+            if queued.is_some() {
+                println!("already inside MULTI");
+            } else {
+                queued = Some(Vec::new());
+                println!(":: OK");
+            }
+            continue;
+        }
+
+        if cmd == "DISCARD" {
+            match queued.take() {
+                Some(ops) => println!(":: discarded {} ops", ops.len()),
+                None => println!("DISCARD without MULTI"),
+            }
+            continue;
+        }
+
+        if cmd == "EXEC" {
+            let Some(ops) = queued.take() else {
+                println!("EXEC without MULTI");
+                continue;
+            };
+
+            let request = Request::new(PropagateBatchRequest { ops });
+            match client.propagate_batch(request).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    remember(&mut session, response.session);
+                    println!(
+                        ":: batch {} applied",
+                        String::from_utf8_lossy(&response.response)
+                    );
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if cmd == "PUBLISH" {
+            if parts.len() < 3 {
+                println!("usage: PUBLISH channel message");
+                continue;
+            }
+
+            let request = Request::new(PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: String::from(parts[1]),
+                value: parts[2..].join(" ").into_bytes(),
+                session: None,
+                path: Vec::new(),
+            });
+
+            match client.propagate_data(request).await {
+                Ok(response) => println!(
+                    ":: published as {}",
+                    String::from_utf8_lossy(&response.into_inner().response)
+                ),
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if cmd == "RETIRE" {
+            if parts.len() != 2 {
+                println!("usage: RETIRE node_id");
+                continue;
+            }
+
+            let request = Request::new(PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: String::from(parts[1]),
+                value: Vec::new(),
+                session: None,
+                path: Vec::new(),
+            });
+
+            match client.propagate_data(request).await {
+                Ok(_) => println!(
+                    ":: retiring {}, pending until every peer of this node confirms",
+                    parts[1]
+                ),
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if cmd == "SUBSCRIBE" {
+            if parts.len() < 2 {
+                println!("usage: SUBSCRIBE channel [channel ...]");
+                continue;
+            }
+
+            let request = SubscribeRequest {
+                channels: parts[1..].iter().map(|c| c.to_string()).collect(),
+            };
+
+            match client.subscribe(Request::new(request)).await {
+                Ok(response) => {
+                    let mut messages = response.into_inner();
+                    tokio::spawn(async move {
+                        while let Ok(Some(message)) = messages.message().await {
+                            println!(
+                                "\n[{}] {} (from {})",
+                                message.channel,
+                                String::from_utf8_lossy(&message.payload),
+                                message.origin
+                            );
+                        }
+                        println!("\n[subscribe] stream closed");
+                    });
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if cmd == "WATCH" {
+            if parts.len() < 2 {
+                println!("usage: WATCH key [key ...]");
+                continue;
+            }
+
+            let mut request = WatchRequest::default();
+            for target in &parts[1..] {
+                match target.strip_suffix('*') {
+                    Some(prefix) => request.prefixes.push(prefix.to_string()),
+                    None => request.keys.push(target.to_string()),
+                }
+            }
+
+            match client.watch(Request::new(request)).await {
+                Ok(response) => {
+                    let mut events = response.into_inner();
+                    //events are printed in the background so the prompt stays usable
+                    tokio::spawn(async move {
+                        while let Ok(Some(event)) = events.message().await {
+                            let origin = match event.origin() {
+                                ChangeOrigin::Local => "local",
+                                ChangeOrigin::Gossip => "gossip",
+                            };
+                            println!(
+                                "\n[watch] {} = {} ({})",
+                                event.key,
+                                format_watched_value(&event),
+                                origin
+                            );
+                        }
+                        println!("\n[watch] stream closed");
+                    });
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if PATH_OPS.contains(&cmd) {
+            let Some(op) = path_op(&parts) else {
+                println!("usage: MINC|MDEC key path amt, MSET key path value, MADD|MREM key path member, MDEL key path");
+                println!("       DSET|DINS|DPUSH key path json, DINC key path amt, DDEL key path");
+                continue;
+            };
+
+            if let Some(ops) = queued.as_mut() {
+                ops.push(op);
+                println!(":: QUEUED");
+                continue;
+            }
+
+            match client.propagate_data(Request::new(op)).await {
+                Ok(response) => {
+                    remember(&mut session, response.into_inner().session);
+                    println!(":: OK");
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if SEQUENCE_OPS.contains(&cmd) || ZSET_OPS.contains(&cmd) {
+            let op = match SEQUENCE_OPS.contains(&cmd) {
+                true => sequence_op(&parts),
+                false => zset_op(&parts),
+            };
+            let Some(op) = op else {
+                println!("usage: LPUSH|RPUSH key value, LINSERT key index value, LDEL key index, LMOVE key from to");
+                println!("       TSET key text, TINS key pos text, TDEL key pos count");
+                println!("       ZADD|ZINCRBY key amt member, ZREM key member");
+                continue;
+            };
+
+            if let Some(ops) = queued.as_mut() {
+                ops.push(op);
+                println!(":: QUEUED");
+                continue;
+            }
+
+            match client.propagate_data(Request::new(op)).await {
+                Ok(response) => {
+                    remember(&mut session, response.into_inner().session);
+                    println!(":: OK");
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if cmd == "PFADD" || cmd == "PFMERGE" {
+            if parts.len() < 3 {
+                println!("usage: PFADD key element [element ...], PFMERGE destkey sourcekey [sourcekey ...]");
+                continue;
+            }
+
+            //the elements or source keys go in the path
+            let op = PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: String::from(parts[1]),
+                value: Vec::new(),
+                session: None,
+                path: parts[2..].iter().map(|p| p.to_string()).collect(),
+            };
+
+            if let Some(ops) = queued.as_mut() {
+                ops.push(op);
+                println!(":: QUEUED");
+                continue;
+            }
+
+            match client.propagate_data(Request::new(op)).await {
+                Ok(response) => {
+                    remember(&mut session, response.into_inner().session);
+                    println!(":: OK");
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if cmd == "PFCOUNT" {
+            if parts.len() < 2 {
+                println!("usage: PFCOUNT key [key ...]");
+                continue;
+            }
+
+            //every key read has to respect the session
+            let seen = SessionToken {
+                keys: parts[1..]
+                    .iter()
+                    .filter_map(|key| seen_of(&session, key))
+                    .flat_map(|token| token.keys)
+                    .collect(),
+            };
+            let request = Request::new(PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: String::from(parts[1]),
+                value: Vec::new(),
+                session: Some(seen),
+                path: parts[2..].iter().map(|p| p.to_string()).collect(),
+            });
+
+            match client.propagate_data(request).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    remember(&mut session, response.session);
+                    let bytes: [u8; 8] = response.response.try_into().unwrap_or([0; 8]);
+                    println!(":: {}", u64::from_be_bytes(bytes));
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if FLAG_OPS.contains(&cmd) {
+            let value = match parts.len() {
+                3 if cmd == "FCREATE" && ["EW", "DW"].contains(&parts[2]) => {
+                    parts[2].as_bytes().to_vec()
+                }
+                2 if cmd != "FCREATE" => Vec::new(),
+                _ => {
+                    println!("usage: FCREATE key EW|DW, FENABLE key, FDISABLE key");
+                    continue;
+                }
+            };
+            let op = PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: String::from(parts[1]),
+                value,
+                session: None,
+                path: Vec::new(),
+            };
+
+            if let Some(ops) = queued.as_mut() {
+                ops.push(op);
+                println!(":: QUEUED");
+                continue;
+            }
+
+            match client.propagate_data(Request::new(op)).await {
+                Ok(response) => {
+                    remember(&mut session, response.into_inner().session);
+                    println!(":: OK");
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if SET_OPS.contains(&cmd) {
+            let value = match parts.len() {
+                3 if cmd == "SCREATE" && SET_KINDS.contains(&parts[2]) => {
+                    parts[2].as_bytes().to_vec()
+                }
+                n if n >= 3 && cmd != "SCREATE" => parts[2..].join(" ").into_bytes(),
+                _ => {
+                    println!("usage: SCREATE key AW|2P|LWW, SADD key member, SREM key member");
+                    continue;
+                }
+            };
+            let op = PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: String::from(parts[1]),
+                value,
+                session: None,
+                path: Vec::new(),
+            };
+
+            if let Some(ops) = queued.as_mut() {
+                ops.push(op);
+                println!(":: QUEUED");
+                continue;
+            }
+
+            match client.propagate_data(Request::new(op)).await {
+                Ok(response) => {
+                    remember(&mut session, response.into_inner().session);
+                    println!(":: OK");
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if BOUND_OPS.contains(&cmd) {
+            let (value, path) = match cmd {
+                "IMAX" | "IMIN" if parts.len() == 3 => match parts[2].parse::<i64>() {
+                    Ok(n) => (n.to_be_bytes().to_vec(), Vec::new()),
+                    Err(_) => {
+                        println!("{} takes an integer", cmd);
+                        continue;
+                    }
+                },
+                "SMAX" | "SMIN" if parts.len() >= 3 => {
+                    let payload = parts[3..].join(" ");
+                    (parts[2].as_bytes().to_vec(), vec![payload])
+                }
+                _ => {
+                    println!("usage: IMAX|IMIN key n, SMAX|SMIN key value [payload]");
+                    continue;
+                }
+            };
+            let op = PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: String::from(parts[1]),
+                value,
+                session: None,
+                path,
+            };
+
+            if let Some(ops) = queued.as_mut() {
+                ops.push(op);
+                println!(":: QUEUED");
+                continue;
+            }
+
+            match client.propagate_data(Request::new(op)).await {
+                Ok(response) => {
+                    remember(&mut session, response.into_inner().session);
+                    println!(":: OK");
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if cmd == "FGET" {
+            if parts.len() != 2 {
+                println!("usage: FGET key");
+                continue;
+            }
+
+            let key = String::from(parts[1]);
+            let request = Request::new(PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: key.clone(),
+                value: Vec::new(),
+                session: seen_of(&session, &key),
+                path: Vec::new(),
+            });
+
+            match client.propagate_data(request).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    remember(&mut session, response.session);
+                    println!(":: {}", flag_state(&response.response));
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if cmd == "SMEMBERS" {
+            if parts.len() != 2 {
+                println!("usage: SMEMBERS key");
+                continue;
+            }
+
+            let key = String::from(parts[1]);
+            let request = Request::new(PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: key.clone(),
+                value: Vec::new(),
+                session: seen_of(&session, &key),
+                path: Vec::new(),
+            });
+
+            match client.propagate_data(request).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    remember(&mut session, response.session);
+                    let members = String::from_utf8_lossy(&response.response).into_owned();
+                    if members.is_empty() {
+                        println!(":: (empty set)");
+                    }
+                    for member in members.lines() {
+                        println!(":: {}", member);
+                    }
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if cmd == "TGET" || cmd == "XGET" {
+            if parts.len() != 2 {
+                println!("usage: {} key", cmd);
+                continue;
+            }
+
+            let key = String::from(parts[1]);
+            let request = Request::new(PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: key.clone(),
+                value: Vec::new(),
+                session: seen_of(&session, &key),
+                path: Vec::new(),
+            });
+
+            match client.propagate_data(request).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    remember(&mut session, response.session);
+                    println!(":: {}", String::from_utf8_lossy(&response.response));
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if RANGE_READS.contains(&cmd) || cmd == "ZRANK" || cmd == "ZSCORE" {
+            let args = match RANGE_READS.contains(&cmd) {
+                true if parts.len() == 4 => vec![parts[2].to_string(), parts[3].to_string()],
+                false if parts.len() >= 3 => vec![parts[2..].join(" ")],
+                _ => {
+                    println!(
+                        "usage: LRANGE|ZRANGE|ZREVRANGE key start stop, ZRANGEBYSCORE key min max"
+                    );
+                    println!("       ZRANK|ZSCORE key member");
+                    continue;
+                }
+            };
+
+            let key = String::from(parts[1]);
+            let request = Request::new(PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: key.clone(),
+                value: Vec::new(),
+                session: seen_of(&session, &key),
+                path: args,
+            });
+
+            match client.propagate_data(request).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    remember(&mut session, response.session);
+                    println!(":: {}", String::from_utf8_lossy(&response.response));
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
+            continue;
+        }
+
+        if cmd == "MGET" || cmd == "DGET" {
+            if parts.len() != 2 && parts.len() != 3 {
+                println!("usage: {} key [path]", cmd);
+                continue;
+            }
+
+            let key = String::from(parts[1]);
+            let request = Request::new(PropagateDataRequest {
+                valuetype: String::from(cmd),
+                key: key.clone(),
+                value: Vec::new(),
+                session: seen_of(&session, &key),
+                path: parts.get(2).map(|p| split_path(p)).unwrap_or_default(),
+            });
+
+            match client.propagate_data(request).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    remember(&mut session, response.session);
+                    println!(":: {}", String::from_utf8_lossy(&response.response));
+                }
+                Err(e) => println!("RPC Failed: {}", e),
+            }
             continue;
         }
 
@@ -63,8 +702,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let key = String::from(parts[1]);
             let val_str = parts[2];
 
-            if value_type.starts_with('C') {
-                let parsed_value = match val_str.parse::<i64>() {
+            if COUNTER_OPS.contains(&value_type.as_str()) {
+                //wide counters take 128 bit amounts
+                let parsed_value = match WIDE_COUNTER_OPS.contains(&value_type.as_str()) {
+                    true => val_str.parse::<i128>().map(|v| v.to_be_bytes().to_vec()),
+                    false => val_str.parse::<i64>().map(|v| v.to_be_bytes().to_vec()),
+                };
+                let parsed_value = match parsed_value {
                     Ok(v) => v,
                     Err(_) => {
                         println!("Error: Value must be an integer");
@@ -72,14 +716,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                 };
 
-                let request = Request::new(PropagateDataRequest {
+                let op = PropagateDataRequest {
                     valuetype: value_type.clone(),
                     key: key.clone(),
-                    value: parsed_value.to_be_bytes().to_vec(),
-                });
+                    value: parsed_value,
+                    session: None,
+                    path: Vec::new(),
+                };
 
+                if let Some(ops) = queued.as_mut() {
+                    ops.push(op);
+                    println!(":: QUEUED");
+                    continue;
+                }
+
+                let request = Request::new(op);
                 match client.propagate_data(request).await {
-                    Ok(response) => println!("response: {:?}", response.into_inner()),
+                    Ok(response) => {
+                        let response = response.into_inner();
+                        println!("response: {:?}", response);
+                        remember(&mut session, response.session);
+                    }
                     Err(e) => println!("RPC Failed: {}", e),
                 }
             } else {
@@ -94,16 +751,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     valuetype: value_type.clone(),
                     key: key.clone(),
                     value: Vec::new(), //send empty bytes instead
+                    session: seen_of(&session, &key),
+                    path: Vec::new(),
                 });
 
                 match client.propagate_data(request).await {
                     Ok(response) => {
-                        let resp_inner = response.into_inner().response;
-
-                        let bytes: [u8; 8] = resp_inner.try_into().unwrap_or([0; 8]);
-                        let val = i64::from_be_bytes(bytes);
-
-                        println!(":: {}", val);
+                        let response = response.into_inner();
+                        remember(&mut session, response.session);
+                        println!(":: {}", counter_value(response.response));
                     }
                     Err(e) => println!("RPC Failed: {}", e),
                 }
@@ -114,3 +770,166 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 }
+
+//write ops on the counter types, all of them take an integer amount
+const COUNTER_OPS: [&str; 11] = [
+    "CSET", "CINC", "CDEC", "GSET", "GINC", "BSET", "BINC", "BDEC", "WSET", "WINC", "WDEC",
+];
+const WIDE_COUNTER_OPS: [&str; 3] = ["WSET", "WINC", "WDEC"];
+
+//write ops on a value inside a map or document, the value is given as a path
+const PATH_OPS: [&str; 11] = [
+    "MINC", "MDEC", "MSET", "MADD", "MREM", "MDEL", "DSET", "DINS", "DPUSH", "DDEL", "DINC",
+];
+
+//builds the request for a map or document write op, None if the args do not fit the op
+fn path_op(parts: &[&str]) -> Option<PropagateDataRequest> {
+    let (cmd, key, path) = (parts[0], parts.get(1)?, parts.get(2)?);
+    let value = match cmd {
+        "MINC" | "MDEC" if parts.len() == 4 => parts[3].parse::<u64>().ok()?.to_be_bytes().to_vec(),
+        "DINC" if parts.len() == 4 => parts[3].parse::<i64>().ok()?.to_be_bytes().to_vec(),
+        "MSET" | "MADD" | "MREM" | "DSET" | "DINS" | "DPUSH" if parts.len() >= 4 => {
+            parts[3..].join(" ").into_bytes()
+        }
+        "MDEL" | "DDEL" if parts.len() == 3 => Vec::new(),
+        _ => return None,
+    };
+
+    Some(PropagateDataRequest {
+        valuetype: cmd.to_string(),
+        key: key.to_string(),
+        value,
+        session: None,
+        path: split_path(path),
+    })
+}
+
+//write ops on a list or text, indexes are sent in the path
+const SEQUENCE_OPS: [&str; 8] = [
+    "LPUSH", "RPUSH", "LINSERT", "LDEL", "LMOVE", "TSET", "TINS", "TDEL",
+];
+
+//builds the request for a list or text write op, None if the args do not fit the op
+fn sequence_op(parts: &[&str]) -> Option<PropagateDataRequest> {
+    let (cmd, key) = (parts[0], parts.get(1)?);
+    let is_index = |arg: &str| arg.parse::<usize>().is_ok();
+    let (path, value) = match cmd {
+        "LPUSH" | "RPUSH" | "TSET" if parts.len() >= 3 => (Vec::new(), parts[2..].join(" ")),
+        "LINSERT" | "TINS" if parts.len() >= 4 && is_index(parts[2]) => {
+            (vec![parts[2]], parts[3..].join(" "))
+        }
+        "LDEL" if parts.len() == 3 && is_index(parts[2]) => (vec![parts[2]], String::new()),
+        "LMOVE" | "TDEL" if parts.len() == 4 && is_index(parts[2]) && is_index(parts[3]) => {
+            (vec![parts[2], parts[3]], String::new())
+        }
+        _ => return None,
+    };
+
+    Some(PropagateDataRequest {
+        valuetype: cmd.to_string(),
+        key: key.to_string(),
+        value: value.into_bytes(),
+        session: None,
+        path: path.into_iter().map(String::from).collect(),
+    })
+}
+
+//write ops on a max or min register
+const BOUND_OPS: [&str; 4] = ["IMAX", "IMIN", "SMAX", "SMIN"];
+
+//write ops on a set, and the kinds of set SCREATE makes
+const SET_OPS: [&str; 3] = ["SCREATE", "SADD", "SREM"];
+const SET_KINDS: [&str; 3] = ["AW", "2P", "LWW"];
+
+//write ops on a flag
+const FLAG_OPS: [&str; 3] = ["FCREATE", "FENABLE", "FDISABLE"];
+
+fn flag_state(value: &[u8]) -> &'static str {
+    match value {
+        [1] => "enabled",
+        _ => "disabled",
+    }
+}
+
+//write ops on a sorted set, the member is sent in the path
+const ZSET_OPS: [&str; 3] = ["ZADD", "ZINCRBY", "ZREM"];
+
+//reads that take a start and a stop
+const RANGE_READS: [&str; 4] = ["LRANGE", "ZRANGE", "ZREVRANGE", "ZRANGEBYSCORE"];
+
+//builds the request for a sorted set write op, None if the args do not fit the op
+fn zset_op(parts: &[&str]) -> Option<PropagateDataRequest> {
+    let (cmd, key) = (parts[0], parts.get(1)?);
+    let (value, member) = match cmd {
+        "ZADD" | "ZINCRBY" if parts.len() >= 4 => (
+            parts[2].parse::<i64>().ok()?.to_be_bytes().to_vec(),
+            parts[3..].join(" "),
+        ),
+        "ZREM" if parts.len() >= 3 => (Vec::new(), parts[2..].join(" ")),
+        _ => return None,
+    };
+
+    Some(PropagateDataRequest {
+        valuetype: cmd.to_string(),
+        key: key.to_string(),
+        value,
+        session: None,
+        path: vec![member],
+    })
+}
+
+//"address.city" -> ["address", "city"], "$" is the whole value
+fn split_path(path: &str) -> Vec<String> {
+    if path == "$" {
+        return Vec::new();
+    }
+    path.split('.').map(String::from).collect()
+}
+
+//joins the versions a node sent back into the session
+fn remember(session: &mut SessionToken, seen: Option<SessionToken>) {
+    let Some(seen) = seen else {
+        return;
+    };
+    for (key, version) in seen.keys {
+        let known = session.keys.entry(key).or_default();
+        for (node, n) in version.entries {
+            let entry = known.entries.entry(node).or_insert(0);
+            *entry = (*entry).max(n);
+        }
+    }
+}
+
+//the part of the session a read of `key` has to respect
+fn seen_of(session: &SessionToken, key: &str) -> Option<SessionToken> {
+    session.keys.get(key).map(|version| SessionToken {
+        keys: HashMap::from([(key.to_string(), version.clone())]),
+    })
+}
+
+//8 bytes, or 16 for a wide counter
+fn counter_value(bytes: Vec<u8>) -> String {
+    match bytes.len() {
+        16 => i128::from_be_bytes(bytes.try_into().unwrap_or([0; 16])).to_string(),
+        _ => i64::from_be_bytes(bytes.try_into().unwrap_or([0; 8])).to_string(),
+    }
+}
+
+fn format_watched_value(event: &WatchEvent) -> String {
+    match event.valuetype.as_str() {
+        "COUNTER" => counter_value(event.value.clone()),
+        "FLAG" => flag_state(&event.value).to_string(),
+        "HLL" => {
+            let bytes: [u8; 8] = event.value.clone().try_into().unwrap_or([0; 8]);
+            format!("~{} distinct", u64::from_be_bytes(bytes))
+        }
+        "MAP" | "DOC" | "LIST" | "TEXT" | "ZSET" | "BOUND" => {
+            String::from_utf8_lossy(&event.value).into_owned()
+        }
+        "SET" => {
+            let members = String::from_utf8_lossy(&event.value).replace('\n', ", ");
+            format!("{{{}}}", members)
+        }
+        _ => format!("{:?}", event.value),
+    }
+}
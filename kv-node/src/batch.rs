@@ -0,0 +1,87 @@
+use dashmap::DashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    communication::{AtomicBatchMessage, StoredValueMessage},
+    ids::{IdGenerator, SeenIds},
+};
+
+const SEEN_CAPACITY: usize = 10_000;
+//how long a batch is kept to gossip it, a peer that had no round for longer than that gets
+//the keys of older batches one by one
+const RETENTION: Duration = Duration::from_secs(60);
+
+//Atomic batches committed on this node or received from a peer. Each one is gossiped as a
+//whole, in the first request of a round, for as long as the keys it touched are, so a peer
+//applies all of its keys in one step instead of seeing them trickle in over several
+//GossipBatch requests. Received batches are recorded too, so they keep their shape when
+//they are passed on, and the seen ids stop them from bouncing between nodes forever.
+#[derive(Debug, Clone)]
+pub struct AtomicBatches {
+    recent: Arc<DashMap<String, RecentBatch>>,
+    seen: Arc<Mutex<SeenIds>>,
+    ids: IdGenerator,
+}
+
+#[derive(Debug)]
+struct RecentBatch {
+    recorded_at: SystemTime,
+    values: HashMap<String, StoredValueMessage>,
+}
+
+impl Default for AtomicBatches {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AtomicBatches {
+    pub fn new() -> Self {
+        AtomicBatches {
+            recent: Arc::new(DashMap::new()),
+            seen: Arc::new(Mutex::new(SeenIds::new(SEEN_CAPACITY))),
+            ids: IdGenerator::new(),
+        }
+    }
+
+    pub fn next_id(&self, node_id: &str) -> String {
+        self.ids.next(node_id)
+    }
+
+    //returns false if the batch was already known, in which case it has been applied before
+    pub fn record(&self, id: String, values: HashMap<String, StoredValueMessage>) -> bool {
+        let first_time = self
+            .seen
+            .lock()
+            .expect("atomic batch seen ids lock poisoned")
+            .insert(&id);
+        if first_time {
+            self.recent.insert(
+                id,
+                RecentBatch {
+                    recorded_at: SystemTime::now(),
+                    values,
+                },
+            );
+        }
+        first_time
+    }
+
+    //batches recorded at or after `since`, ones older than RETENTION are forgotten
+    pub fn recorded_since(&self, since: SystemTime) -> Vec<AtomicBatchMessage> {
+        self.recent
+            .retain(|_, batch| batch.recorded_at.elapsed().unwrap_or(Duration::ZERO) < RETENTION);
+        self.recent
+            .iter()
+            .filter(|batch| batch.recorded_at >= since)
+            .map(|batch| AtomicBatchMessage {
+                id: batch.key().clone(),
+                values: batch.value().values.clone(),
+            })
+            .collect()
+    }
+}
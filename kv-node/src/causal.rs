@@ -0,0 +1,429 @@
+use kv_types::causal::VersionVector;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    communication::{CausalUpdate, StoredValueMessage},
+    ids::incarnation,
+    outbox::{Outbox, Queued},
+};
+
+//a peer that stays down for long enough can no longer catch up on this node's writes
+const OUTBOX_CAPACITY: usize = 100_000;
+//how many received updates can wait for their dependencies, past that a request is refused
+//and its sender tries again later
+const BUFFER_CAPACITY: usize = 100_000;
+
+//Causal delivery mode, turned on with `causal_delivery = true` in the config.
+//
+//Every local write gets the next sequence number of this node and carries, as its
+//dependencies, how many writes of every origin it had applied when the write was made. The
+//write is queued for every peer until acked, and a receiving node keeps it buffered until it
+//has applied all of those dependencies and every earlier write of the same origin. So a
+//peer never sees a write to one key before the writes to other keys it was made on top of.
+//
+//Updates are only sent by the node that made them, which assumes every node lists every
+//other node as a peer, like config.toml does. An origin is a run of a node, "<node
+//id>@<incarnation>". A node that restarts comes back with an empty store and a new origin.
+//Its old origin is dead from then on: nobody sends its writes any more, so as soon as a node
+//hears of the new origin, writes that depend on the old one no longer wait for it. The same
+//goes for every origin of a node id that was retired.
+//
+//Plain per-key gossip is off in this mode, since a peer would merge it right away. What a
+//node missed, because it joined late, was down or heard of a dead origin, it catches up on
+//from each peer's whole store instead. The store comes with how many writes of every origin
+//it holds, so the node goes on from there with each origin's stream. A node catches up from
+//every peer once it starts, from the sender of a stream it first hears of past its start,
+//and from every peer again when an origin dies or a node id is retired.
+#[derive(Debug, Clone)]
+pub struct CausalDelivery {
+    origin: String,
+    state: Arc<Mutex<CausalState>>,
+    outbox: Outbox<CausalUpdate>,
+    buffer_capacity: usize,
+}
+
+#[derive(Debug, Default)]
+struct CausalState {
+    //per origin, how many of its writes have been applied here
+    applied: VersionVector,
+    //latest incarnation heard of for every node id
+    latest: HashMap<String, u64>,
+    //node ids retired for good
+    retired: HashSet<String>,
+    //received updates whose dependencies are not applied yet, in the order they arrived
+    buffered: Vec<CausalUpdate>,
+    //peers whose store this node caught up on since it last heard of a dead origin
+    caught_up: HashSet<String>,
+}
+
+//what came of the updates of one request
+#[derive(Debug, Default)]
+pub struct Received {
+    //can be applied now, in an order that respects their dependencies
+    pub ready: Vec<CausalUpdate>,
+    //did not fit the buffer, the request has to be refused so they are sent again
+    pub refused: usize,
+    //some origin's stream came in past its start, the sender has to be caught up from
+    pub behind: bool,
+}
+
+impl CausalDelivery {
+    pub fn new(node_id: &str) -> Self {
+        CausalDelivery {
+            origin: format!("{}@{}", node_id, incarnation()),
+            state: Arc::new(Mutex::new(CausalState::default())),
+            outbox: Outbox::new(OUTBOX_CAPACITY),
+            buffer_capacity: BUFFER_CAPACITY,
+        }
+    }
+
+    //records a local write and queues it for every peer. The caller must still hold the keys
+    //it wrote, so that sequence numbers follow the order the writes were applied in
+    pub fn record_local(
+        &self,
+        values: HashMap<String, StoredValueMessage>,
+        peers: impl Iterator<Item = String>,
+    ) {
+        let update = {
+            let mut state = self.state.lock().expect("causal state lock poisoned");
+            let deps = state.deps();
+            let seq = state.applied.advance(&self.origin, 1);
+
+            CausalUpdate {
+                origin: self.origin.clone(),
+                seq,
+                deps,
+                values,
+            }
+        };
+
+        for peer in peers {
+            if !self.outbox.push(&peer, update.clone()) {
+                println!("causal outbox for {} is full, it will not catch up", peer);
+            }
+        }
+    }
+
+    //up to `limit` local writes the peer has not acknowledged yet, oldest first
+    pub fn pending_for(&self, peer: &str, limit: usize) -> Vec<CausalUpdate> {
+        self.outbox.pending_for(peer, limit)
+    }
+
+    pub fn ack(&self, peer: &str, delivered: &[CausalUpdate]) {
+        self.outbox.ack(peer, delivered);
+    }
+
+    //takes updates received from a peer. The ready ones count as applied once returned, so
+    //the caller has to merge them before anything else reads the store
+    pub fn receive(&self, updates: Vec<CausalUpdate>) -> Received {
+        let mut state = self.state.lock().expect("causal state lock poisoned");
+        let before = state.buffered.len();
+        let mut behind = false;
+
+        for update in updates {
+            state.hear_of(&update.origin);
+            for origin in update.deps.keys() {
+                state.hear_of(origin);
+            }
+            if update.seq == 0 || state.is_known(&update) {
+                continue;
+            }
+            //the earlier writes were sent to an earlier run of this node, or before the
+            //sender listed it as a peer. It waits until we caught up on them
+            behind |= update.seq > 1
+                && !state.is_dead(&update.origin)
+                && !state.applied.entries.contains_key(&update.origin);
+            state.buffered.push(update);
+        }
+        state.prune();
+        let ready = state.deliver();
+
+        //only updates of this request can be over the capacity, the sender still has them
+        let capacity = self.buffer_capacity.max(before);
+        let refused = state.buffered.len().saturating_sub(capacity);
+        state.buffered.truncate(capacity);
+
+        if !state.buffered.is_empty() {
+            println!(
+                "{} causal updates waiting for their dependencies",
+                state.buffered.len()
+            );
+        }
+        Received {
+            ready,
+            refused,
+            behind,
+        }
+    }
+
+    //whether the node still has to catch up on the peer's store
+    pub fn is_behind(&self, peer: &str) -> bool {
+        let state = self.state.lock().expect("causal state lock poisoned");
+        !state.caught_up.contains(peer)
+    }
+
+    pub fn fell_behind(&self, peer: &str) {
+        let mut state = self.state.lock().expect("causal state lock poisoned");
+        state.caught_up.remove(peer);
+    }
+
+    //how many writes of every origin the store holds, has to be called under the exclusive
+    //gate so the store matches it
+    pub fn applied(&self) -> HashMap<String, u64> {
+        let state = self.state.lock().expect("causal state lock poisoned");
+        state.applied.entries.clone()
+    }
+
+    //takes what a peer's store held of every origin, once the caller merged that store. Returns
+    //the updates that were waiting on it and can be applied now
+    pub fn catch_up(&self, peer: &str, applied: HashMap<String, u64>) -> Vec<CausalUpdate> {
+        let mut state = self.state.lock().expect("causal state lock poisoned");
+        for origin in applied.keys() {
+            state.hear_of(origin);
+        }
+        //our own writes are counted as they are made
+        let applied = applied
+            .into_iter()
+            .filter(|(origin, _)| *origin != self.origin && !state.is_dead(origin))
+            .collect();
+        state.applied.join(&VersionVector { entries: applied });
+        state.caught_up.insert(peer.to_string());
+
+        let CausalState {
+            applied, buffered, ..
+        } = &mut *state;
+        buffered
+            .retain(|u| !applied.entries.contains_key(&u.origin) || u.seq > applied.get(&u.origin));
+        state.prune();
+        state.deliver()
+    }
+
+    //every origin of a retired node id is dead, returns the updates that were waiting on one
+    //and can be applied now
+    pub fn retire(&self, node_id: &str) -> Vec<CausalUpdate> {
+        let mut state = self.state.lock().expect("causal state lock poisoned");
+        if state.retired.insert(node_id.to_string()) {
+            //its writes not sent yet are gone with it, but peers may have got them
+            state.caught_up.clear();
+        }
+        state.prune();
+        state.deliver()
+    }
+}
+
+impl CausalState {
+    fn deps(&self) -> HashMap<String, u64> {
+        self.applied.entries.clone()
+    }
+
+    fn hear_of(&mut self, origin: &str) {
+        if let Some((node_id, incarnation)) = split_origin(origin) {
+            let latest = self.latest.entry(node_id.to_string()).or_insert(0);
+            //an earlier run of the node is dead, the writes it did not get to send us may
+            //have reached other peers
+            if *latest != 0 && incarnation > *latest {
+                self.caught_up.clear();
+            }
+            *latest = (*latest).max(incarnation);
+        }
+    }
+
+    //the process behind the origin is gone, and nobody will send any more of its writes
+    fn is_dead(&self, origin: &str) -> bool {
+        match split_origin(origin) {
+            Some((node_id, incarnation)) => {
+                self.retired.contains(node_id)
+                    || self
+                        .latest
+                        .get(node_id)
+                        .is_some_and(|latest| *latest > incarnation)
+            }
+            None => false,
+        }
+    }
+
+    //forgets the counts of dead origins, so they do not pile up across restarts
+    fn prune(&mut self) {
+        let dead: Vec<String> = self
+            .applied
+            .entries
+            .keys()
+            .filter(|origin| self.is_dead(origin))
+            .cloned()
+            .collect();
+        for origin in dead {
+            self.applied.entries.remove(&origin);
+        }
+    }
+
+    fn deliver(&mut self) -> Vec<CausalUpdate> {
+        let mut ready = Vec::new();
+        while let Some(pos) = self.buffered.iter().position(|u| self.deliverable(u)) {
+            let update = self.buffered.remove(pos);
+            if !self.is_dead(&update.origin) {
+                self.applied.observe(&(update.origin.clone(), update.seq));
+            }
+            ready.push(update);
+        }
+        ready
+    }
+
+    //already applied or already waiting in the buffer
+    fn is_known(&self, update: &CausalUpdate) -> bool {
+        (self.applied.entries.contains_key(&update.origin)
+            && update.seq <= self.applied.get(&update.origin))
+            || self
+                .buffered
+                .iter()
+                .any(|b| b.origin == update.origin && b.seq == update.seq)
+    }
+
+    //the writes of a dead origin that did arrive are applied as they are, the ones before
+    //them that did not never will
+    fn deliverable(&self, update: &CausalUpdate) -> bool {
+        let in_turn =
+            self.is_dead(&update.origin) || update.seq == self.applied.get(&update.origin) + 1;
+        in_turn
+            && update.deps.iter().all(|(origin, n)| {
+                origin == &update.origin || self.is_dead(origin) || self.applied.get(origin) >= *n
+            })
+    }
+}
+
+//"<node id>@<incarnation>"
+fn split_origin(origin: &str) -> Option<(&str, u64)> {
+    let (node_id, incarnation) = origin.rsplit_once('@')?;
+    Some((node_id, incarnation.parse().ok()?))
+}
+
+impl Queued for CausalUpdate {
+    fn queue_id(&self) -> String {
+        format!("{}:{}", self.origin, self.seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(origin: &str, seq: u64, deps: &[(&str, u64)], key: &str) -> CausalUpdate {
+        CausalUpdate {
+            origin: origin.to_string(),
+            seq,
+            deps: deps.iter().map(|(n, s)| (n.to_string(), *s)).collect(),
+            values: HashMap::from([(key.to_string(), StoredValueMessage::default())]),
+        }
+    }
+
+    fn keys(updates: &[CausalUpdate]) -> Vec<String> {
+        updates
+            .iter()
+            .flat_map(|u| u.values.keys().cloned())
+            .collect()
+    }
+
+    #[test]
+    fn update_waits_for_its_dependency_from_another_origin() {
+        let receiver = CausalDelivery::new("node_3");
+        receiver.receive(vec![update("a", 1, &[], "warmup_a")]);
+        receiver.receive(vec![update("b", 1, &[], "warmup_b")]);
+
+        //b wrote "order_ids" after seeing a's write to "order_count"
+        let received = receiver.receive(vec![update("b", 2, &[("a", 2), ("b", 1)], "order_ids")]);
+        assert!(received.ready.is_empty());
+
+        let received = receiver.receive(vec![update("a", 2, &[("a", 1)], "order_count")]);
+        assert_eq!(keys(&received.ready), vec!["order_count", "order_ids"]);
+    }
+
+    #[test]
+    fn same_origin_is_applied_in_order_and_only_once() {
+        let receiver = CausalDelivery::new("node_2");
+        receiver.receive(vec![update("a", 1, &[], "x")]);
+
+        let received = receiver.receive(vec![update("a", 3, &[("a", 2)], "z")]);
+        assert!(received.ready.is_empty());
+
+        let received = receiver.receive(vec![
+            update("a", 2, &[("a", 1)], "y"),
+            update("a", 2, &[("a", 1)], "y"),
+        ]);
+        assert_eq!(keys(&received.ready), vec!["y", "z"]);
+
+        let received = receiver.receive(vec![update("a", 3, &[("a", 2)], "z")]);
+        assert!(received.ready.is_empty());
+    }
+
+    #[test]
+    fn a_restarted_origin_does_not_hold_up_the_others() {
+        let receiver = CausalDelivery::new("node_3");
+        receiver.receive(vec![update("node_1@1", 1, &[], "x")]);
+        //node_2 saw a second write of node_1 that node_1 never got to send us before it
+        //restarted
+        let received = receiver.receive(vec![update("node_2@1", 1, &[("node_1@1", 2)], "y")]);
+        assert!(received.ready.is_empty());
+
+        //node_1 is back as a new origin, the write we missed is gone for good
+        let received = receiver.receive(vec![update("node_1@2", 1, &[], "z")]);
+        assert_eq!(keys(&received.ready), vec!["y", "z"]);
+        let received = receiver.receive(vec![update("node_2@1", 2, &[("node_1@1", 2)], "w")]);
+        assert_eq!(keys(&received.ready), vec!["w"]);
+
+        //and the dead origin is neither counted nor passed on as a dependency
+        receiver.record_local(HashMap::new(), [String::from("peer")].into_iter());
+        let deps = &receiver.pending_for("peer", 1)[0].deps;
+        assert!(!deps.contains_key("node_1@1"));
+        assert_eq!(deps.get("node_2@1"), Some(&2));
+    }
+
+    #[test]
+    fn a_stream_heard_of_past_its_start_waits_for_a_catch_up() {
+        let receiver = CausalDelivery::new("node_3");
+        let received = receiver.receive(vec![update("node_1@1", 4, &[("node_1@1", 3)], "x")]);
+        assert!(received.ready.is_empty());
+        assert!(received.behind);
+
+        //node_1's store held its first 5 writes, the buffered one is in it already
+        let ready = receiver.catch_up(
+            "node_1",
+            HashMap::from([("node_1@1".to_string(), 5), ("node_2@1".to_string(), 2)]),
+        );
+        assert!(ready.is_empty());
+        assert!(!receiver.is_behind("node_1"));
+        let received = receiver.receive(vec![update("node_1@1", 6, &[("node_2@1", 2)], "y")]);
+        assert_eq!(keys(&received.ready), vec!["y"]);
+        assert!(!received.behind);
+
+        //a restart of node_2 means catching up from everyone again
+        receiver.receive(vec![update("node_2@2", 1, &[], "z")]);
+        assert!(receiver.is_behind("node_1"));
+    }
+
+    #[test]
+    fn a_full_buffer_refuses_what_it_cannot_apply() {
+        let mut receiver = CausalDelivery::new("node_3");
+        receiver.buffer_capacity = 2;
+        receiver.receive(vec![update("node_1@1", 1, &[], "x")]);
+
+        let waiting: Vec<CausalUpdate> = (0..3)
+            .map(|n| update("node_2@1", n + 1, &[("node_1@1", 2)], "y"))
+            .collect();
+        let received = receiver.receive(waiting);
+        assert_eq!(received.refused, 1);
+
+        //what unblocks the buffer still gets in, and the refused update after it
+        let received = receiver.receive(vec![
+            update("node_1@1", 2, &[], "x"),
+            update("node_2@1", 3, &[("node_1@1", 2)], "y"),
+        ]);
+        assert_eq!(received.refused, 0);
+        assert_eq!(received.ready.len(), 4);
+
+        //and retiring a node id releases what waits on it
+        receiver.receive(vec![update("node_2@1", 4, &[("node_4@1", 1)], "v")]);
+        assert_eq!(keys(&receiver.retire("node_4")), vec!["v"]);
+    }
+}
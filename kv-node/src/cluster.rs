@@ -0,0 +1,121 @@
+use tonic::{
+    codegen::InterceptedService,
+    metadata::{Ascii, MetadataValue},
+    service::Interceptor,
+    transport::{Channel, Endpoint},
+    Request, Status,
+};
+
+use crate::communication::replication_service_client::ReplicationServiceClient;
+
+//Every request, from peers and from clients alike, names the cluster it is meant for in this
+//header, and a node refuses every request meant for another cluster. So a node whose config
+//lists a peer of another cluster, e.g. a staging node that lists a production one, never
+//merges anything of it or into it, even before the two said Hello.
+//
+//A request without the header is meant for the cluster without an id, which is what nodes
+//and clients from before cluster ids send.
+pub const CLUSTER_HEADER: &str = "x-cluster-id";
+
+//client to a peer that sends the cluster id of this node along with every request
+pub type PeerClient = ReplicationServiceClient<InterceptedService<Channel, ClusterStamp>>;
+
+pub async fn connect(
+    peer_addr: String,
+    stamp: ClusterStamp,
+) -> Result<PeerClient, tonic::transport::Error> {
+    let channel = Endpoint::from_shared(peer_addr)?.connect().await?;
+    Ok(ReplicationServiceClient::with_interceptor(channel, stamp))
+}
+
+//ids go out in a header, so they are limited to what a header can hold
+fn cluster_header(cluster_id: &str) -> Result<MetadataValue<Ascii>, String> {
+    MetadataValue::try_from(cluster_id)
+        .map_err(|_| format!("cluster id {:?} has to be printable ascii", cluster_id))
+}
+
+//adds the header to outgoing requests
+#[derive(Debug, Clone)]
+pub struct ClusterStamp {
+    cluster_id: MetadataValue<Ascii>,
+}
+
+impl ClusterStamp {
+    pub fn new(cluster_id: &str) -> Result<Self, String> {
+        Ok(ClusterStamp {
+            cluster_id: cluster_header(cluster_id)?,
+        })
+    }
+}
+
+impl Interceptor for ClusterStamp {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        request
+            .metadata_mut()
+            .insert(CLUSTER_HEADER, self.cluster_id.clone());
+        Ok(request)
+    }
+}
+
+//refuses incoming requests meant for another cluster
+#[derive(Debug, Clone)]
+pub struct ClusterCheck {
+    cluster_id: String,
+}
+
+impl ClusterCheck {
+    pub fn new(cluster_id: &str) -> Self {
+        ClusterCheck {
+            cluster_id: cluster_id.to_string(),
+        }
+    }
+}
+
+impl Interceptor for ClusterCheck {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let theirs = match request.metadata().get(CLUSTER_HEADER) {
+            Some(value) => value.to_str().map_err(|_| {
+                Status::invalid_argument(format!("{} is not printable ascii", CLUSTER_HEADER))
+            })?,
+            None => "",
+        };
+        if theirs != self.cluster_id {
+            return Err(Status::permission_denied(format!(
+                "request for cluster {:?} sent to a node of cluster {:?}",
+                theirs, self.cluster_id
+            )));
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamped(cluster_id: &str) -> Request<()> {
+        ClusterStamp::new(cluster_id)
+            .unwrap()
+            .call(Request::new(()))
+            .unwrap()
+    }
+
+    #[test]
+    fn requests_for_other_clusters_are_refused() {
+        let mut prod = ClusterCheck::new("prod");
+        assert!(prod.call(stamped("prod")).is_ok());
+        let refused = prod.call(stamped("staging")).unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::PermissionDenied);
+        //nor does a request without a cluster get in
+        assert!(prod.call(Request::new(())).is_err());
+    }
+
+    #[test]
+    fn requests_without_a_cluster_are_for_the_cluster_without_an_id() {
+        let mut unnamed = ClusterCheck::new("");
+        assert!(unnamed.call(Request::new(())).is_ok());
+        assert!(unnamed.call(stamped("")).is_ok());
+        assert!(unnamed.call(stamped("prod")).is_err());
+        assert!(ClusterStamp::new("prod\n").is_err());
+    }
+}
@@ -4,9 +4,30 @@ use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
+    //may be left out if there is a data_dir, which then keeps a generated one
+    #[serde(default)]
     pub node_id: String,
     pub listen_address: String,
     pub peers: Vec<String>,
+    //hold back gossiped writes until the writes they causally depend on have been applied,
+    //every node of the cluster should use the same setting
+    #[serde(default)]
+    pub causal_delivery: bool,
+    //where the node keeps what has to outlive it, for now its id
+    #[serde(default)]
+    pub data_dir: Option<PathBuf>,
+    //nodes only take gossip and client requests meant for the same cluster, left out for the
+    //default one
+    #[serde(default)]
+    pub cluster_id: String,
+    //how long a read waits for gossip to catch up with what the client's session has seen
+    //before it is refused with UNAVAILABLE, so the client can retry or use another node
+    #[serde(default = "default_session_wait_ms")]
+    pub session_wait_ms: u64,
+}
+
+pub fn default_session_wait_ms() -> u64 {
+    4000
 }
 
 impl Config {
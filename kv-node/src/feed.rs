@@ -0,0 +1,90 @@
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+
+//how many items a slow receiver can fall behind before it is cut off
+pub const FEED_CAPACITY: usize = 1024;
+
+//Forwards the items of a broadcast channel that `wanted` lets through to one streaming rpc,
+//Watch and Subscribe. A receiver that went away is noticed right away, not only once an
+//item it wants comes along. A receiver that falls more than FEED_CAPACITY items behind
+//would miss some, so its stream ends with DATA_LOSS instead and it can start over.
+pub fn forward<T, F>(
+    sender: &broadcast::Sender<T>,
+    wanted: F,
+) -> ReceiverStream<Result<T, tonic::Status>>
+where
+    T: Clone + Send + 'static,
+    F: Fn(&T) -> bool + Send + 'static,
+{
+    let mut items = sender.subscribe();
+    let (tx, rx) = mpsc::channel(FEED_CAPACITY);
+
+    tokio::spawn(async move {
+        loop {
+            let item = tokio::select! {
+                _ = tx.closed() => break,
+                item = items.recv() => item,
+            };
+            match item {
+                Ok(item) => {
+                    if !wanted(&item) {
+                        continue;
+                    }
+                    if tx.send(Ok(item)).await.is_err() {
+                        break; //receiver went away
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    let _ = tx
+                        .send(Err(tonic::Status::data_loss(format!(
+                            "fell {} items behind and missed them, start over",
+                            missed
+                        ))))
+                        .await;
+                    break;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn a_receiver_that_went_away_stops_its_task() {
+        let (sender, _) = broadcast::channel::<u32>(FEED_CAPACITY);
+        let items = forward(&sender, |item| *item == 1);
+        assert_eq!(sender.receiver_count(), 1);
+
+        //nothing it wants comes along, the task still has to go
+        drop(items);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while sender.receiver_count() > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the forwarding task outlived its receiver");
+    }
+
+    #[tokio::test]
+    async fn a_receiver_that_fell_behind_is_told_so() {
+        let (sender, _) = broadcast::channel::<usize>(FEED_CAPACITY);
+        let mut items = forward(&sender, |_| true);
+        //the forwarding task is not run until the test waits, by then the oldest are gone
+        for item in 0..FEED_CAPACITY * 2 {
+            sender.send(item).unwrap();
+        }
+
+        let lagged = items.next().await.unwrap().unwrap_err();
+        assert_eq!(lagged.code(), tonic::Code::DataLoss);
+        assert!(items.next().await.is_none());
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    time::SystemTime,
+};
+
+use crate::communication::{CausalUpdate, GossipBatchRequest, GossipBatchResponse, PubSubMessage};
+
+//how many requests of a round can be on their way to a peer that streams gossip before one
+//of them has to be acked. A slow peer acks late, which holds back the rest of its round and
+//nothing else, and a peer that stops reading its requests stops acking them as well
+pub const GOSSIP_WINDOW: usize = 4;
+
+//What a peer has had of this node so far, kept by the task that gossips to it. A round sends
+//every value changed since the last round started plus what the peer did not ack of it, so
+//nothing is missed however long a round to a slow peer takes.
+#[derive(Debug)]
+pub struct PeerSync {
+    //values and atomic batches changed at or after this went out in no round yet
+    pub since: SystemTime,
+    //keys the peer did not ack in the last round, sent again as they are by then
+    pub unacked: HashSet<String>,
+    //the process of the peer that had all of the above
+    instance_id: String,
+}
+
+impl Default for PeerSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PeerSync {
+    //a peer gets everything in its first round
+    pub fn new() -> Self {
+        PeerSync {
+            since: SystemTime::UNIX_EPOCH,
+            unacked: HashSet::new(),
+            instance_id: String::new(),
+        }
+    }
+
+    //a peer that restarted lost what it had, it gets everything again. Peers from before
+    //instance ids do not say, they only get everything in their first round
+    pub fn heard_from(&mut self, instance_id: &str) {
+        if instance_id.is_empty() || instance_id == self.instance_id {
+            return;
+        }
+        //the first answer only tells who had the first round
+        if !self.instance_id.is_empty() {
+            *self = PeerSync::new();
+        }
+        self.instance_id = instance_id.to_string();
+    }
+}
+
+//The requests of one gossip round to a peer, in the order they are sent and acked. A peer
+//acks every request with the keys of it that it merged and the ones it refused to, what it
+//did neither with by the end of the round is sent again in the next one.
+#[derive(Debug)]
+pub struct Round {
+    unsent: VecDeque<GossipBatchRequest>,
+    in_flight: VecDeque<Sent>,
+    window: usize,
+    unacked: HashSet<String>,
+    acked_keys: usize,
+    //instance of the peer that answered
+    pub answered_by: String,
+}
+
+//what a request carried, the pub/sub messages and causal updates are acked with it
+#[derive(Debug)]
+pub struct Sent {
+    keys: Vec<String>,
+    pub messages: Vec<PubSubMessage>,
+    pub causal_updates: Vec<CausalUpdate>,
+}
+
+impl Round {
+    pub fn new(requests: Vec<GossipBatchRequest>, window: usize) -> Self {
+        Round {
+            unsent: requests.into(),
+            in_flight: VecDeque::new(),
+            window: window.max(1),
+            unacked: HashSet::new(),
+            acked_keys: 0,
+            answered_by: String::new(),
+        }
+    }
+
+    //the next request to send, None while the window is full or once all of them went out
+    pub fn send_next(&mut self) -> Option<GossipBatchRequest> {
+        if self.in_flight.len() >= self.window {
+            return None;
+        }
+        let request = self.unsent.pop_front()?;
+        self.in_flight.push_back(Sent {
+            keys: request.batch.keys().cloned().collect(),
+            messages: request.messages.clone(),
+            causal_updates: request.causal_updates.clone(),
+        });
+        Some(request)
+    }
+
+    //acks the oldest request in flight, the keys of it the peer neither acked nor refused
+    //stay unacked
+    pub fn ack(&mut self, response: &GossipBatchResponse) -> Option<Sent> {
+        if !response.instance_id.is_empty() {
+            self.answered_by = response.instance_id.clone();
+        }
+        let sent = self.in_flight.pop_front()?;
+        let acked: HashSet<&String> = response.acked_keys.iter().collect();
+        let refused: HashSet<&String> = response.refused_keys.iter().collect();
+        for key in &sent.keys {
+            if acked.contains(key) {
+                self.acked_keys += 1;
+            } else if !refused.contains(key) {
+                self.unacked.insert(key.clone());
+            }
+        }
+        Some(sent)
+    }
+
+    pub fn done(&self) -> bool {
+        self.unsent.is_empty() && self.in_flight.is_empty()
+    }
+
+    //how many keys the peer acked so far
+    pub fn synced(&self) -> usize {
+        self.acked_keys
+    }
+
+    //every key the peer did not ack, whether it was sent or not
+    pub fn unacked(mut self) -> HashSet<String> {
+        let left = self.in_flight.iter().flat_map(|sent| sent.keys.iter());
+        let unsent = self.unsent.iter().flat_map(|request| request.batch.keys());
+        self.unacked.extend(left.chain(unsent).cloned());
+        self.unacked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::StoredValueMessage;
+
+    fn request(keys: &[&str]) -> GossipBatchRequest {
+        GossipBatchRequest {
+            batch: keys
+                .iter()
+                .map(|key| (key.to_string(), StoredValueMessage::default()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn ack(acked: &[&str], refused: &[&str]) -> GossipBatchResponse {
+        GossipBatchResponse {
+            acked_keys: acked.iter().map(|key| key.to_string()).collect(),
+            refused_keys: refused.iter().map(|key| key.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn no_more_than_the_window_goes_out_unacked() {
+        let mut round = Round::new(vec![request(&["a"]), request(&["b"]), request(&["c"])], 2);
+        assert!(round.send_next().is_some());
+        assert!(round.send_next().is_some());
+        assert!(round.send_next().is_none());
+
+        round.ack(&ack(&["a"], &[]));
+        assert_eq!(
+            round.send_next().unwrap().batch.keys().collect::<Vec<_>>(),
+            ["c"]
+        );
+        round.ack(&ack(&["b"], &[]));
+        round.ack(&ack(&["c"], &[]));
+        assert!(round.done());
+        assert_eq!(round.synced(), 3);
+        assert!(round.unacked().is_empty());
+    }
+
+    #[test]
+    fn keys_not_acked_are_left_for_the_next_round() {
+        let mut round = Round::new(vec![request(&["a", "b", "d"]), request(&["c"])], 1);
+        round.send_next();
+        //the peer only merged one of them, and holds another type under d
+        round.ack(&ack(&["a"], &["d"]));
+        round.send_next();
+        //and the round broke off before the second request was acked
+        let unacked = round.unacked();
+        assert_eq!(
+            unacked,
+            HashSet::from([String::from("b"), String::from("c")])
+        );
+    }
+
+    #[test]
+    fn a_restarted_peer_gets_everything_again() {
+        let mut sync = PeerSync::new();
+        sync.since = SystemTime::now();
+        sync.heard_from("a");
+        assert!(sync.since > SystemTime::UNIX_EPOCH);
+        sync.unacked.insert(String::from("k"));
+        //peers from before instance ids do not say who answered
+        sync.heard_from("");
+        sync.heard_from("a");
+        assert!(sync.since > SystemTime::UNIX_EPOCH);
+
+        sync.heard_from("b");
+        assert_eq!(sync.since, SystemTime::UNIX_EPOCH);
+        assert!(sync.unacked.is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+use dashmap::DashMap;
+use std::{collections::HashSet, sync::Arc};
+
+use crate::{
+    communication::{
+        stored_value_message::Value, AtomicBatchMessage, CausalUpdate, HelloMessage, NodeIdentity,
+        StoredValueMessage,
+    },
+    wire,
+};
+
+//Before a node gossips to a peer it says Hello, and both sides learn what the other speaks:
+//its protocol version, the oldest one it still gossips with, and the value types it knows.
+//Peers that cannot talk to each other, or belong to different clusters, do not gossip at
+//all. Peers that can are sent only the values they know, so a node that was upgraded to a
+//version with a new type keeps gossiping with the nodes not upgraded yet, which could only
+//drop values of that type on the floor.
+//
+//A peer that does not know Hello either is from before it, version 0, and is sent
+//everything as before.
+//
+//Values go out in the binary encoding, as "encoded", to the peers that know it. The others
+//get them in the messages of their type, as before the encoding.
+//
+//The version goes up whenever a node of the new version would misread what an older one
+//sends or the other way round. A new value type alone does not need it, value_types covers
+//that.
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const MIN_PROTOCOL_VERSION: u32 = 0;
+//first version that gossips over the Gossip stream, older peers get a GossipBatch at a time
+pub const STREAMING_PROTOCOL_VERSION: u32 = 2;
+
+//names of the StoredValueMessage value fields
+const VALUE_TYPES: [&str; 20] = [
+    "counter",
+    "g_counter",
+    "bounded_counter",
+    "map",
+    "doc",
+    "list",
+    "text",
+    "zset",
+    "hll",
+    "enable_wins_flag",
+    "disable_wins_flag",
+    "max_int",
+    "min_int",
+    "max_bytes",
+    "min_bytes",
+    "set",
+    "two_phase_set",
+    "lww_set",
+    "wide_counter",
+    "encoded",
+];
+
+pub fn value_type(value: &Value) -> &'static str {
+    match value {
+        Value::Counter(_) => "counter",
+        Value::GCounter(_) => "g_counter",
+        Value::BoundedCounter(_) => "bounded_counter",
+        Value::Map(_) => "map",
+        Value::Doc(_) => "doc",
+        Value::List(_) => "list",
+        Value::Text(_) => "text",
+        Value::Zset(_) => "zset",
+        Value::Hll(_) => "hll",
+        Value::EnableWinsFlag(_) => "enable_wins_flag",
+        Value::DisableWinsFlag(_) => "disable_wins_flag",
+        Value::MaxInt(_) => "max_int",
+        Value::MinInt(_) => "min_int",
+        Value::MaxBytes(_) => "max_bytes",
+        Value::MinBytes(_) => "min_bytes",
+        Value::Set(_) => "set",
+        Value::TwoPhaseSet(_) => "two_phase_set",
+        Value::LwwSet(_) => "lww_set",
+        Value::WideCounter(_) => "wide_counter",
+        Value::Encoded(_) => "encoded",
+    }
+}
+
+//what a peer said in its Hello
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub node_id: String,
+    //the version both sides speak, the lower of the two
+    pub protocol_version: u32,
+    //None for a peer from before Hello
+    value_types: Option<HashSet<String>>,
+}
+
+impl Peer {
+    pub fn legacy() -> Self {
+        Peer {
+            node_id: String::new(),
+            protocol_version: 0,
+            value_types: None,
+        }
+    }
+
+    //a peer from before Hello knows every type but the encoding, which came later
+    fn knows(&self, value_type: &str) -> bool {
+        match &self.value_types {
+            None => value_type != "encoded",
+            Some(known) => known.contains(value_type),
+        }
+    }
+
+    pub fn supports(&self, value: &StoredValueMessage) -> bool {
+        match &value.value {
+            Some(Value::Encoded(encoded)) => {
+                self.knows("encoded") && self.knows(&encoded.value_type)
+            }
+            Some(value) => self.knows(value_type(value)),
+            None => self.value_types.is_none(),
+        }
+    }
+
+    //the value in a form the peer reads, None if it would not know it
+    pub fn prepare(&self, value: StoredValueMessage) -> Option<StoredValueMessage> {
+        let value = match self.knows("encoded") {
+            true => value,
+            false => wire::unencoded(value).ok()?,
+        };
+        self.supports(&value).then_some(value)
+    }
+
+    //a batch only goes out whole
+    pub fn prepare_batch(&self, batch: AtomicBatchMessage) -> Option<AtomicBatchMessage> {
+        let values = batch
+            .values
+            .into_iter()
+            .map(|(key, value)| Some((key, self.prepare(value)?)))
+            .collect::<Option<_>>()?;
+        Some(AtomicBatchMessage { values, ..batch })
+    }
+
+    pub fn prepare_update(&self, update: CausalUpdate) -> Option<CausalUpdate> {
+        let values = update
+            .values
+            .into_iter()
+            .map(|(key, value)| Some((key, self.prepare(value)?)))
+            .collect::<Option<_>>()?;
+        Some(CausalUpdate { values, ..update })
+    }
+}
+
+//Hellos of this node's peers, by the address they are gossiped to at
+#[derive(Debug, Clone, Default)]
+pub struct Handshakes {
+    cluster_id: String,
+    peers: Arc<DashMap<String, Peer>>,
+}
+
+impl Handshakes {
+    pub fn new(cluster_id: &str) -> Self {
+        Handshakes {
+            cluster_id: cluster_id.to_string(),
+            peers: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub fn hello(&self, node: NodeIdentity) -> HelloMessage {
+        HelloMessage {
+            node: Some(node),
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            value_types: VALUE_TYPES.iter().map(|name| name.to_string()).collect(),
+            cluster_id: self.cluster_id.clone(),
+        }
+    }
+
+    //what both sides can speak, or why they cannot talk to each other
+    pub fn negotiate(&self, theirs: &HelloMessage) -> Result<Peer, String> {
+        let node_id = theirs
+            .node
+            .as_ref()
+            .map(|node| node.node_id.clone())
+            .unwrap_or_default();
+        if theirs.cluster_id != self.cluster_id {
+            return Err(format!(
+                "node {} belongs to cluster {:?}, this node to cluster {:?}",
+                node_id, theirs.cluster_id, self.cluster_id
+            ));
+        }
+        if theirs.min_protocol_version > PROTOCOL_VERSION {
+            return Err(format!(
+                "node {} needs protocol version {} or later, this node speaks {}",
+                node_id, theirs.min_protocol_version, PROTOCOL_VERSION
+            ));
+        }
+        Ok(Peer {
+            node_id,
+            protocol_version: theirs.protocol_version.min(PROTOCOL_VERSION),
+            value_types: Some(theirs.value_types.iter().cloned().collect()),
+        })
+    }
+
+    pub fn get(&self, peer_addr: &str) -> Option<Peer> {
+        self.peers.get(peer_addr).map(|peer| peer.clone())
+    }
+
+    pub fn insert(&self, peer_addr: &str, peer: Peer) {
+        self.peers.insert(peer_addr.to_string(), peer);
+    }
+
+    //the peer has to say Hello again, e.g. after its connection dropped, it may have been
+    //upgraded in the meantime
+    pub fn forget(&self, peer_addr: &str) {
+        self.peers.remove(peer_addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::CRDTValue;
+    use kv_types::g_counter::GCounter;
+
+    fn node(node_id: &str) -> NodeIdentity {
+        NodeIdentity {
+            node_id: node_id.to_string(),
+            listen_address: String::from("127.0.0.1:8000"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn peers_only_get_the_types_they_know() {
+        let handshakes = Handshakes::new("prod");
+        let mut older = handshakes.hello(node("node_2"));
+        older.value_types.retain(|name| name != "g_counter");
+        let peer = handshakes.negotiate(&older).unwrap();
+
+        let counter = CRDTValue::GCounter(GCounter::new(String::from("node_1"), 3));
+        let g_counter = StoredValueMessage {
+            value: Some(wire::encoded(&counter)),
+            version: Default::default(),
+        };
+        assert!(peer.prepare(g_counter.clone()).is_none());
+        let batch = AtomicBatchMessage {
+            id: String::from("node_1:1:0"),
+            values: [(String::from("views"), g_counter.clone())].into(),
+        };
+        assert!(peer.prepare_batch(batch).is_none());
+
+        //a peer that knows the type gets it encoded, one from before the encoding does not
+        let current = handshakes.negotiate(&handshakes.hello(node("node_3")));
+        assert_eq!(
+            current.unwrap().prepare(g_counter.clone()),
+            Some(g_counter.clone())
+        );
+        let legacy = Peer::legacy().prepare(g_counter).unwrap();
+        assert!(matches!(legacy.value, Some(Value::GCounter(_))));
+    }
+
+    #[test]
+    fn incompatible_versions_and_other_clusters_are_refused() {
+        let handshakes = Handshakes::new("prod");
+        let mut newer = handshakes.hello(node("node_2"));
+        newer.protocol_version = PROTOCOL_VERSION + 1;
+        assert_eq!(
+            handshakes.negotiate(&newer).unwrap().protocol_version,
+            PROTOCOL_VERSION
+        );
+        newer.min_protocol_version = PROTOCOL_VERSION + 1;
+        assert!(handshakes.negotiate(&newer).is_err());
+
+        let staging = Handshakes::new("staging").hello(node("node_3"));
+        assert!(handshakes.negotiate(&staging).is_err());
+    }
+
+    #[test]
+    fn every_value_type_is_announced() {
+        //an empty field of each tag decodes to the type a StoredValueMessage holds under it,
+        //if there is one
+        use prost::{encoding, Message};
+        let mut announced: Vec<&str> = (1..64)
+            .filter_map(|tag| {
+                let mut field = Vec::new();
+                encoding::encode_key(tag, encoding::WireType::LengthDelimited, &mut field);
+                field.push(0);
+                StoredValueMessage::decode(&field[..]).ok()
+            })
+            .filter_map(|message| message.value.as_ref().map(value_type))
+            .collect();
+        announced.sort();
+        let mut listed = VALUE_TYPES.to_vec();
+        listed.sort();
+        assert_eq!(announced, listed);
+    }
+}
@@ -0,0 +1,270 @@
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{communication::NodeIdentity, config::Config};
+
+//file in the data dir holding the id of the node
+const NODE_ID_FILE: &str = "node_id";
+//how long a claim on a node id holds after the last request that made it. A few gossip
+//rounds, so a node that restarted somewhere else can take its id back soon after
+const CLAIM_TTL: Duration = Duration::from_secs(10);
+
+//the id this node runs under. With a data dir the id is kept in there, so it stays the same
+//across restarts and changes of address: the first start takes the id of the config or,
+//without one, a random UUID, later starts refuse a config that names another id
+pub fn resolve_node_id(config: &Config) -> io::Result<String> {
+    let Some(data_dir) = &config.data_dir else {
+        if config.node_id.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the config needs a node_id or a data_dir to keep a generated one in",
+            ));
+        }
+        return Ok(config.node_id.clone());
+    };
+
+    let path = data_dir.join(NODE_ID_FILE);
+    match fs::read_to_string(&path) {
+        Ok(stored) => {
+            let stored = stored.trim().to_string();
+            if !config.node_id.is_empty() && config.node_id != stored {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} belongs to node {}, but the config names node {}",
+                        data_dir.display(),
+                        stored,
+                        config.node_id
+                    ),
+                ));
+            }
+            Ok(stored)
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            let node_id = match config.node_id.is_empty() {
+                true => generate_node_id(),
+                false => config.node_id.clone(),
+            };
+            fs::create_dir_all(data_dir)?;
+            fs::write(&path, &node_id)?;
+            println!("node id {} stored in {}", node_id, path.display());
+            Ok(node_id)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub fn generate_node_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+//Which process each node id was last claimed by, to catch two nodes running under the same
+//id, which would write over each other's counter entries. Every process makes up a random
+//instance id when it starts, and every gossip request names its sender by node id, instance
+//and address. A request that claims an id another instance still uses, or the id of this
+//node from another instance, is refused. The instance that gets refused stops, so it is
+//always the one that joined last.
+//
+//A node that moves to another address keeps its instance, so it is let through straight
+//away, and so is a new instance at the address of the claim, it can only be the node that
+//restarted. A node that restarts somewhere else is refused until the claim of its previous
+//process runs out, so it waits that long before it gives up, see settled.
+#[derive(Debug, Clone)]
+pub struct Identities {
+    own: NodeIdentity,
+    started: Instant,
+    //by node id
+    claims: Arc<DashMap<String, Claim>>,
+}
+
+#[derive(Debug, Clone)]
+struct Claim {
+    instance: String,
+    listen_address: String,
+    last_seen: SystemTime,
+}
+
+//nodes from before instance ids are told apart by their address
+fn instance(identity: &NodeIdentity) -> &str {
+    match identity.instance_id.is_empty() {
+        true => &identity.listen_address,
+        false => &identity.instance_id,
+    }
+}
+
+//only one process at a time listens on e.g. 10.0.0.2:8000, but every host has a 0.0.0.0:8000
+fn restarted_in_place(claim: &Claim, sender: &NodeIdentity) -> bool {
+    claim.listen_address == sender.listen_address
+        && sender
+            .listen_address
+            .parse::<SocketAddr>()
+            .is_ok_and(|addr| !addr.ip().is_unspecified())
+}
+
+impl Identities {
+    pub fn new(node_id: &str, listen_address: &str) -> Self {
+        Identities {
+            own: NodeIdentity {
+                node_id: node_id.to_string(),
+                listen_address: listen_address.to_string(),
+                instance_id: uuid::Uuid::new_v4().to_string(),
+            },
+            started: Instant::now(),
+            claims: Arc::new(DashMap::new()),
+        }
+    }
+
+    //what this node sends along with its requests
+    pub fn own(&self) -> NodeIdentity {
+        self.own.clone()
+    }
+
+    //whether a node of this id ever said hello or gossiped to this node, or is this node
+    pub fn knows(&self, node_id: &str) -> bool {
+        node_id == self.own.node_id || self.claims.contains_key(node_id)
+    }
+
+    //records the claim of a sender, or says which node already holds its id
+    pub fn check(&self, sender: &NodeIdentity) -> Result<(), String> {
+        let taken = |holder: &str| {
+            format!(
+                "node id {} of {} is already used by the node at {}, every node needs its own id",
+                sender.node_id, sender.listen_address, holder
+            )
+        };
+        if sender.node_id == self.own.node_id {
+            if instance(sender) == instance(&self.own) {
+                return Ok(()); //this node is in its own list of peers
+            }
+            return Err(taken(&self.own.listen_address));
+        }
+
+        let now = SystemTime::now();
+        match self.claims.entry(sender.node_id.clone()) {
+            Entry::Occupied(mut occupied) => {
+                let claim = occupied.get_mut();
+                let live = claim.last_seen.elapsed().unwrap_or(Duration::ZERO) < CLAIM_TTL;
+                let other =
+                    claim.instance != instance(sender) && !restarted_in_place(claim, sender);
+                if other && live {
+                    return Err(taken(&claim.listen_address));
+                }
+                claim.instance = instance(sender).to_string();
+                claim.listen_address = sender.listen_address.clone();
+                claim.last_seen = now;
+            }
+            Entry::Vacant(vacant) => {
+                vacant.insert(Claim {
+                    instance: instance(sender).to_string(),
+                    listen_address: sender.listen_address.clone(),
+                    last_seen: now,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    //whether the claims peers hold of the previous process of this node, if it just
+    //restarted, have run out. Until then a peer refusing our id may only remember that one
+    pub fn settled(&self) -> bool {
+        self.started.elapsed() > 2 * CLAIM_TTL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(node_id: &str, listen_address: &str, instance_id: &str) -> NodeIdentity {
+        NodeIdentity {
+            node_id: node_id.to_string(),
+            listen_address: listen_address.to_string(),
+            instance_id: instance_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn a_second_instance_cannot_claim_a_live_id() {
+        let identities = Identities::new("node_1", "10.0.0.1:8000");
+        let check = |node_id, listen_address, instance_id| {
+            identities.check(&identity(node_id, listen_address, instance_id))
+        };
+        assert!(check("node_2", "10.0.0.2:8000", "a").is_ok());
+        assert!(check("node_2", "10.0.0.2:8000", "a").is_ok());
+        assert!(check("node_2", "10.0.0.3:8000", "b").is_err());
+        //nor the id of this node
+        assert!(check("node_1", "10.0.0.1:8000", "b").is_err());
+        assert!(identities.check(&identities.own()).is_ok());
+
+        //a claim nobody renewed for a while can be taken over, the node restarted
+        identities.claims.get_mut("node_2").unwrap().last_seen = SystemTime::now() - CLAIM_TTL;
+        assert!(check("node_2", "10.0.0.3:8000", "b").is_ok());
+
+        assert!(identities.knows("node_1") && identities.knows("node_2"));
+        assert!(!identities.knows("node_3"));
+    }
+
+    #[test]
+    fn a_node_that_moved_keeps_its_id() {
+        let identities = Identities::new("node_1", "10.0.0.1:8000");
+        assert!(identities
+            .check(&identity("node_2", "10.0.0.2:8000", "a"))
+            .is_ok());
+        assert!(identities
+            .check(&identity("node_2", "10.0.0.3:8000", "a"))
+            .is_ok());
+        assert_eq!(
+            identities.claims.get("node_2").unwrap().listen_address,
+            "10.0.0.3:8000"
+        );
+
+        //nodes from before instance ids still move once their claim ran out
+        assert!(identities
+            .check(&identity("node_3", "10.0.0.4:8000", ""))
+            .is_ok());
+        assert!(identities
+            .check(&identity("node_3", "10.0.0.5:8000", ""))
+            .is_err());
+        //a new instance at the same address is the node restarting, unless every host has it
+        assert!(identities
+            .check(&identity("node_2", "10.0.0.3:8000", "b"))
+            .is_ok());
+        assert!(identities
+            .check(&identity("node_4", "0.0.0.0:8000", "a"))
+            .is_ok());
+        assert!(identities
+            .check(&identity("node_4", "0.0.0.0:8000", "b"))
+            .is_err());
+        //and a node that only just started waits for the claims of its previous process
+        assert!(!identities.settled());
+    }
+
+    #[test]
+    fn the_data_dir_keeps_the_id() {
+        let data_dir = std::env::temp_dir().join(format!("kv-node-{}", generate_node_id()));
+        let mut config = Config {
+            node_id: String::new(),
+            listen_address: String::from("127.0.0.1:8000"),
+            peers: Vec::new(),
+            causal_delivery: false,
+            data_dir: Some(data_dir.clone()),
+            cluster_id: String::new(),
+            session_wait_ms: crate::config::default_session_wait_ms(),
+        };
+
+        let generated = resolve_node_id(&config).unwrap();
+        assert_eq!(resolve_node_id(&config).unwrap(), generated);
+        config.node_id = generated.clone();
+        assert_eq!(resolve_node_id(&config).unwrap(), generated);
+        config.node_id = String::from("node_1");
+        assert!(resolve_node_id(&config).is_err());
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+//hands out ids of the form "<node id>:<incarnation>:<sequence no>" for things this node
+//originates and gossips, the incarnation is the start time of the process so ids stay
+//unique when a node restarts and its sequence numbers start from 0 again
+#[derive(Debug, Clone)]
+pub struct IdGenerator {
+    incarnation: u64,
+    next_seq: Arc<AtomicU64>,
+}
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdGenerator {
+    pub fn new() -> Self {
+        IdGenerator {
+            incarnation: incarnation(),
+            next_seq: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn next(&self, node_id: &str) -> String {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        format!("{}:{}:{}", node_id, self.incarnation, seq)
+    }
+}
+
+//start time of this process in microseconds, tells two runs of the same node apart
+pub fn incarnation() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+//remembers the last `capacity` ids so redeliveries of gossiped items can be dropped
+#[derive(Debug)]
+pub struct SeenIds {
+    capacity: usize,
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl SeenIds {
+    pub fn new(capacity: usize) -> Self {
+        SeenIds {
+            capacity,
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    //returns false if the id was already seen
+    pub fn insert(&mut self, id: &str) -> bool {
+        if !self.ids.insert(id.to_string()) {
+            return false;
+        }
+        self.order.push_back(id.to_string());
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
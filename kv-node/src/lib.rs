@@ -1,5 +1,19 @@
+pub mod batch;
+pub mod causal;
+pub mod cluster;
 pub mod config;
+pub mod feed;
+pub mod gossip;
+pub mod handshake;
+pub mod identity;
+pub mod ids;
 pub mod network;
+pub mod outbox;
+pub mod pubsub;
+pub mod retire;
+pub mod session;
+pub mod watch;
+pub mod wire;
 
 pub mod communication {
     tonic::include_proto!("communication");
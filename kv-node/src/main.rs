@@ -1,13 +1,25 @@
 use dashmap::DashMap;
-use kv_node::{config::Config, network::ReplicationServer};
-use std::{env, net::SocketAddr, path::Path, sync::Arc, time::SystemTime};
+use kv_node::{
+    batch::AtomicBatches,
+    causal::CausalDelivery,
+    cluster::ClusterStamp,
+    config::{default_session_wait_ms, Config},
+    handshake::Handshakes,
+    identity::{self, Identities},
+    network::ReplicationServer,
+    pubsub::PubSub,
+    retire::Retirements,
+    watch::ChangeFeed,
+};
+use std::{env, net::SocketAddr, path::Path, sync::Arc, time::{Duration, SystemTime}};
 use std::io::Write;
+use tokio::sync::RwLock;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
 
-    let config = if args.contains(&"--interactive".to_string()) || args.contains(&"--i".to_string())
+    let mut config = if args.contains(&"--interactive".to_string()) || args.contains(&"--i".to_string())
     {
         //for testing, allowing node_addr, peers, id to be entered by the node operator
         println!("@@Interative Mode@@");   
@@ -27,6 +39,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         eprintln!("   interactive mode: ./kv-node --interactive");
         return Ok(());
     };
+    config.node_id = match identity::resolve_node_id(&config) {
+        Ok(node_id) => node_id,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(());
+        }
+    };
+    let cluster = match ClusterStamp::new(&config.cluster_id) {
+        Ok(cluster) => cluster,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return Ok(());
+        }
+    };
 
     let map = Arc::new(DashMap::new());
     let peers = Arc::new(DashMap::new());
@@ -34,11 +60,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         peers.insert(peer_addr.clone(), SystemTime::UNIX_EPOCH);
     }
 
-    // let node_id = config.node_id.clone();
     let server = ReplicationServer {
         store: map.clone(),
         node_id: config.node_id.clone(),
-        peers: peers,
+        peers,
+        changes: ChangeFeed::new(),
+        pubsub: PubSub::new(),
+        batches: AtomicBatches::new(),
+        causal: config
+            .causal_delivery
+            .then(|| CausalDelivery::new(&config.node_id)),
+        write_gate: Arc::new(RwLock::new(())),
+        retirements: Retirements::new(),
+        identities: Identities::new(&config.node_id, &config.listen_address),
+        handshakes: Handshakes::new(&config.cluster_id),
+        cluster,
+        session_wait: Duration::from_millis(config.session_wait_ms),
     };
 
     println!("starting server on {}..", config.listen_address);
@@ -51,19 +88,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
-    //gossip loop runs here
-    server.create_and_gossip_batch().await?;
+    //gossip loop runs here, it only returns if the node cannot stay in the cluster
+    if let Err(e) = server.create_and_gossip_batch().await {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
     Ok(())
 }
 
 fn get_interactive_cofig() -> Result<Config, Box<dyn std::error::Error>> {
     let mut node_id = String::new();
-    print!("enter the node id for this node: ");
+    print!("enter the node id for this node (empty for a random one): ");
     std::io::stdout().flush().unwrap();
     std::io::stdin()
         .read_line(&mut node_id)
         .expect("failed to read line, restart node again");
-    let node_id = node_id.trim().to_string();
+    let node_id = match node_id.trim() {
+        "" => identity::generate_node_id(),
+        node_id => node_id.to_string(),
+    };
 
     let mut node_addr = String::new();
     print!("enter node's address (egs: 127.0.0.1:8000): ");
@@ -101,8 +144,12 @@ fn get_interactive_cofig() -> Result<Config, Box<dyn std::error::Error>> {
     }
 
     Ok(Config {
-        node_id: node_id,
+        node_id,
         listen_address: node_addr,
         peers: peers_config,
+        causal_delivery: false,
+        data_dir: None,
+        cluster_id: String::new(),
+        session_wait_ms: default_session_wait_ms(),
     })
 }
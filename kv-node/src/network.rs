@@ -1,36 +1,227 @@
-use dashmap::DashMap;
-use kv_types::{aw_set::AWSet, pn_counter::PNCounter, Merge};
+use dashmap::{mapref::entry::Entry, DashMap};
+use kv_types::{
+    bounded_counter::BoundedCounter,
+    causal::VersionVector,
+    flag::{DisableWinsFlag, EnableWinsFlag},
+    g_counter::GCounter,
+    hyperloglog::HyperLogLog,
+    json_doc::{to_json, DocError, JsonDoc},
+    lww_register::LwwRegister,
+    lww_set::LwwSet,
+    max_min_register::{MaxRegister, MinRegister, Ranked},
+    or_map::{MapError, ORMap},
+    or_set::ORSet,
+    pn_counter::{CounterOverflow, PNCounter},
+    rga::{IndexOutOfBounds, Rga},
+    sorted_set::SortedSet,
+    text::Text,
+    two_phase_set::TwoPhaseSet,
+    wide_counter::WideCounter,
+    CrdtValue, Merge, Scalar,
+};
 use std::{
     collections::HashMap,
+    pin::Pin,
     net::SocketAddr,
     sync::Arc,
     time::{Duration, SystemTime},
 };
-use tonic::{transport::Channel, transport::Server, Request, Response};
+use tokio::{
+    sync::{mpsc, RwLock},
+    task::JoinSet,
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tonic::{transport::Server, Request, Response};
 
 use crate::{
     communication::{
-        replication_service_client::ReplicationServiceClient,
         replication_service_server::{ReplicationService, ReplicationServiceServer},
+        stored_value_message, AtomicBatchMessage, CatchUpRequest, CatchUpResponse, ChangeOrigin,
         GossipBatchRequest, GossipBatchResponse, GossipChangesRequest, GossipChangesResponse,
-        PnCounterMessage, PropagateDataRequest, PropagateDataResponse,
+        HelloMessage,
+        PropagateBatchRequest, PropagateDataRequest, PropagateDataResponse, PubSubMessage,
+        SessionToken, StoredValueMessage, SubscribeRequest, TransferRightsRequest,
+        TransferRightsResponse, WatchEvent, WatchRequest,
     },
+    batch::AtomicBatches,
+    causal::CausalDelivery,
+    cluster::{self, ClusterCheck, ClusterStamp, PeerClient},
     config::Config,
+    gossip::{PeerSync, Round, GOSSIP_WINDOW},
+    handshake::{Handshakes, Peer, STREAMING_PROTOCOL_VERSION},
+    identity::Identities,
+    pubsub::PubSub,
+    retire::{self, Retirements},
+    session,
+    watch::ChangeFeed,
+    wire::{self, json_from_serde, json_to_serde},
 };
 
+#[allow(dead_code)] //fanout for push(), which is commented out for now
 const K: usize = 3;
 const BATCH_SIZE: usize = 1000;
+//how long a change stays eligible for gossip, also the pause between two gossip rounds
+const GOSSIP_INTERVAL: Duration = Duration::from_secs(2);
+//client ops that modify the store, the only ones allowed in an atomic batch
+const WRITE_OPS: [&str; 45] = [
+    "CSET", "CINC", "CDEC", "GSET", "GINC", "BSET", "BINC", "BDEC", "MINC", "MDEC", "MSET", "MADD",
+    "MREM", "MDEL", "DSET", "DINS", "DPUSH", "DDEL", "DINC", "LPUSH", "RPUSH", "LINSERT", "LDEL",
+    "LMOVE", "TSET", "TINS", "TDEL", "ZADD", "ZINCRBY", "ZREM", "PFADD", "PFMERGE", "FCREATE",
+    "FENABLE", "FDISABLE", "IMAX", "IMIN", "SMAX", "SMIN", "SCREATE", "SADD", "SREM", "WSET",
+    "WINC", "WDEC",
+];
+//reads of a sorted set
+const ZSET_READS: [&str; 5] = ["ZRANGE", "ZREVRANGE", "ZRANGEBYSCORE", "ZRANK", "ZSCORE"];
 
-#[derive(Debug)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum CRDTValue {
     Counter(PNCounter), //others later
-    ASet(AWSet<String>),
+    GCounter(GCounter),
+    BCounter(BoundedCounter),
+    //i128 value, for aggregates that can outgrow i64
+    WCounter(WideCounter),
+    //add-wins, two-phase and last-writer-wins sets, picked with SCREATE
+    ASet(ORSet<String>),
+    TwoPhaseSet(TwoPhaseSet<String>),
+    LwwSet(LwwSet<String>),
+    Map(ORMap),
+    Doc(JsonDoc),
+    //elements are string registers
+    List(Rga<CrdtValue>),
+    Text(Text),
+    ZSet(SortedSet),
+    Hll(HyperLogLog),
+    EwFlag(EnableWinsFlag),
+    DwFlag(DisableWinsFlag),
+    MaxInt(MaxRegister<i64>),
+    MinInt(MinRegister<i64>),
+    MaxBytes(MaxRegister<Ranked>),
+    MinBytes(MinRegister<Ranked>),
+}
+
+impl CRDTValue {
+    //name of the type as seen by watchers
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            CRDTValue::Counter(_)
+            | CRDTValue::GCounter(_)
+            | CRDTValue::BCounter(_)
+            | CRDTValue::WCounter(_) => "COUNTER",
+            CRDTValue::ASet(_) | CRDTValue::TwoPhaseSet(_) | CRDTValue::LwwSet(_) => "SET",
+            CRDTValue::Map(_) => "MAP",
+            CRDTValue::Doc(_) => "DOC",
+            CRDTValue::List(_) => "LIST",
+            CRDTValue::Text(_) => "TEXT",
+            CRDTValue::ZSet(_) => "ZSET",
+            CRDTValue::Hll(_) => "HLL",
+            CRDTValue::EwFlag(_) | CRDTValue::DwFlag(_) => "FLAG",
+            CRDTValue::MaxInt(_)
+            | CRDTValue::MinInt(_)
+            | CRDTValue::MaxBytes(_)
+            | CRDTValue::MinBytes(_) => "BOUND",
+        }
+    }
+
+    //value of any of the counter types. Unsigned counters past i64::MAX read as i64::MAX,
+    //and wide counters as the bound of i64 they are past, CGET sends those in full
+    pub fn counter_value(&self) -> Option<i64> {
+        match self {
+            CRDTValue::Counter(counter) => Some(counter.value()),
+            CRDTValue::GCounter(counter) => {
+                Some(i64::try_from(counter.value()).unwrap_or(i64::MAX))
+            }
+            CRDTValue::BCounter(counter) => {
+                Some(i64::try_from(counter.value()).unwrap_or(i64::MAX))
+            }
+            CRDTValue::WCounter(counter) => {
+                Some(counter.value().clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+            }
+            CRDTValue::ASet(_)
+            | CRDTValue::TwoPhaseSet(_)
+            | CRDTValue::LwwSet(_)
+            | CRDTValue::Map(_)
+            | CRDTValue::Doc(_)
+            | CRDTValue::List(_)
+            | CRDTValue::Text(_)
+            | CRDTValue::ZSet(_)
+            | CRDTValue::Hll(_)
+            | CRDTValue::EwFlag(_)
+            | CRDTValue::DwFlag(_)
+            | CRDTValue::MaxInt(_)
+            | CRDTValue::MinInt(_)
+            | CRDTValue::MaxBytes(_)
+            | CRDTValue::MinBytes(_) => None,
+        }
+    }
+
+    //merges a value of the same type into this one, false if the types differ
+    pub fn merge_from(&mut self, other: &CRDTValue) -> bool {
+        match (self, other) {
+            (CRDTValue::Counter(local), CRDTValue::Counter(remote)) => local.merge(remote),
+            (CRDTValue::GCounter(local), CRDTValue::GCounter(remote)) => local.merge(remote),
+            (CRDTValue::WCounter(local), CRDTValue::WCounter(remote)) => local.merge(remote),
+            (CRDTValue::BCounter(local), CRDTValue::BCounter(remote)) => local.merge(remote),
+            (CRDTValue::ASet(local), CRDTValue::ASet(remote)) => local.merge(remote),
+            (CRDTValue::TwoPhaseSet(local), CRDTValue::TwoPhaseSet(remote)) => local.merge(remote),
+            (CRDTValue::LwwSet(local), CRDTValue::LwwSet(remote)) => local.merge(remote),
+            (CRDTValue::Map(local), CRDTValue::Map(remote)) => local.merge(remote),
+            (CRDTValue::Doc(local), CRDTValue::Doc(remote)) => local.merge(remote),
+            (CRDTValue::List(local), CRDTValue::List(remote)) => local.merge(remote),
+            (CRDTValue::Text(local), CRDTValue::Text(remote)) => local.merge(remote),
+            (CRDTValue::ZSet(local), CRDTValue::ZSet(remote)) => local.merge(remote),
+            (CRDTValue::Hll(local), CRDTValue::Hll(remote)) => local.merge(remote),
+            (CRDTValue::EwFlag(local), CRDTValue::EwFlag(remote)) => local.merge(remote),
+            (CRDTValue::DwFlag(local), CRDTValue::DwFlag(remote)) => local.merge(remote),
+            (CRDTValue::MaxInt(local), CRDTValue::MaxInt(remote)) => local.merge(remote),
+            (CRDTValue::MinInt(local), CRDTValue::MinInt(remote)) => local.merge(remote),
+            (CRDTValue::MaxBytes(local), CRDTValue::MaxBytes(remote)) => local.merge(remote),
+            (CRDTValue::MinBytes(local), CRDTValue::MinBytes(remote)) => local.merge(remote),
+            _ => return false,
+        }
+        true
+    }
+
+    //the user visible value, encoded the same way the read ops send it back
+    pub fn read_bytes(&self) -> Vec<u8> {
+        match self {
+            CRDTValue::Counter(_) | CRDTValue::GCounter(_) | CRDTValue::BCounter(_) => self
+                .counter_value()
+                .unwrap_or_default()
+                .to_be_bytes()
+                .to_vec(),
+            //16 bytes instead of 8
+            CRDTValue::WCounter(counter) => counter.value().to_be_bytes().to_vec(),
+            CRDTValue::ASet(_) | CRDTValue::TwoPhaseSet(_) | CRDTValue::LwwSet(_) => {
+                set_members(self).join("\n").into_bytes()
+            }
+            CRDTValue::Map(_) | CRDTValue::Doc(_) => json_at(self, &[])
+                .unwrap_or_default()
+                .to_string()
+                .into_bytes(),
+            CRDTValue::List(list) => list_range(list, 0, -1).to_string().into_bytes(),
+            CRDTValue::Text(text) => text.to_string().into_bytes(),
+            CRDTValue::ZSet(zset) => scored(zset.ranked()).to_string().into_bytes(),
+            CRDTValue::Hll(sketch) => sketch.count().to_be_bytes().to_vec(),
+            CRDTValue::EwFlag(flag) => vec![u8::from(flag.value())],
+            CRDTValue::DwFlag(flag) => vec![u8::from(flag.value())],
+            CRDTValue::MaxInt(_)
+            | CRDTValue::MinInt(_)
+            | CRDTValue::MaxBytes(_)
+            | CRDTValue::MinBytes(_) => bound_json(self)
+                .unwrap_or_default()
+                .to_string()
+                .into_bytes(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct StoredValue {
     pub data: CRDTValue,
     pub last_updated: SystemTime,
+    //per node, how many writes it has made to this key, joined on merge. Session tokens are
+    //built from it
+    pub version: VersionVector,
 }
 
 #[derive(Debug, Clone)]
@@ -38,26 +229,24 @@ pub struct ReplicationServer {
     pub store: Arc<DashMap<String, StoredValue>>,
     pub node_id: String,
     pub peers: Arc<DashMap<String, SystemTime>>,
-}
-
-// convert domain -> proto for sending
-impl From<PNCounter> for PnCounterMessage {
-    fn from(domain: PNCounter) -> Self {
-        Self {
-            p: domain.p,
-            n: domain.n,
-        }
-    }
-}
-
-// convert proto -> domain for receiving
-impl From<PnCounterMessage> for PNCounter {
-    fn from(wire: PnCounterMessage) -> Self {
-        Self {
-            p: wire.p,
-            n: wire.n,
-        }
-    }
+    pub changes: ChangeFeed,
+    pub pubsub: PubSub,
+    pub batches: AtomicBatches,
+    //set when the node runs in causal delivery mode
+    pub causal: Option<CausalDelivery>,
+    //taken shared by single key writes, reads and merges, and exclusively while an atomic
+    //batch is applied
+    pub write_gate: Arc<RwLock<()>>,
+    //node ids being retired from the counters, see retire.rs
+    pub retirements: Retirements,
+    //which address each node id is used from, to catch nodes sharing one
+    pub identities: Identities,
+    //what each peer speaks, learnt from its Hello
+    pub handshakes: Handshakes,
+    //names the cluster of this node in every request to a peer, see cluster.rs
+    pub cluster: ClusterStamp,
+    //how long a read waits for this node to catch up with the client's session
+    pub session_wait: Duration,
 }
 
 #[tonic::async_trait]
@@ -73,86 +262,357 @@ impl ReplicationService for ReplicationServer {
         let raw_value_bytes = req_inner.value;
         let map = Arc::clone(&self.store);
 
-        if value_type == "CSET" {
-            //value shld be a u64
-            let bytes: [u8; 8] = raw_value_bytes.try_into().map_err(|_| {
-                tonic::Status::invalid_argument("invalid byte length for u64, expected 8 bytes")
-            })?;
-
-            let numeric_val: u64 = u64::from_be_bytes(bytes);
+        if WRITE_OPS.contains(&value_type.as_str()) {
+            if value_type == "BDEC" {
+                let amount = decode_u64(raw_value_bytes.clone())?;
+                self.acquire_rights(&key, amount).await;
+            }
 
-            println!("received valid CSET: {}", numeric_val);
+            let _gate = self.write_gate.read().await;
+            let raw_value_bytes = match value_type.as_str() {
+                "PFMERGE" => {
+                    self.union_of_sketches(&req_inner.path, &HashMap::new())?
+                        .registers
+                }
+                _ => raw_value_bytes,
+            };
+            let mut stored = match map.entry(key.clone()) {
+                Entry::Occupied(mut occupied) => {
+                    let current = &mut occupied.get_mut().data;
+                    if let Some(new_value) = self.apply_write(
+                        &value_type,
+                        Some(current),
+                        raw_value_bytes,
+                        &req_inner.path,
+                    )? {
+                        *current = new_value;
+                    }
+                    occupied.into_ref()
+                }
+                Entry::Vacant(vacant) => {
+                    let new_value = self
+                        .apply_write(&value_type, None, raw_value_bytes, &req_inner.path)?
+                        .ok_or_else(|| tonic::Status::not_found("key does not exist"))?;
+                    vacant.insert(StoredValue {
+                        data: new_value,
+                        last_updated: SystemTime::now(),
+                        version: VersionVector::new(),
+                    })
+                }
+            };
+            stored.last_updated = SystemTime::now();
+            stored.version.advance(&self.node_id, 1);
+            self.notify(&key, &stored.data, ChangeOrigin::Local);
+            self.record_causal([(&key, &*stored)].into_iter());
 
-            let new_pn: CRDTValue = CRDTValue::Counter(PNCounter {
-                p: HashMap::from([(self.node_id.clone(), numeric_val)]),
-                n: HashMap::from([(self.node_id.clone(), 0)]),
-            });
-            map.insert(
-                key,
-                StoredValue {
-                    data: new_pn,
-                    last_updated: SystemTime::now(),
-                },
-            );
-            println!("Counter set!");
             //need to send an ack that the op has been done
-            Ok(Response::new(PropagateDataResponse { success: true, response: Vec::new() })) //send empty bytes for response
-        } else if value_type == "CINC" {
-            let bytes: [u8; 8] = raw_value_bytes.try_into().map_err(|_| {
-                tonic::Status::invalid_argument("invalid byte length for u64, expected 8 bytes")
+            Ok(Response::new(PropagateDataResponse {
+                success: true,
+                response: Vec::new(), //send empty bytes for response
+                session: Some(session::token_for(&key, &stored.version)),
+            }))
+        } else if value_type == "CGET" {
+            //no need to resolve raw_value_bytes here, "CGET key"
+            println!("received valid CGET, get value of key: {}", key);
+            session::wait_until_seen(&map, &key, req_inner.session.as_ref(), self.session_wait)
+                .await?;
+            let _gate = self.write_gate.read().await;
+
+            let val = map
+                .get(&key)
+                .ok_or_else(|| tonic::Status::not_found("key does not exist"))?;
+            match val.data.counter_value() {
+                Some(value) => {
+                    println!("value is {}", value);
+                    return Ok(Response::new(PropagateDataResponse {
+                        success: true,
+                        response: val.data.read_bytes(),
+                        session: Some(session::token_for(&key, &val.version)),
+                    }));
+                }
+                None => println!("type mismatch: key exisits, but value is not a counter"),
+            }
+            Ok(Response::new(PropagateDataResponse { success: false, response: Vec::new(), session: None }))
+        } else if value_type == "MGET" || value_type == "DGET" {
+            //"MGET key [path]" or "DGET key [path]", the whole map or document or one value
+            //in it, as JSON. null if there is nothing at the path
+            session::wait_until_seen(&map, &key, req_inner.session.as_ref(), self.session_wait)
+                .await?;
+            let _gate = self.write_gate.read().await;
+
+            let val = map
+                .get(&key)
+                .ok_or_else(|| tonic::Status::not_found("key does not exist"))?;
+            let json = json_at(&val.data, &req_inner.path).ok_or_else(|| {
+                tonic::Status::failed_precondition(
+                    "type mismatch: key exisits, but value is not a map or document",
+                )
             })?;
+            Ok(Response::new(PropagateDataResponse {
+                success: true,
+                response: json.to_string().into_bytes(),
+                session: Some(session::token_for(&key, &val.version)),
+            }))
+        } else if value_type == "LRANGE" {
+            //"LRANGE key start stop", the elements from start to stop inclusive as a JSON
+            //array. Negative indexes count from the end, -1 is the last element
+            session::wait_until_seen(&map, &key, req_inner.session.as_ref(), self.session_wait)
+                .await?;
+            let _gate = self.write_gate.read().await;
 
-            let numeric_val: u64 = u64::from_be_bytes(bytes);
+            let bounds = match req_inner.path.as_slice() {
+                [start, stop] => start.parse::<i64>().ok().zip(stop.parse::<i64>().ok()),
+                _ => None,
+            };
+            let (start, stop) = bounds.ok_or_else(|| {
+                tonic::Status::invalid_argument("LRANGE takes an integer start and stop")
+            })?;
+            let val = map
+                .get(&key)
+                .ok_or_else(|| tonic::Status::not_found("key does not exist"))?;
+            let CRDTValue::List(list) = &val.data else {
+                return Err(tonic::Status::failed_precondition(
+                    "type mismatch: key exisits, but value is not a list",
+                ));
+            };
+            Ok(Response::new(PropagateDataResponse {
+                success: true,
+                response: list_range(list, start, stop).to_string().into_bytes(),
+                session: Some(session::token_for(&key, &val.version)),
+            }))
+        } else if value_type == "TGET" {
+            //"TGET key", the text as UTF-8
+            session::wait_until_seen(&map, &key, req_inner.session.as_ref(), self.session_wait)
+                .await?;
+            let _gate = self.write_gate.read().await;
+
+            let val = map
+                .get(&key)
+                .ok_or_else(|| tonic::Status::not_found("key does not exist"))?;
+            let CRDTValue::Text(text) = &val.data else {
+                return Err(tonic::Status::failed_precondition(
+                    "type mismatch: key exisits, but value is not text",
+                ));
+            };
+            Ok(Response::new(PropagateDataResponse {
+                success: true,
+                response: text.to_string().into_bytes(),
+                session: Some(session::token_for(&key, &val.version)),
+            }))
+        } else if ZSET_READS.contains(&value_type.as_str()) {
+            //"ZRANGE key start stop" and "ZREVRANGE key start stop" by rank, with -1 the last
+            //one, "ZRANGEBYSCORE key min max", "ZRANK key member" and "ZSCORE key member".
+            //Members come back as [member, score] pairs in JSON
+            session::wait_until_seen(&map, &key, req_inner.session.as_ref(), self.session_wait)
+                .await?;
+            let _gate = self.write_gate.read().await;
 
-            println!("received valid CINC, to increase by: {}", numeric_val);
+            let val = map
+                .get(&key)
+                .ok_or_else(|| tonic::Status::not_found("key does not exist"))?;
+            let CRDTValue::ZSet(zset) = &val.data else {
+                return Err(tonic::Status::failed_precondition(
+                    "type mismatch: key exisits, but value is not a sorted set",
+                ));
+            };
+            Ok(Response::new(PropagateDataResponse {
+                success: true,
+                response: zset_query(zset, &value_type, &req_inner.path)?
+                    .to_string()
+                    .into_bytes(),
+                session: Some(session::token_for(&key, &val.version)),
+            }))
+        } else if value_type == "PFCOUNT" {
+            //"PFCOUNT key [key ...]", the estimated number of distinct elements added to any
+            //of the keys, as the bytes of a u64. Keys that do not exist count as empty
+            let mut keys = vec![key];
+            keys.extend(req_inner.path);
+            for key in &keys {
+                session::wait_until_seen(&map, key, req_inner.session.as_ref(), self.session_wait)
+                    .await?;
+            }
+            let _gate = self.write_gate.read().await;
 
-            let mut val = map.get_mut(&key).unwrap();
-            match &mut val.data {
-                CRDTValue::Counter(local_counter) => {
-                    local_counter.increment(self.node_id.clone(), numeric_val);
-                    println!("Counter incremented by: {}", numeric_val);
-                    return Ok(Response::new(PropagateDataResponse { success: true, response: Vec::new() }))
+            let union = self.union_of_sketches(&keys, &HashMap::new())?;
+            let mut session = SessionToken::default();
+            for key in &keys {
+                if let Some(stored) = map.get(key) {
+                    let token = session::token_for(key, &stored.version);
+                    session.keys.extend(token.keys);
                 }
-                _ => println!("type mismatch: key exisits, but value is not of type PNCounter"),
             }
-            Ok(Response::new(PropagateDataResponse { success: false, response: Vec::new() }))
-        } else if value_type == "CDEC" {
-            let bytes: [u8; 8] = raw_value_bytes.try_into().map_err(|_| {
-                tonic::Status::invalid_argument("invalid byte length for u64, expected 8 bytes")
+            Ok(Response::new(PropagateDataResponse {
+                success: true,
+                response: union.count().to_be_bytes().to_vec(),
+                session: Some(session),
+            }))
+        } else if value_type == "FGET" {
+            //"FGET key", one byte, 1 if the flag is enabled
+            session::wait_until_seen(&map, &key, req_inner.session.as_ref(), self.session_wait)
+                .await?;
+            let _gate = self.write_gate.read().await;
+
+            let val = map
+                .get(&key)
+                .ok_or_else(|| tonic::Status::not_found("key does not exist"))?;
+            if val.data.type_name() != "FLAG" {
+                return Err(tonic::Status::failed_precondition(
+                    "type mismatch: key exisits, but value is not a flag",
+                ));
+            }
+            Ok(Response::new(PropagateDataResponse {
+                success: true,
+                response: val.data.read_bytes(),
+                session: Some(session::token_for(&key, &val.version)),
+            }))
+        } else if value_type == "SMEMBERS" {
+            //"SMEMBERS key", the members sorted, one per line
+            session::wait_until_seen(&map, &key, req_inner.session.as_ref(), self.session_wait)
+                .await?;
+            let _gate = self.write_gate.read().await;
+
+            let val = map
+                .get(&key)
+                .ok_or_else(|| tonic::Status::not_found("key does not exist"))?;
+            if val.data.type_name() != "SET" {
+                return Err(tonic::Status::failed_precondition(
+                    "type mismatch: key exisits, but value is not a set",
+                ));
+            }
+            Ok(Response::new(PropagateDataResponse {
+                success: true,
+                response: val.data.read_bytes(),
+                session: Some(session::token_for(&key, &val.version)),
+            }))
+        } else if value_type == "XGET" {
+            //"XGET key", a max or min register as JSON, a number for integers and
+            //{"value": .., "payload": ..} for byte strings
+            session::wait_until_seen(&map, &key, req_inner.session.as_ref(), self.session_wait)
+                .await?;
+            let _gate = self.write_gate.read().await;
+
+            let val = map
+                .get(&key)
+                .ok_or_else(|| tonic::Status::not_found("key does not exist"))?;
+            let json = bound_json(&val.data).ok_or_else(|| {
+                tonic::Status::failed_precondition(
+                    "type mismatch: key exisits, but value is not a max or min register",
+                )
             })?;
+            Ok(Response::new(PropagateDataResponse {
+                success: true,
+                response: json.to_string().into_bytes(),
+                session: Some(session::token_for(&key, &val.version)),
+            }))
+        } else if value_type == "RETIRE" {
+            //key is the node id to retire, this node absorbs its counts once every peer
+            //confirmed
+            self.retirements
+                .propose(&key, &self.node_id)
+                .map_err(tonic::Status::failed_precondition)?;
+            println!("retiring {} into {}", key, self.node_id);
+            Ok(Response::new(PropagateDataResponse {
+                success: true,
+                response: Vec::new(),
+                session: None,
+            }))
+        } else if value_type == "PUBLISH" {
+            //key is the channel, value is the opaque payload
+            let message = self.pubsub.publish(
+                &self.node_id,
+                key,
+                raw_value_bytes,
+                self.peer_addrs(),
+            );
+            println!("published {} on channel {}", message.id, message.channel);
+            Ok(Response::new(PropagateDataResponse { success: true, response: message.id.into_bytes(), session: None }))
+        } else {
+            println!("other types soon!");
+            Ok(Response::new(PropagateDataResponse { success: false, response: Vec::new(), session: None }))
+        }
+    }
 
-            let numeric_val: u64 = u64::from_be_bytes(bytes);
+    async fn propagate_batch(
+        &self,
+        request: tonic::Request<PropagateBatchRequest>,
+    ) -> Result<tonic::Response<PropagateDataResponse>, tonic::Status> {
+        let ops = request.into_inner().ops;
+        if ops.is_empty() {
+            return Err(tonic::Status::invalid_argument("batch has no ops"));
+        }
+        println!("received batch of {} ops", ops.len());
 
-            println!("received valid CDEC, to decrease by: {}", numeric_val);
+        //nothing else touches the store while the batch is applied, so other clients and
+        //the gossip loop see either none or all of it
+        let _gate = self.write_gate.write().await;
 
-            let mut val = map.get_mut(&key).unwrap();
-            match &mut val.data {
-                CRDTValue::Counter(local_counter) => {
-                    local_counter.decrement(self.node_id.clone(), numeric_val);
-                    println!("Counter decremented by: {}", numeric_val);
-                    return Ok(Response::new(PropagateDataResponse { success: true, response: Vec::new() }))
-                }
-                _ => println!("type mismatch: key exisits, but value is not of type PNCounter"),
+        //ops are applied to copies first, the store is only touched once all of them succeeded
+        let mut staged: HashMap<String, CRDTValue> = HashMap::new();
+        for op in ops {
+            if !WRITE_OPS.contains(&op.valuetype.as_str()) {
+                return Err(tonic::Status::invalid_argument(format!(
+                    "{} cannot be part of a batch",
+                    op.valuetype
+                )));
             }
-            Ok(Response::new(PropagateDataResponse { success: false, response: Vec::new() }))
-        } else if value_type == "CGET" {
-            //no need to resolve raw_value_bytes here, "CGET key"
-            println!("received valid CGET, get value of key: {}", key);
-
-            let val = map.get(&key).unwrap();
-            match &val.data {
-                CRDTValue::Counter(local_counter) => {
-                    let value = local_counter.value();
-                    println!("value is {}", value);
-                    return Ok(Response::new(PropagateDataResponse { success: true, response: value.to_be_bytes().to_vec() }))
+            if !staged.contains_key(&op.key) {
+                if let Some(stored) = self.store.get(&op.key) {
+                    staged.insert(op.key.clone(), stored.data.clone());
                 }
-                _ => println!("type mismatch: key exisits, but value is not of type PNCounter"),
             }
-            Ok(Response::new(PropagateDataResponse { success: false, response: Vec::new() }))
+            let value = match op.valuetype.as_str() {
+                "PFMERGE" => self.union_of_sketches(&op.path, &staged)?.registers,
+                _ => op.value,
+            };
+            if let Some(new_value) =
+                self.apply_write(&op.valuetype, staged.get_mut(&op.key), value, &op.path)?
+            {
+                staged.insert(op.key, new_value);
+            }
+        }
+
+        //each key counts the batch as one write, however many ops touched it
+        let now = SystemTime::now();
+        let committed: HashMap<String, StoredValue> = staged
+            .into_iter()
+            .map(|(key, data)| {
+                let mut version = self
+                    .store
+                    .get(&key)
+                    .map(|stored| stored.version.clone())
+                    .unwrap_or_default();
+                version.advance(&self.node_id, 1);
+                let stored = StoredValue {
+                    data,
+                    last_updated: now,
+                    version,
+                };
+                (key, stored)
+            })
+            .collect();
+
+        //in causal mode the batch is a single causal update, which is atomic on its own
+        let batch_id = self.batches.next_id(&self.node_id);
+        if self.causal.is_some() {
+            self.record_causal(committed.iter());
         } else {
-            println!("other types soon!");
-            Ok(Response::new(PropagateDataResponse { success: false, response: Vec::new() }))
+            self.batches
+                .record(batch_id.clone(), value_messages(committed.iter()));
+        }
+
+        let mut session = SessionToken::default();
+        for (key, stored) in committed {
+            self.notify(&key, &stored.data, ChangeOrigin::Local);
+            session.keys.extend(session::token_for(&key, &stored.version).keys);
+            self.store.insert(key, stored);
         }
+        println!("batch {} committed", batch_id);
+
+        Ok(Response::new(PropagateDataResponse {
+            success: true,
+            response: batch_id.into_bytes(),
+            session: Some(session),
+        }))
     }
 
     async fn gossip_changes(
@@ -162,26 +622,16 @@ impl ReplicationService for ReplicationServer {
         let changes_inner = changes.into_inner();
         let key = changes_inner.key;
         let counter = changes_inner.counter.unwrap();
-        let remote_counter = PNCounter::from(counter); //the actual PNCounter type
 
         //call merge now with the value corresponding to the same key in this node
-        self.store
-            .entry(key)
-            .and_modify(|current_value| {
-                match &mut current_value.data {
-                    CRDTValue::Counter(local_counter) => {
-                        local_counter.merge(&mut remote_counter.clone());
-                        println!("merged from remote node");
-                    } //other types later
-                    _ => println!("type mismatch: key exisits, but value is not of type PNCounter"),
-                }
-
-                current_value.last_updated = SystemTime::now()
-            })
-            .or_insert_with(|| StoredValue {
-                data: CRDTValue::Counter(remote_counter),
-                last_updated: SystemTime::now(),
-            });
+        let _gate = self.write_gate.read().await;
+        self.merge_remote(
+            key,
+            StoredValueMessage {
+                value: Some(stored_value_message::Value::Counter(counter)),
+                version: HashMap::new(),
+            },
+        );
 
         Ok(Response::new(GossipChangesResponse { success: true }))
     }
@@ -190,177 +640,2018 @@ impl ReplicationService for ReplicationServer {
         &self,
         batch: tonic::Request<GossipBatchRequest>,
     ) -> Result<tonic::Response<GossipBatchResponse>, tonic::Status> {
-        let batch = batch.into_inner().batch;
-        for (key, counter) in batch {
-            let remote_counter = PNCounter::from(counter);
-
-            self.store
-                .entry(key)
-                .and_modify(|current_value| {
-                    match &mut current_value.data {
-                        CRDTValue::Counter(local_counter) => {
-                            local_counter.merge(&mut remote_counter.clone());
-                            println!("merged from remote node");
-                        } //other types later
-                        _ => println!(
-                            "type mismatch: key exisits, but value is not of type PNCounter"
-                        ),
-                    }
+        Ok(Response::new(self.apply_gossip(batch.into_inner()).await?))
+    }
 
-                    current_value.last_updated = SystemTime::now()
-                })
-                .or_insert_with(|| StoredValue {
-                    data: CRDTValue::Counter(remote_counter),
-                    last_updated: SystemTime::now(),
-                });
+    type GossipStream =
+        Pin<Box<dyn Stream<Item = Result<GossipBatchResponse, tonic::Status>> + Send>>;
+
+    async fn gossip(
+        &self,
+        requests: tonic::Request<tonic::Streaming<GossipBatchRequest>>,
+    ) -> Result<tonic::Response<Self::GossipStream>, tonic::Status> {
+        let responses = self.serve_gossip(requests.into_inner());
+        Ok(Response::new(Box::pin(responses)))
+    }
+
+    async fn hello(
+        &self,
+        request: tonic::Request<HelloMessage>,
+    ) -> Result<tonic::Response<HelloMessage>, tonic::Status> {
+        let theirs = request.into_inner();
+        if let Some(node) = &theirs.node {
+            self.identities
+                .check(node)
+                .map_err(tonic::Status::already_exists)?;
+        }
+        self.handshakes
+            .negotiate(&theirs)
+            .map_err(tonic::Status::failed_precondition)?;
+        Ok(Response::new(self.handshakes.hello(self.identities.own())))
+    }
+
+    type WatchStream = Pin<Box<dyn Stream<Item = Result<WatchEvent, tonic::Status>> + Send>>;
+
+    async fn watch(
+        &self,
+        request: tonic::Request<WatchRequest>,
+    ) -> Result<tonic::Response<Self::WatchStream>, tonic::Status> {
+        let filter = request.into_inner();
+        if filter.keys.is_empty() && filter.prefixes.is_empty() {
+            return Err(tonic::Status::invalid_argument(
+                "watch needs at least one key or prefix",
+            ));
+        }
+
+        println!(
+            "new watcher on keys {:?}, prefixes {:?}",
+            filter.keys, filter.prefixes
+        );
+        Ok(Response::new(Box::pin(self.changes.subscribe(filter))))
+    }
+
+    type SubscribeStream =
+        Pin<Box<dyn Stream<Item = Result<PubSubMessage, tonic::Status>> + Send>>;
+
+    async fn subscribe(
+        &self,
+        request: tonic::Request<SubscribeRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeStream>, tonic::Status> {
+        let request = request.into_inner();
+        if request.channels.is_empty() {
+            return Err(tonic::Status::invalid_argument(
+                "subscribe needs at least one channel",
+            ));
+        }
+
+        println!("new subscriber on channels {:?}", request.channels);
+        Ok(Response::new(Box::pin(self.pubsub.subscribe(request))))
+    }
+
+    async fn transfer_rights(
+        &self,
+        request: tonic::Request<TransferRightsRequest>,
+    ) -> Result<tonic::Response<TransferRightsResponse>, tonic::Status> {
+        let request = request.into_inner();
+        if request.to == self.node_id {
+            return Err(tonic::Status::invalid_argument(
+                "rights cannot be transferred to the node holding them",
+            ));
+        }
+        //rights handed to an id no node runs under, e.g. a typo, would be gone for good
+        if !self.identities.knows(&request.to) {
+            return Err(tonic::Status::not_found(format!(
+                "no node {} ever said hello to this node, rights can only go to known nodes",
+                request.to
+            )));
         }
 
-        Ok(Response::new(GossipBatchResponse { success: (true) }))
+        let _gate = self.write_gate.read().await;
+        let mut stored = self
+            .store
+            .get_mut(&request.key)
+            .ok_or_else(|| tonic::Status::not_found("key does not exist"))?;
+        let CRDTValue::BCounter(counter) = &mut stored.data else {
+            return Err(tonic::Status::failed_precondition(
+                "type mismatch: key exisits, but value is not a bounded counter",
+            ));
+        };
+
+        //hands over as much as this node holds, the caller asks other nodes for the rest
+        let granted = counter.rights(&self.node_id).min(request.amount);
+        if granted > 0 {
+            counter
+                .transfer(self.node_id.clone(), request.to.clone(), granted)
+                .map_err(|e| tonic::Status::failed_precondition(e.to_string()))?;
+            stored.last_updated = SystemTime::now();
+            stored.version.advance(&self.node_id, 1);
+            self.record_causal([(&request.key, &*stored)].into_iter());
+        }
+        println!(
+            "granted {} of {} rights on {} to {}",
+            granted, request.amount, request.key, request.to
+        );
+
+        let value = match request.reads_encoded {
+            true => Some(value_message(&stored)),
+            false => wire::unencoded(value_message(&stored)).ok(),
+        };
+        Ok(Response::new(TransferRightsResponse { granted, value }))
+    }
+
+    async fn catch_up(
+        &self,
+        request: tonic::Request<CatchUpRequest>,
+    ) -> Result<tonic::Response<CatchUpResponse>, tonic::Status> {
+        let Some(causal) = &self.causal else {
+            return Err(tonic::Status::failed_precondition(
+                "this node does not run in causal delivery mode",
+            ));
+        };
+        if let Some(sender) = &request.into_inner().sender {
+            self.identities
+                .check(sender)
+                .map_err(tonic::Status::already_exists)?;
+        }
+
+        //exclusive, so no write is in the store without being counted or the other way round
+        let _gate = self.write_gate.write().await;
+        let values = self
+            .store
+            .iter()
+            .map(|key_val| (key_val.key().clone(), value_message(key_val.value())))
+            .collect();
+        Ok(Response::new(CatchUpResponse {
+            values,
+            applied: causal.applied(),
+        }))
     }
 }
 
 impl ReplicationServer {
-    pub async fn start_listener(&self, config: Config) -> Result<(), Box<dyn std::error::Error>> {
-        let addr: SocketAddr = config.listen_address.as_str().parse()?;
-        Server::builder()
-            .add_service(ReplicationServiceServer::new(self.clone()))
-            .serve(addr)
-            .await?;
+    //applies one client write op. `current` is the value stored under the key, if there is
+    //one. Ops that change the value in place return None, ops that build a whole new value
+    //return it and the caller stores it under the key
+    fn apply_write(
+        &self,
+        value_type: &str,
+        current: Option<&mut CRDTValue>,
+        raw_value_bytes: Vec<u8>,
+        path: &[String],
+    ) -> Result<Option<CRDTValue>, OpError> {
+        match value_type {
+            "CSET" => {
+                let numeric_val = decode_u64(raw_value_bytes)?;
+                println!("received valid CSET: {}", numeric_val);
 
-        Ok(())
-    }
+                Ok(Some(CRDTValue::Counter(PNCounter::new(
+                    self.node_id.clone(),
+                    numeric_val,
+                    0,
+                ))))
+            }
+            "CINC" => {
+                let numeric_val = decode_u64(raw_value_bytes)?;
+                println!("received valid CINC, to increase by: {}", numeric_val);
 
-    // pub async fn push(
-    //     &self,
-    //     key: String,
-    //     value: CRDTValue,
-    // ) -> Result<(), Box<dyn std::error::Error>> {
-    //     //send updates to k randomly chosen peers
-    //     //first make sure to preconnect to 3 randomly chosen peer nodes
-    //     //lots of things to think of, like what if a node goes down, how will this node reconnect to
-    //     //some other node etc, will tackle these later
+                let local_counter = expect_counter(current)?;
+                local_counter
+                    .checked_increment(self.node_id.clone(), numeric_val)
+                    .map_err(overflow_status)?;
+                println!("Counter incremented by: {}", numeric_val);
+                Ok(None)
+            }
+            "CDEC" => {
+                let numeric_val = decode_u64(raw_value_bytes)?;
+                println!("received valid CDEC, to decrease by: {}", numeric_val);
 
-    //     let mut rng = rand::rng();
+                let local_counter = expect_counter(current)?;
+                local_counter
+                    .checked_decrement(self.node_id.clone(), numeric_val)
+                    .map_err(overflow_status)?;
+                println!("Counter decremented by: {}", numeric_val);
+                Ok(None)
+            }
+            "GSET" => {
+                let numeric_val = decode_u64(raw_value_bytes)?;
+                println!("received valid GSET: {}", numeric_val);
 
-    //     let chosen_peers: Vec<String> = {
-    //         let peers: Vec<String> = self.peers.iter().map(|entry| entry.key().clone()).collect();
-    //         peers.choose_multiple(&mut rng, K).cloned().collect()
-    //     };
+                Ok(Some(CRDTValue::GCounter(GCounter::new(
+                    self.node_id.clone(),
+                    numeric_val,
+                ))))
+            }
+            "GINC" => {
+                let numeric_val = decode_u64(raw_value_bytes)?;
+                println!("received valid GINC, to increase by: {}", numeric_val);
 
-    //     for peer_addr in chosen_peers.iter() {
-    //         match ReplicationServiceClient::connect((*peer_addr).clone()).await {
-    //             Ok(mut peer_client) => match &value {
-    //                 CRDTValue::Counter(inner) => {
-    //                     let state = Request::new(GossipChangesRequest {
-    //                         key: key.clone(),
-    //                         counter: Some(PnCounterMessage::from(inner.clone())),
-    //                     });
+                match current {
+                    Some(CRDTValue::GCounter(local_counter)) => {
+                        local_counter
+                            .checked_increment(self.node_id.clone(), numeric_val)
+                            .map_err(overflow_status)?;
+                        Ok(None)
+                    }
+                    other => Err(missing_or_mismatched(other, "grow-only counter", "GSET")),
+                }
+            }
+            "WSET" => {
+                let numeric_val = decode_i128(raw_value_bytes)?;
+                println!("received valid WSET: {}", numeric_val);
 
-    //                     println!("connected to the peer with id: {}", peer_addr);
-    //                     match peer_client.gossip_changes(state).await {
-    //                         Ok(response) => {
-    //                             println!("Response from peer: {:?}", response.into_inner())
-    //                         }
-    //                         Err(e) => println!("failed to send update to {}: {}", peer_addr, e),
-    //                     }
-    //                 }
-    //                 _ => print!("other types soon!"),
-    //             },
-    //             Err(e) => {
-    //                 println!("failed to connect to {}: {}", peer_addr, e);
-    //                 continue;
-    //             }
-    //         }
-    //     }
+                Ok(Some(CRDTValue::WCounter(WideCounter::new(
+                    self.node_id.clone(),
+                    numeric_val,
+                ))))
+            }
+            "WINC" | "WDEC" => {
+                let numeric_val = decode_i128(raw_value_bytes)?;
+                println!("received valid {}, by: {}", value_type, numeric_val);
+                let amount = u128::try_from(numeric_val)
+                    .map_err(|_| OpError::invalid_argument("amount must not be negative"))?;
 
-    //     Ok(())
-    // }
+                match current {
+                    Some(CRDTValue::WCounter(local_counter)) => {
+                        let result = if value_type == "WINC" {
+                            local_counter.increment(self.node_id.clone(), amount)
+                        } else {
+                            local_counter.decrement(self.node_id.clone(), amount)
+                        };
+                        result.map_err(overflow_status)?;
+                        Ok(None)
+                    }
+                    other => Err(missing_or_mismatched(other, "wide counter", "WSET")),
+                }
+            }
+            "BSET" => {
+                let numeric_val = decode_u64(raw_value_bytes)?;
+                println!("received valid BSET: {}", numeric_val);
 
-    pub async fn create_and_gossip_batch(&self) -> Result<(), Box<dyn std::error::Error>> {
-        //a connection pool of rpc connections so as to not cause redundant ::connect's again if
-        //a node has already been connected to in an earlier iteration
+                //this node starts out holding all of the rights
+                Ok(Some(CRDTValue::BCounter(BoundedCounter::new(
+                    self.node_id.clone(),
+                    numeric_val,
+                ))))
+            }
+            "BINC" => {
+                let numeric_val = decode_u64(raw_value_bytes)?;
+                println!("received valid BINC, to increase by: {}", numeric_val);
 
-        let mut connection_pool: HashMap<String, ReplicationServiceClient<Channel>> =
-            HashMap::new();
+                match current {
+                    Some(CRDTValue::BCounter(local_counter)) => {
+                        local_counter
+                            .checked_increment(self.node_id.clone(), numeric_val)
+                            .map_err(overflow_status)?;
+                        Ok(None)
+                    }
+                    other => Err(missing_or_mismatched(other, "bounded counter", "BSET")),
+                }
+            }
+            "BDEC" => {
+                let numeric_val = decode_u64(raw_value_bytes)?;
+                println!("received valid BDEC, to decrease by: {}", numeric_val);
 
-        loop {
-            let mut chosen_peers: Vec<String> = Vec::new();
-            for peers in self.peers.iter() {
-                if peers.value().elapsed().unwrap_or(Duration::ZERO) > Duration::from_secs(2) {
-                    chosen_peers.push(peers.key().clone());
+                match current {
+                    Some(CRDTValue::BCounter(local_counter)) => {
+                        local_counter
+                            .decrement(self.node_id.clone(), numeric_val)
+                            .map_err(|e| OpError::failed_precondition(e.to_string()))?;
+                        Ok(None)
+                    }
+                    other => Err(missing_or_mismatched(other, "bounded counter", "BSET")),
                 }
             }
+            "MINC" | "MDEC" => {
+                let numeric_val = decode_u64(raw_value_bytes)?;
+                println!(
+                    "received valid {} on {:?}: {}",
+                    value_type, path, numeric_val
+                );
 
-            for peer_addr in &chosen_peers {
-                if !connection_pool.contains_key(peer_addr) {
-                    let endpoint = if peer_addr.starts_with("http") {
-                        peer_addr.clone()
+                with_map(current, |map| {
+                    if value_type == "MINC" {
+                        map.increment(&self.node_id, path, numeric_val)
                     } else {
-                        format!("http://{}", peer_addr)
-                    };
+                        map.decrement(&self.node_id, path, numeric_val)
+                    }
+                })
+            }
+            "MSET" => {
+                let value = decode_string(raw_value_bytes)?;
+                println!("received valid MSET on {:?}", path);
 
-                    match ReplicationServiceClient::connect(endpoint).await {
-                        Ok(client) => {
-                            connection_pool.insert(peer_addr.clone(), client);
-                        }
-                        Err(e) => {
-                            println!("failed to connect to {}: {}", peer_addr, e);
-                            continue;
+                with_map(current, |map| {
+                    map.set_register(&self.node_id, path, Scalar::String(value))
+                })
+            }
+            "MADD" | "MREM" => {
+                let member = decode_string(raw_value_bytes)?;
+                println!("received valid {} on {:?}: {}", value_type, path, member);
+
+                with_map(current, |map| {
+                    if value_type == "MADD" {
+                        map.add_to_set(&self.node_id, path, member)
+                    } else {
+                        map.remove_from_set(&self.node_id, path, &member)
+                    }
+                })
+            }
+            "MDEL" => {
+                println!("received valid MDEL on {:?}", path);
+
+                match current {
+                    Some(CRDTValue::Map(map)) => {
+                        if !map.remove(path) {
+                            return Err(OpError::not_found("field does not exist"));
                         }
+                        Ok(None)
                     }
+                    other => Err(missing_or_mismatched(other, "map", "MSET")),
                 }
+            }
+            "DSET" | "DINS" | "DPUSH" => {
+                let text = decode_string(raw_value_bytes)?;
+                let value: serde_json::Value = serde_json::from_str(&text).map_err(|e| {
+                    OpError::invalid_argument(format!("value is not valid JSON: {}", e))
+                })?;
+                println!("received valid {} on {:?}: {}", value_type, path, value);
 
-                //for each key in the current node, transfer each of the node states for merge
-                if let Some(peer_client) = connection_pool.get_mut(peer_addr) {
-                    let mut batch = HashMap::new();
-                    let mut updates_sent = 0;
-
-                    for key_val in self.store.iter() {
-                        let key = key_val.key();
-                        let value = key_val.value();
-
-                        if value.last_updated.elapsed().unwrap_or(Duration::ZERO)
-                            < Duration::from_secs(2)
-                        {
-                            if let CRDTValue::Counter(inner) = &value.data {
-                                batch.insert(key.clone(), PnCounterMessage::from(inner.clone()));
-                            }
-
-                            if batch.len() >= BATCH_SIZE {
-                                let req = Request::new(GossipBatchRequest {
-                                    batch: batch.clone(),
-                                });
-                                if let Err(e) = peer_client.gossip_batch(req).await {
-                                    eprintln!("Failed to send batch to {}: {}", peer_addr, e);
-                                } else {
-                                    updates_sent += batch.len();
-                                }
-                                batch.clear();
-                            }
-                        }
+                let value = json_from_serde(value);
+                with_doc(current, |doc| match value_type {
+                    "DSET" => doc.set(&self.node_id, path, value),
+                    "DINS" => doc.insert(&self.node_id, path, value),
+                    _ => doc.push(&self.node_id, path, value),
+                })
+            }
+            "DDEL" => {
+                println!("received valid DDEL on {:?}", path);
+
+                match current {
+                    Some(CRDTValue::Doc(doc)) => {
+                        doc.delete(&self.node_id, path).map_err(doc_status)?;
+                        Ok(None)
                     }
+                    other => Err(missing_or_mismatched(other, "document", "DSET")),
+                }
+            }
+            "DINC" => {
+                let amt = decode_u64(raw_value_bytes)? as i64; //sent as the bytes of an i64
+                println!("received valid DINC on {:?}: {}", path, amt);
 
-                    if !batch.is_empty() {
-                        let req = Request::new(GossipBatchRequest {
-                            batch: batch.clone(),
-                        });
-                        if let Err(e) = peer_client.gossip_batch(req).await {
-                            eprintln!("Failed to send final batch to {}: {}", peer_addr, e);
-                        } else {
-                            updates_sent += batch.len();
+                with_doc(current, |doc| doc.increment(&self.node_id, path, amt))
+            }
+            "LPUSH" | "RPUSH" | "LINSERT" => {
+                let value = decode_string(raw_value_bytes)?;
+                println!("received valid {} {:?}: {}", value_type, path, value);
+
+                let element = CrdtValue::Register(LwwRegister::new(
+                    self.node_id.clone(),
+                    Scalar::String(value),
+                ));
+                //LINSERT puts the element at the index given in the path
+                let at = match value_type {
+                    "LINSERT" => Some(list_index(path, 0)?),
+                    _ => None,
+                };
+                with_list(current, |list| {
+                    let index = match value_type {
+                        "LPUSH" => 0,
+                        "RPUSH" => list.len(),
+                        _ => at.expect("parsed above"),
+                    };
+                    list.insert(&self.node_id, index, element).map(|_| ())
+                })
+            }
+            "LDEL" => {
+                println!("received valid LDEL {:?}", path);
+
+                match current {
+                    Some(CRDTValue::List(list)) => {
+                        list.delete(list_index(path, 0)?).map_err(index_status)?;
+                        Ok(None)
+                    }
+                    other => Err(missing_or_mismatched(other, "list", "RPUSH")),
+                }
+            }
+            "LMOVE" => {
+                println!("received valid LMOVE {:?}", path);
+
+                match current {
+                    Some(CRDTValue::List(list)) => {
+                        let (from, to) = (list_index(path, 0)?, list_index(path, 1)?);
+                        list.move_to(&self.node_id, from, to)
+                            .map_err(index_status)?;
+                        Ok(None)
+                    }
+                    other => Err(missing_or_mismatched(other, "list", "RPUSH")),
+                }
+            }
+            "TSET" | "TINS" => {
+                let value = decode_string(raw_value_bytes)?;
+                println!("received valid {} {:?}: {}", value_type, path, value);
+
+                //TSET diffs the new text against the current one, so concurrent edits to the
+                //parts it leaves alone are kept
+                let at = match value_type {
+                    "TINS" => Some(list_index(path, 0)?),
+                    _ => None,
+                };
+                with_text(current, |text| match at {
+                    Some(index) => text.insert(&self.node_id, index, &value),
+                    None => {
+                        text.update(&self.node_id, &value);
+                        Ok(())
+                    }
+                })
+            }
+            "TDEL" => {
+                let (index, count) = (list_index(path, 0)?, list_index(path, 1)?);
+                println!("received valid TDEL at {}: {}", index, count);
+
+                match current {
+                    Some(CRDTValue::Text(text)) => {
+                        text.delete(index, count).map_err(index_status)?;
+                        Ok(None)
+                    }
+                    other => Err(missing_or_mismatched(other, "text value", "TSET")),
+                }
+            }
+            "ZADD" | "ZINCRBY" => {
+                let amt = decode_u64(raw_value_bytes)? as i64; //sent as the bytes of an i64
+                let member = zset_member(path)?;
+                println!("received valid {} for {}: {}", value_type, member, amt);
+
+                let op = |zset: &mut SortedSet| match value_type {
+                    "ZADD" => zset.add(&self.node_id, member, amt),
+                    _ => zset.increment(&self.node_id, member, amt).map(|_| ()),
+                };
+                match current {
+                    Some(CRDTValue::ZSet(zset)) => {
+                        op(zset).map_err(overflow_status)?;
+                        Ok(None)
+                    }
+                    Some(_) => Err(OpError::failed_precondition(
+                        "type mismatch: key exisits, but value is not a sorted set",
+                    )),
+                    None => {
+                        let mut zset = SortedSet::new();
+                        op(&mut zset).map_err(overflow_status)?;
+                        Ok(Some(CRDTValue::ZSet(zset)))
+                    }
+                }
+            }
+            "ZREM" => {
+                let member = zset_member(path)?;
+                println!("received valid ZREM for {}", member);
+
+                match current {
+                    Some(CRDTValue::ZSet(zset)) => {
+                        if !zset.remove(member) {
+                            return Err(OpError::not_found("member does not exist"));
                         }
+                        Ok(None)
                     }
+                    other => Err(missing_or_mismatched(other, "sorted set", "ZADD")),
+                }
+            }
+            "PFADD" => {
+                println!("received valid PFADD of {} elements", path.len());
 
-                    self.peers.insert(peer_addr.clone(), SystemTime::now());
+                let op = |sketch: &mut HyperLogLog| {
+                    for element in path {
+                        sketch.add(element.as_bytes());
+                    }
+                };
+                match current {
+                    Some(CRDTValue::Hll(sketch)) => {
+                        op(sketch);
+                        Ok(None)
+                    }
+                    Some(_) => Err(OpError::failed_precondition(
+                        "type mismatch: key exisits, but value is not a HyperLogLog",
+                    )),
+                    None => {
+                        let mut sketch = HyperLogLog::new();
+                        op(&mut sketch);
+                        Ok(Some(CRDTValue::Hll(sketch)))
+                    }
+                }
+            }
+            "PFMERGE" => {
+                //the value is the union of the source keys, see union_of_sketches
+                let union = HyperLogLog::from_registers(raw_value_bytes).ok_or_else(|| {
+                    OpError::invalid_argument("value is not a HyperLogLog sketch")
+                })?;
+                println!("received valid PFMERGE of {:?}", path);
 
-                    if updates_sent > 0 {
-                        println!("Synced {} items with {}", updates_sent, peer_addr);
+                match current {
+                    Some(CRDTValue::Hll(sketch)) => {
+                        sketch.merge(&union);
+                        Ok(None)
                     }
+                    Some(_) => Err(OpError::failed_precondition(
+                        "type mismatch: key exisits, but value is not a HyperLogLog",
+                    )),
+                    None => Ok(Some(CRDTValue::Hll(union))),
                 }
             }
-            //wait for 2s before the next gossip round
-            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-        }
-        Ok(())
+            "FCREATE" => {
+                let kind = decode_string(raw_value_bytes)?;
+                println!("received valid FCREATE: {}", kind);
+
+                //a new flag is disabled, and replaces whatever the key held
+                match kind.as_str() {
+                    "EW" => Ok(Some(CRDTValue::EwFlag(EnableWinsFlag::new()))),
+                    "DW" => Ok(Some(CRDTValue::DwFlag(DisableWinsFlag::new()))),
+                    _ => Err(OpError::invalid_argument(
+                        "a flag is either EW (enable-wins) or DW (disable-wins)",
+                    )),
+                }
+            }
+            "FENABLE" | "FDISABLE" => {
+                println!("received valid {}", value_type);
+
+                let enable = value_type == "FENABLE";
+                match current {
+                    Some(CRDTValue::EwFlag(flag)) if enable => flag.enable(&self.node_id),
+                    Some(CRDTValue::EwFlag(flag)) => flag.disable(&self.node_id),
+                    Some(CRDTValue::DwFlag(flag)) if enable => flag.enable(&self.node_id),
+                    Some(CRDTValue::DwFlag(flag)) => flag.disable(&self.node_id),
+                    other => return Err(missing_or_mismatched(other, "flag", "FCREATE")),
+                }
+                Ok(None)
+            }
+            "SCREATE" => {
+                let kind = decode_string(raw_value_bytes)?;
+                println!("received valid SCREATE: {}", kind);
+
+                //a new set is empty, and replaces whatever the key held
+                match kind.as_str() {
+                    "AW" => Ok(Some(CRDTValue::ASet(ORSet::new()))),
+                    "2P" => Ok(Some(CRDTValue::TwoPhaseSet(TwoPhaseSet::new()))),
+                    "LWW" => Ok(Some(CRDTValue::LwwSet(LwwSet::new()))),
+                    _ => Err(OpError::invalid_argument(
+                        "a set is AW (add-wins), 2P (two-phase) or LWW (last-writer-wins)",
+                    )),
+                }
+            }
+            "SADD" => {
+                let member = decode_string(raw_value_bytes)?;
+                println!("received valid SADD: {}", member);
+
+                match current {
+                    Some(CRDTValue::ASet(set)) => set.add(&self.node_id, member),
+                    Some(CRDTValue::TwoPhaseSet(set)) => {
+                        if !set.add(member) {
+                            return Err(OpError::failed_precondition(
+                                "member was removed from a two-phase set, it can not be added again",
+                            ));
+                        }
+                    }
+                    Some(CRDTValue::LwwSet(set)) => set.add(&self.node_id, member),
+                    other => return Err(missing_or_mismatched(other, "set", "SCREATE")),
+                }
+                Ok(None)
+            }
+            "SREM" => {
+                let member = decode_string(raw_value_bytes)?;
+                println!("received valid SREM: {}", member);
+
+                //removing a member that is not there is not an error, a two-phase set still
+                //keeps it out for good
+                match current {
+                    Some(CRDTValue::ASet(set)) => {
+                        set.remove(&member);
+                    }
+                    Some(CRDTValue::TwoPhaseSet(set)) => {
+                        set.remove(member);
+                    }
+                    Some(CRDTValue::LwwSet(set)) => {
+                        set.remove(&self.node_id, member);
+                    }
+                    other => return Err(missing_or_mismatched(other, "set", "SCREATE")),
+                }
+                Ok(None)
+            }
+            "IMAX" | "IMIN" => {
+                let value = decode_u64(raw_value_bytes)? as i64; //sent as the bytes of an i64
+                println!("received valid {}: {}", value_type, value);
+
+                //a value that is not past the current one is not an error, it just loses
+                match (value_type, current) {
+                    ("IMAX", Some(CRDTValue::MaxInt(register))) => {
+                        register.set(value);
+                        Ok(None)
+                    }
+                    ("IMIN", Some(CRDTValue::MinInt(register))) => {
+                        register.set(value);
+                        Ok(None)
+                    }
+                    (_, Some(_)) => Err(OpError::failed_precondition(format!(
+                        "type mismatch: key exisits, but value is not what {} works on",
+                        value_type
+                    ))),
+                    ("IMAX", None) => Ok(Some(CRDTValue::MaxInt(MaxRegister::new(value)))),
+                    (_, None) => Ok(Some(CRDTValue::MinInt(MinRegister::new(value)))),
+                }
+            }
+            "SMAX" | "SMIN" => {
+                //the payload, if any, comes in the path
+                let value = Ranked {
+                    value: raw_value_bytes,
+                    payload: path.first().cloned().unwrap_or_default().into_bytes(),
+                };
+                println!("received valid {}", value_type);
+
+                match (value_type, current) {
+                    ("SMAX", Some(CRDTValue::MaxBytes(register))) => {
+                        register.set(value);
+                        Ok(None)
+                    }
+                    ("SMIN", Some(CRDTValue::MinBytes(register))) => {
+                        register.set(value);
+                        Ok(None)
+                    }
+                    (_, Some(_)) => Err(OpError::failed_precondition(format!(
+                        "type mismatch: key exisits, but value is not what {} works on",
+                        value_type
+                    ))),
+                    ("SMAX", None) => Ok(Some(CRDTValue::MaxBytes(MaxRegister::new(value)))),
+                    (_, None) => Ok(Some(CRDTValue::MinBytes(MinRegister::new(value)))),
+                }
+            }
+            _ => Err(OpError::invalid_argument(format!(
+                "{} is not a write op",
+                value_type
+            ))),
+        }
+    }
+
+    //union of the sketches under `keys`, PFMERGE is applied with its registers as the value.
+    //Values in `staged` are used over the stored ones, and missing keys count as empty
+    fn union_of_sketches(
+        &self,
+        keys: &[String],
+        staged: &HashMap<String, CRDTValue>,
+    ) -> Result<HyperLogLog, OpError> {
+        let mut union = HyperLogLog::new();
+        for key in keys {
+            let stored = self.store.get(key);
+            let value = match staged.get(key) {
+                Some(value) => value,
+                None => match &stored {
+                    Some(stored) => &stored.data,
+                    None => continue,
+                },
+            };
+            match value {
+                CRDTValue::Hll(sketch) => union.merge(sketch),
+                _ => {
+                    return Err(OpError::failed_precondition(format!(
+                        "type mismatch: {} exisits, but value is not a HyperLogLog",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(union)
+    }
+
+    fn peer_addrs(&self) -> impl Iterator<Item = String> + '_ {
+        self.peers.iter().map(|peer| peer.key().clone())
+    }
+
+    //before a BDEC, asks peers for whatever rights this node is short of. Best effort, the
+    //decrement itself is refused if the rights are still not there
+    async fn acquire_rights(&self, key: &str, amount: u64) {
+        let held = match self.store.get(key).as_deref() {
+            Some(StoredValue {
+                data: CRDTValue::BCounter(counter),
+                ..
+            }) => counter.rights(&self.node_id),
+            _ => return, //apply_write reports the missing key or wrong type
+        };
+        let mut missing = amount.saturating_sub(held);
+
+        let peers: Vec<String> = self.peer_addrs().collect();
+        for peer_addr in peers {
+            if missing == 0 {
+                break;
+            }
+
+            let mut peer_client =
+                match cluster::connect(endpoint(&peer_addr), self.cluster.clone()).await {
+                    Ok(client) => client,
+                    Err(e) => {
+                        println!("failed to connect to {}: {}", peer_addr, e);
+                        continue;
+                    }
+                };
+            let request = Request::new(TransferRightsRequest {
+                key: key.to_string(),
+                to: self.node_id.clone(),
+                amount: missing,
+                reads_encoded: true,
+            });
+            match peer_client.transfer_rights(request).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    if let Some(value) = response.value {
+                        let _gate = self.write_gate.read().await;
+                        self.merge_remote(key.to_string(), value);
+                    }
+                    println!(
+                        "{} granted {} rights on {}",
+                        peer_addr, response.granted, key
+                    );
+                    missing = missing.saturating_sub(response.granted);
+                }
+                Err(e) => println!("failed to get rights on {} from {}: {}", key, peer_addr, e),
+            }
+        }
+    }
+
+    //queues a local write for causal delivery, a no-op outside of causal mode. Has to be
+    //called while the written keys are still held
+    fn record_causal<'a>(&self, written: impl Iterator<Item = (&'a String, &'a StoredValue)>) {
+        if let Some(causal) = &self.causal {
+            causal.record_local(value_messages(written), self.peer_addrs());
+        }
+    }
+
+    fn notify(&self, key: &str, value: &CRDTValue, origin: ChangeOrigin) {
+        self.changes
+            .notify(key, value.type_name(), value.read_bytes(), origin);
+    }
+
+    //merges a value received from a peer into the local state, watchers only hear about
+    //it if the merge actually changed the value they can read. Returns false if the value
+    //could not be merged, a type this node does not know or not the type stored under the key
+    fn merge_remote(&self, key: String, remote: StoredValueMessage) -> bool {
+        let Some(value) = remote.value else {
+            println!("not merging {}, its type is unknown to this node", key);
+            return false;
+        };
+        let mut remote_value = match CRDTValue::try_from(value) {
+            Ok(value) => value,
+            Err(e) => {
+                println!("not merging {}: {}", key, e);
+                return false;
+            }
+        };
+        for retired in self.retirements.folded() {
+            retire::forget(&mut remote_value, &retired);
+        }
+        let remote_version = VersionVector::from(remote.version);
+
+        let (stored, changed) = match self.store.entry(key.clone()) {
+            Entry::Occupied(occupied) => {
+                let mut current_value = occupied.into_ref();
+                let before = current_value.data.read_bytes();
+                if !current_value.data.merge_from(&remote_value) {
+                    println!(
+                        "type mismatch: key exisits, but value is not of type {}",
+                        remote_value.type_name()
+                    );
+                    //nor has this copy seen the writes of the remote one
+                    return false;
+                }
+                println!("merged from remote node");
+                let changed = current_value.data.read_bytes() != before;
+                current_value.version.join(&remote_version);
+
+                current_value.last_updated = SystemTime::now();
+                (current_value, changed)
+            }
+            //a key seen for the first time is always a change
+            Entry::Vacant(vacant) => {
+                let stored = vacant.insert(StoredValue {
+                    data: remote_value,
+                    last_updated: SystemTime::now(),
+                    version: remote_version,
+                });
+                (stored, true)
+            }
+        };
+
+        if changed {
+            self.notify(&key, &stored.data, ChangeOrigin::Gossip);
+        }
+        true
+    }
+
+    pub async fn start_listener(&self, config: Config) -> Result<(), Box<dyn std::error::Error>> {
+        let addr: SocketAddr = config.listen_address.as_str().parse()?;
+        Server::builder()
+            .add_service(ReplicationServiceServer::with_interceptor(
+                self.clone(),
+                ClusterCheck::new(&config.cluster_id),
+            ))
+            .serve(addr)
+            .await?;
+
+        Ok(())
+    }
+
+    // pub async fn push(
+    //     &self,
+    //     key: String,
+    //     value: CRDTValue,
+    // ) -> Result<(), Box<dyn std::error::Error>> {
+    //     //send updates to k randomly chosen peers
+    //     //first make sure to preconnect to 3 randomly chosen peer nodes
+    //     //lots of things to think of, like what if a node goes down, how will this node reconnect to
+    //     //some other node etc, will tackle these later
+
+    //     let mut rng = rand::rng();
+
+    //     let chosen_peers: Vec<String> = {
+    //         let peers: Vec<String> = self.peers.iter().map(|entry| entry.key().clone()).collect();
+    //         peers.choose_multiple(&mut rng, K).cloned().collect()
+    //     };
+
+    //     for peer_addr in chosen_peers.iter() {
+    //         match ReplicationServiceClient::connect((*peer_addr).clone()).await {
+    //             Ok(mut peer_client) => match &value {
+    //                 CRDTValue::Counter(inner) => {
+    //                     let state = Request::new(GossipChangesRequest {
+    //                         key: key.clone(),
+    //                         counter: Some(PnCounterMessage::from(inner.clone())),
+    //                     });
+
+    //                     println!("connected to the peer with id: {}", peer_addr);
+    //                     match peer_client.gossip_changes(state).await {
+    //                         Ok(response) => {
+    //                             println!("Response from peer: {:?}", response.into_inner())
+    //                         }
+    //                         Err(e) => println!("failed to send update to {}: {}", peer_addr, e),
+    //                     }
+    //                 }
+    //                 _ => print!("other types soon!"),
+    //             },
+    //             Err(e) => {
+    //                 println!("failed to connect to {}: {}", peer_addr, e);
+    //                 continue;
+    //             }
+    //         }
+    //     }
+
+    //     Ok(())
+    // }
+
+    //answers the requests of a Gossip stream. Once GOSSIP_WINDOW responses wait for the
+    //sender to read them no more requests are read, and the sender runs out of window
+    fn serve_gossip(
+        &self,
+        mut requests: impl Stream<Item = Result<GossipBatchRequest, tonic::Status>>
+            + Send
+            + Unpin
+            + 'static,
+    ) -> ReceiverStream<Result<GossipBatchResponse, tonic::Status>> {
+        let (tx, rx) = mpsc::channel(GOSSIP_WINDOW);
+        let server = self.clone();
+
+        tokio::spawn(async move {
+            //in order, the sender takes each response as the ack of its oldest request. A
+            //request that fails ends the stream, so none after it is acked before it
+            while let Some(Ok(batch)) = requests.next().await {
+                let response = server.apply_gossip(batch).await;
+                let refused = response.is_err();
+                if tx.send(response).await.is_err() || refused {
+                    break;
+                }
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+
+    //merges what a peer gossiped, for GossipBatch and each request of a Gossip stream
+    async fn apply_gossip(
+        &self,
+        batch: GossipBatchRequest,
+    ) -> Result<GossipBatchResponse, tonic::Status> {
+        if let Some(sender) = &batch.sender {
+            self.identities
+                .check(sender)
+                .map_err(tonic::Status::already_exists)?;
+        }
+
+        //folded retirements are learnt before anything is merged, so no value below can
+        //bring back the entries they dropped
+        let newly_folded = self.retirements.learn(&batch.retirements);
+        if !newly_folded.is_empty() {
+            let _gate = self.write_gate.write().await;
+            for retired in &newly_folded {
+                for mut stored in self.store.iter_mut() {
+                    retire::forget(&mut stored.data, retired);
+                }
+                self.retire_causal(retired);
+                println!("forgot retired node {}", retired);
+            }
+        }
+
+        //atomic batches go first, each one under the exclusive gate so none of our readers
+        //can see some of its keys merged and others not
+        for atomic in batch.atomic_batches {
+            if !self.batches.record(atomic.id.clone(), atomic.values.clone()) {
+                continue; //applied it before
+            }
+            let _gate = self.write_gate.write().await;
+            for (key, value) in atomic.values {
+                self.merge_remote(key, value);
+            }
+            println!("applied atomic batch {} from remote node", atomic.id);
+        }
+
+        if let Some(causal) = &self.causal {
+            if !batch.causal_updates.is_empty() {
+                let _gate = self.write_gate.write().await;
+                let received = causal.receive(batch.causal_updates);
+                if let (true, Some(sender)) = (received.behind, &batch.sender) {
+                    causal.fell_behind(&endpoint(&sender.listen_address));
+                }
+                for update in received.ready {
+                    for (key, value) in update.values {
+                        self.merge_remote(key, value);
+                    }
+                }
+                //nothing of the request is acked, the sender keeps every update of it
+                //queued and tries again once the buffer drained
+                if received.refused > 0 {
+                    return Err(tonic::Status::resource_exhausted(format!(
+                        "{} causal updates did not fit the buffer of updates waiting for \
+                         their dependencies",
+                        received.refused
+                    )));
+                }
+            }
+        } else if !batch.causal_updates.is_empty() {
+            println!("dropping causal updates, this node does not run in causal delivery mode");
+        }
+
+        let _gate = self.write_gate.read().await;
+        let (mut acked_keys, mut refused_keys) = (Vec::new(), Vec::new());
+        for (key, value) in batch.batch {
+            match self.merge_remote(key.clone(), value) {
+                true => acked_keys.push(key),
+                false => refused_keys.push(key),
+            }
+        }
+        //passed on to every other peer, the sender has it already
+        let sender = batch
+            .sender
+            .as_ref()
+            .map(|sender| endpoint(&sender.listen_address));
+        for message in batch.messages {
+            let onward = self
+                .peer_addrs()
+                .filter(|peer| Some(endpoint(peer)) != sender);
+            self.pubsub.receive(message, onward);
+        }
+
+        //the survivor of a retirement we agree to needs every count of the retired id we
+        //know about before it can fold them
+        let retirement_acks = self.retirements.acks_for(&batch.retirements, &self.node_id);
+        let retiring_values = self
+            .store
+            .iter()
+            .filter(|stored| {
+                retirement_acks
+                    .iter()
+                    .any(|retired| retire::has_entries_of(&stored.data, retired))
+            })
+            .map(|stored| (stored.key().clone(), value_message(stored.value())))
+            .filter_map(|(key, message)| {
+                let message = match batch.reads_encoded {
+                    true => message,
+                    false => wire::unencoded(message).ok()?,
+                };
+                Some((key, message))
+            })
+            .collect();
+
+        Ok(GossipBatchResponse {
+            success: true,
+            retirement_acks,
+            retiring_values,
+            acked_keys,
+            refused_keys,
+            instance_id: self.identities.own().instance_id,
+        })
+    }
+
+    //another node has our id, carrying on would mix up the entries of both in every counter.
+    //Right after a restart that node may be the previous process of this one, which peers
+    //remember for a little while, so the node only gives up once they would have forgotten it
+    fn claim_refused(&self, peer_addr: &str, reason: &str) -> Result<(), String> {
+        if self.identities.settled() {
+            return Err(reason.to_string());
+        }
+        println!("{} refuses our id for now: {}", peer_addr, reason);
+        Ok(())
+    }
+
+    //learns what the peer speaks. A peer that does not know Hello is from before it
+    async fn say_hello(
+        &self,
+        peer_client: &mut PeerClient,
+        peer_addr: &str,
+    ) -> Result<Peer, tonic::Status> {
+        let hello = Request::new(self.handshakes.hello(self.identities.own()));
+        let peer = match peer_client.hello(hello).await {
+            Ok(response) => self
+                .handshakes
+                .negotiate(&response.into_inner())
+                .map_err(tonic::Status::failed_precondition)?,
+            Err(e) if e.code() == tonic::Code::Unimplemented => Peer::legacy(),
+            Err(e) => return Err(e),
+        };
+        println!(
+            "said hello to {} at {}, speaking protocol version {}",
+            peer.node_id, peer_addr, peer.protocol_version
+        );
+        self.handshakes.insert(peer_addr, peer.clone());
+        Ok(peer)
+    }
+
+    //merges the counts a peer sent along with its acks, and only then records the acks
+    async fn receive_retirement_acks(&self, peer_addr: &str, response: GossipBatchResponse) {
+        if response.retirement_acks.is_empty() {
+            return;
+        }
+        let _gate = self.write_gate.read().await;
+        for (key, value) in response.retiring_values {
+            self.merge_remote(key, value);
+        }
+        for retired in response.retirement_acks {
+            self.retirements
+                .record_ack(&retired, &self.node_id, peer_addr);
+        }
+    }
+
+    //folds the retired ids every peer acked into this node's entries. Counts moving from one
+    //entry to another are a local write as far as the version vectors are concerned
+    async fn fold_retirements(&self) {
+        let peers: Vec<String> = self.peer_addrs().collect();
+        let ready = self.retirements.ready_to_fold(&self.node_id, &peers);
+        if ready.is_empty() {
+            return;
+        }
+
+        let _gate = self.write_gate.write().await;
+        let now = SystemTime::now();
+        for retired in ready {
+            let mut written = HashMap::new();
+            for mut stored in self.store.iter_mut() {
+                if retire::fold(&mut stored.data, &retired, &self.node_id) {
+                    stored.last_updated = now;
+                    stored.version.advance(&self.node_id, 1);
+                    let copy = StoredValue {
+                        data: stored.data.clone(),
+                        last_updated: now,
+                        version: stored.version.clone(),
+                    };
+                    written.insert(stored.key().clone(), copy);
+                }
+            }
+            self.record_causal(written.iter());
+            self.retirements.mark_folded(&retired);
+            self.retire_causal(&retired);
+            println!(
+                "folded retired node {} into {} in {} counters",
+                retired,
+                self.node_id,
+                written.len()
+            );
+        }
+    }
+
+    //causal updates that waited on a writer of the retired id no longer wait, has to be
+    //called under the exclusive gate
+    fn retire_causal(&self, retired: &str) {
+        if let Some(causal) = &self.causal {
+            for update in causal.retire(retired) {
+                for (key, value) in update.values {
+                    self.merge_remote(key, value);
+                }
+            }
+        }
+    }
+
+    //values changed at or after `since`. Nothing is sent this way in causal mode, a peer
+    //would merge it right away without looking at its dependencies, it catches up instead
+    fn updates_since(&self, since: SystemTime) -> Vec<(String, StoredValueMessage)> {
+        if self.causal.is_some() {
+            return Vec::new();
+        }
+
+        self.store
+            .iter()
+            .filter(|key_val| key_val.value().last_updated >= since)
+            .map(|key_val| (key_val.key().clone(), value_message(key_val.value())))
+            .collect()
+    }
+
+    pub async fn create_and_gossip_batch(&self) -> Result<(), Box<dyn std::error::Error>> {
+        //a task per peer, so a slow or unreachable peer only holds up the rounds to itself
+        let mut gossip = JoinSet::new();
+        for peer_addr in self.peer_addrs().collect::<Vec<String>>() {
+            gossip.spawn(self.clone().gossip_with(peer_addr));
+        }
+
+        loop {
+            tokio::select! {
+                Some(stopped) = gossip.join_next() => match stopped {
+                    Ok(Err(e)) => return Err(e.into()),
+                    Ok(Ok(())) => {}
+                    Err(e) => eprintln!("gossip task failed: {}", e),
+                },
+                _ = tokio::time::sleep(GOSSIP_INTERVAL) => self.fold_retirements().await,
+            }
+        }
+    }
+
+    //gossips to one peer every GOSSIP_INTERVAL, only returns if the node cannot stay in the
+    //cluster
+    async fn gossip_with(self, peer_addr: String) -> Result<(), String> {
+        //connected once and kept, the channel reconnects by itself
+        let mut peer_client = None;
+        let mut sync = PeerSync::new();
+        loop {
+            self.gossip_round(&mut peer_client, &peer_addr, &mut sync)
+                .await?;
+            tokio::time::sleep(GOSSIP_INTERVAL).await;
+        }
+    }
+
+    //what a round sends the peer, taken from a consistent view of the store: an atomic batch
+    //is either fully in it or not at all
+    async fn snapshot(
+        &self,
+        sync: &mut PeerSync,
+    ) -> (Vec<(String, StoredValueMessage)>, Vec<AtomicBatchMessage>) {
+        let _gate = self.write_gate.read().await;
+        //whatever changes from here on goes out next round
+        let started = SystemTime::now();
+        let mut updates = self.updates_since(sync.since);
+        let atomic_batches = self.batches.recorded_since(sync.since);
+        sync.since = started;
+        for key in sync.unacked.drain() {
+            if updates.iter().any(|(recent, _)| *recent == key) {
+                continue;
+            }
+            if let Some(message) = self.store.get(&key).map(|v| value_message(&v)) {
+                updates.push((key, message));
+            }
+        }
+        (updates, atomic_batches)
+    }
+
+    async fn gossip_round(
+        &self,
+        peer_client: &mut Option<PeerClient>,
+        peer_addr: &str,
+        sync: &mut PeerSync,
+    ) -> Result<(), String> {
+        let peer_client = match peer_client {
+            Some(client) => client,
+            None => match cluster::connect(endpoint(peer_addr), self.cluster.clone()).await {
+                Ok(client) => peer_client.insert(client),
+                Err(e) => {
+                    println!("failed to connect to {}: {}", peer_addr, e);
+                    return Ok(());
+                }
+            },
+        };
+
+        let peer = match self.handshakes.get(peer_addr) {
+            Some(peer) => peer,
+            None => match self.say_hello(peer_client, peer_addr).await {
+                Ok(peer) => peer,
+                Err(e) if e.code() == tonic::Code::AlreadyExists => {
+                    return self.claim_refused(peer_addr, e.message());
+                }
+                //tried again next round
+                Err(e) => {
+                    println!("not gossiping with {}: {}", peer_addr, e.message());
+                    return Ok(());
+                }
+            },
+        };
+
+        if let Some(causal) = &self.causal {
+            if causal.is_behind(&endpoint(peer_addr)) {
+                //tried again next round, what the peer sends meanwhile waits in the buffer
+                let request = Request::new(CatchUpRequest {
+                    sender: Some(self.identities.own()),
+                });
+                match peer_client.catch_up(request).await {
+                    Ok(response) => self.caught_up(peer_addr, response.into_inner()).await,
+                    Err(e) => println!("failed to catch up from {}: {}", peer_addr, e.message()),
+                }
+            }
+        }
+
+        //whatever the peer would not know is left out
+        let (updates, atomic_batches) = self.snapshot(sync).await;
+        let updates: Vec<(String, StoredValueMessage)> = updates
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, peer.prepare(value)?)))
+            .collect();
+        let atomic_batches: Vec<AtomicBatchMessage> = atomic_batches
+            .into_iter()
+            .filter_map(|batch| peer.prepare_batch(batch))
+            .collect();
+        //pub/sub messages, atomic batches and causal updates ride along with the first
+        //request of the round
+        let messages = self.pubsub.pending_for(peer_addr, BATCH_SIZE);
+        let causal_updates: Vec<_> = self
+            .causal
+            .as_ref()
+            .map(|causal| causal.pending_for(peer_addr, BATCH_SIZE))
+            .unwrap_or_default()
+            .into_iter()
+            //held back until the peer is upgraded, they are only acked once sent
+            .filter_map(|update| peer.prepare_update(update))
+            .collect();
+
+        let mut requests: Vec<GossipBatchRequest> = updates
+            .chunks(BATCH_SIZE)
+            .map(|chunk| GossipBatchRequest {
+                batch: chunk.iter().cloned().collect(),
+                retirements: self.retirements.to_gossip(),
+                sender: Some(self.identities.own()),
+                reads_encoded: true,
+                ..Default::default()
+            })
+            .collect();
+        //a round always sends at least one request, even with nothing else in it the peer
+        //gets to check our id
+        if requests.is_empty() {
+            requests.push(GossipBatchRequest {
+                retirements: self.retirements.to_gossip(),
+                sender: Some(self.identities.own()),
+                reads_encoded: true,
+                ..Default::default()
+            });
+        }
+        requests[0].messages = messages;
+        requests[0].atomic_batches = atomic_batches;
+        requests[0].causal_updates = causal_updates;
+
+        let streaming = peer.protocol_version >= STREAMING_PROTOCOL_VERSION;
+        let mut round = Round::new(requests, if streaming { GOSSIP_WINDOW } else { 1 });
+        let sent = match streaming {
+            true => self.stream_round(peer_client, peer_addr, &mut round).await,
+            false => self.unary_round(peer_client, peer_addr, &mut round).await,
+        };
+        match sent {
+            Ok(()) => {}
+            Err(e) if e.code() == tonic::Code::AlreadyExists => {
+                self.claim_refused(peer_addr, e.message())?;
+            }
+            //the peer merges requests in order and stops at the first one that fails, the
+            //keys it did not ack go again next round
+            Err(e) => {
+                eprintln!("Failed to send batch to {}: {}", peer_addr, e);
+                //it may come back upgraded
+                self.handshakes.forget(peer_addr);
+            }
+        }
+
+        self.peers.insert(peer_addr.to_string(), SystemTime::now());
+        if round.synced() > 0 {
+            println!("Synced {} items with {}", round.synced(), peer_addr);
+        }
+        let answered_by = round.answered_by.clone();
+        sync.unacked = round.unacked();
+        sync.heard_from(&answered_by);
+        Ok(())
+    }
+
+    //merges the peer's whole store, then goes on with every origin's stream from where it is
+    async fn caught_up(&self, peer_addr: &str, response: CatchUpResponse) {
+        let Some(causal) = &self.causal else {
+            return;
+        };
+        let _gate = self.write_gate.write().await;
+        let keys = response.values.len();
+        for (key, value) in response.values {
+            self.merge_remote(key, value);
+        }
+        for update in causal.catch_up(&endpoint(peer_addr), response.applied) {
+            for (key, value) in update.values {
+                self.merge_remote(key, value);
+            }
+        }
+        println!("caught up on {} keys from {}", keys, peer_addr);
+    }
+
+    //sends the round over one Gossip stream, up to GOSSIP_WINDOW requests ahead of the acks
+    async fn stream_round(
+        &self,
+        peer_client: &mut PeerClient,
+        peer_addr: &str,
+        round: &mut Round,
+    ) -> Result<(), tonic::Status> {
+        let (tx, rx) = mpsc::channel(GOSSIP_WINDOW);
+        //a full window goes out with the call, it never waits on the channel
+        while let Some(request) = round.send_next() {
+            let _ = tx.try_send(request);
+        }
+        let mut responses = peer_client
+            .gossip(ReceiverStream::new(rx))
+            .await?
+            .into_inner();
+
+        while !round.done() {
+            let Some(response) = responses.message().await? else {
+                return Err(tonic::Status::aborted("peer ended the gossip stream early"));
+            };
+            self.receive_ack(peer_addr, round, response).await;
+            while let Some(request) = round.send_next() {
+                let _ = tx.try_send(request);
+            }
+        }
+        //dropping tx ends the stream
+        Ok(())
+    }
+
+    //sends the round one GossipBatch at a time, for peers from before the Gossip stream
+    async fn unary_round(
+        &self,
+        peer_client: &mut PeerClient,
+        peer_addr: &str,
+        round: &mut Round,
+    ) -> Result<(), tonic::Status> {
+        while let Some(request) = round.send_next() {
+            //peers from before per key acks merge a request whole or fail it
+            let keys = request.batch.keys().cloned().collect();
+            let mut response = peer_client
+                .gossip_batch(Request::new(request))
+                .await?
+                .into_inner();
+            response.acked_keys = keys;
+            self.receive_ack(peer_addr, round, response).await;
+        }
+        Ok(())
+    }
+
+    async fn receive_ack(&self, peer_addr: &str, round: &mut Round, response: GossipBatchResponse) {
+        if !response.refused_keys.is_empty() {
+            println!(
+                "{} cannot merge {:?}, they are not sent again until they change",
+                peer_addr, response.refused_keys
+            );
+        }
+        if let Some(sent) = round.ack(&response) {
+            self.pubsub.ack(peer_addr, &sent.messages);
+            if let Some(causal) = &self.causal {
+                causal.ack(peer_addr, &sent.causal_updates);
+            }
+        }
+        self.receive_retirement_acks(peer_addr, response).await;
+    }
+}
+
+//peers may be configured with or without the scheme
+fn endpoint(peer_addr: &str) -> String {
+    if peer_addr.starts_with("http") {
+        peer_addr.to_string()
+    } else {
+        format!("http://{}", peer_addr)
+    }
+}
+
+//why a client op was refused. The helpers below and apply_write return it instead of a
+//tonic::Status, which is too large to pass around in every Result, and the rpc handlers
+//turn it into one with `?`
+#[derive(Debug)]
+struct OpError {
+    code: tonic::Code,
+    message: String,
+}
+
+impl OpError {
+    fn invalid_argument(message: impl Into<String>) -> Self {
+        OpError {
+            code: tonic::Code::InvalidArgument,
+            message: message.into(),
+        }
+    }
+
+    fn failed_precondition(message: impl Into<String>) -> Self {
+        OpError {
+            code: tonic::Code::FailedPrecondition,
+            message: message.into(),
+        }
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        OpError {
+            code: tonic::Code::NotFound,
+            message: message.into(),
+        }
+    }
+
+    fn out_of_range(message: impl Into<String>) -> Self {
+        OpError {
+            code: tonic::Code::OutOfRange,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<OpError> for tonic::Status {
+    fn from(e: OpError) -> Self {
+        tonic::Status::new(e.code, e.message)
+    }
+}
+
+fn decode_u64(raw_value_bytes: Vec<u8>) -> Result<u64, OpError> {
+    //value shld be a u64
+    let bytes: [u8; 8] = raw_value_bytes
+        .try_into()
+        .map_err(|_| OpError::invalid_argument("invalid byte length for u64, expected 8 bytes"))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+//wide counter amounts, 16 bytes
+fn decode_i128(raw_value_bytes: Vec<u8>) -> Result<i128, OpError> {
+    let bytes: [u8; 16] = raw_value_bytes.try_into().map_err(|_| {
+        OpError::invalid_argument("invalid byte length for i128, expected 16 bytes")
+    })?;
+    Ok(i128::from_be_bytes(bytes))
+}
+
+fn decode_string(raw_value_bytes: Vec<u8>) -> Result<String, OpError> {
+    String::from_utf8(raw_value_bytes)
+        .map_err(|_| OpError::invalid_argument("value is not valid utf-8"))
+}
+
+fn expect_counter(current: Option<&mut CRDTValue>) -> Result<&mut PNCounter, OpError> {
+    match current {
+        Some(CRDTValue::Counter(local_counter)) => Ok(local_counter),
+        Some(_) => Err(OpError::failed_precondition(
+            "type mismatch: key exisits, but value is not of type PNCounter",
+        )),
+        None => Err(OpError::not_found("counter does not exist, CSET it first")),
+    }
+}
+
+//members of any of the set types, sorted
+fn set_members(value: &CRDTValue) -> Vec<&str> {
+    let mut members: Vec<&str> = match value {
+        CRDTValue::ASet(set) => set.elements().map(String::as_str).collect(),
+        CRDTValue::TwoPhaseSet(set) => set.elements().map(String::as_str).collect(),
+        CRDTValue::LwwSet(set) => set.elements().map(String::as_str).collect(),
+        _ => Vec::new(),
+    };
+    members.sort_unstable();
+    members
+}
+
+fn missing_or_mismatched(
+    current: Option<&mut CRDTValue>,
+    type_name: &str,
+    create_op: &str,
+) -> OpError {
+    match current {
+        Some(_) => OpError::failed_precondition(format!(
+            "type mismatch: key exisits, but value is not a {}",
+            type_name
+        )),
+        None => OpError::not_found(format!(
+            "{} does not exist, {} it first",
+            type_name, create_op
+        )),
+    }
+}
+
+//runs a map op on the map stored under the key, or on a new one if the key does not exist
+fn with_map(
+    current: Option<&mut CRDTValue>,
+    op: impl FnOnce(&mut ORMap) -> Result<(), MapError>,
+) -> Result<Option<CRDTValue>, OpError> {
+    let map_status = |e: MapError| match e {
+        MapError::EmptyPath => OpError::invalid_argument(e.to_string()),
+        MapError::TypeMismatch { .. } => OpError::failed_precondition(e.to_string()),
+        MapError::Overflow(e) => overflow_status(e),
+    };
+    match current {
+        Some(CRDTValue::Map(map)) => {
+            op(map).map_err(map_status)?;
+            Ok(None)
+        }
+        Some(_) => Err(OpError::failed_precondition(
+            "type mismatch: key exisits, but value is not a map",
+        )),
+        None => {
+            let mut map = ORMap::new();
+            op(&mut map).map_err(map_status)?;
+            Ok(Some(CRDTValue::Map(map)))
+        }
+    }
+}
+
+//runs a document op on the document stored under the key, or on a new one if the key does
+//not exist
+fn with_doc(
+    current: Option<&mut CRDTValue>,
+    op: impl FnOnce(&mut JsonDoc) -> Result<(), DocError>,
+) -> Result<Option<CRDTValue>, OpError> {
+    match current {
+        Some(CRDTValue::Doc(doc)) => {
+            op(doc).map_err(doc_status)?;
+            Ok(None)
+        }
+        Some(_) => Err(OpError::failed_precondition(
+            "type mismatch: key exisits, but value is not a document",
+        )),
+        None => {
+            let mut doc = JsonDoc::new();
+            op(&mut doc).map_err(doc_status)?;
+            Ok(Some(CRDTValue::Doc(doc)))
+        }
+    }
+}
+
+//runs a list op on the list stored under the key, or on a new one if the key does not exist
+fn with_list(
+    current: Option<&mut CRDTValue>,
+    op: impl FnOnce(&mut Rga<CrdtValue>) -> Result<(), IndexOutOfBounds>,
+) -> Result<Option<CRDTValue>, OpError> {
+    match current {
+        Some(CRDTValue::List(list)) => {
+            op(list).map_err(index_status)?;
+            Ok(None)
+        }
+        Some(_) => Err(OpError::failed_precondition(
+            "type mismatch: key exisits, but value is not a list",
+        )),
+        None => {
+            let mut list = Rga::new();
+            op(&mut list).map_err(index_status)?;
+            Ok(Some(CRDTValue::List(list)))
+        }
+    }
+}
+
+//runs a text op on the text stored under the key, or on an empty one if the key does not exist
+fn with_text(
+    current: Option<&mut CRDTValue>,
+    op: impl FnOnce(&mut Text) -> Result<(), IndexOutOfBounds>,
+) -> Result<Option<CRDTValue>, OpError> {
+    match current {
+        Some(CRDTValue::Text(text)) => {
+            op(text).map_err(index_status)?;
+            Ok(None)
+        }
+        Some(_) => Err(OpError::failed_precondition(
+            "type mismatch: key exisits, but value is not text",
+        )),
+        None => {
+            let mut text = Text::new();
+            op(&mut text).map_err(index_status)?;
+            Ok(Some(CRDTValue::Text(text)))
+        }
+    }
+}
+
+//the write was refused, the counter is as it was
+fn overflow_status(e: CounterOverflow) -> OpError {
+    OpError::out_of_range(e.to_string())
+}
+
+fn index_status(e: IndexOutOfBounds) -> OpError {
+    OpError::out_of_range(e.to_string())
+}
+
+//list and text ops get their indexes in the path of the request
+fn list_index(path: &[String], n: usize) -> Result<usize, OpError> {
+    path.get(n)
+        .and_then(|index| index.parse().ok())
+        .ok_or_else(|| OpError::invalid_argument("missing or invalid list index"))
+}
+
+//elements from `start` to `stop` inclusive as a JSON array, negative indexes count from the end
+fn list_range(list: &Rga<CrdtValue>, start: i64, stop: i64) -> serde_json::Value {
+    let (skip, take) = window(list.len(), start, stop);
+    list.iter()
+        .skip(skip)
+        .take(take)
+        .map(|element| json_to_serde(to_json(element)))
+        .collect()
+}
+
+//(skip, take) for the items from `start` to `stop` inclusive out of `len`, negative indexes
+//count from the end
+fn window(len: usize, start: i64, stop: i64) -> (usize, usize) {
+    let len = len as i64;
+    let resolve = |index: i64| if index < 0 { len + index } else { index };
+    let (start, stop) = (resolve(start).max(0), resolve(stop).min(len - 1));
+    if start > stop {
+        return (0, 0);
+    }
+    (start as usize, (stop - start + 1) as usize)
+}
+
+//sorted set ops get the member in the path of the request
+fn zset_member(path: &[String]) -> Result<&str, OpError> {
+    match path {
+        [member] => Ok(member),
+        _ => Err(OpError::invalid_argument("missing sorted set member")),
+    }
+}
+
+//answers one of ZSET_READS
+fn zset_query(zset: &SortedSet, op: &str, args: &[String]) -> Result<serde_json::Value, OpError> {
+    let int = |n: usize| {
+        args.get(n)
+            .and_then(|arg| arg.parse::<i64>().ok())
+            .ok_or_else(|| OpError::invalid_argument(format!("{} takes two integers", op)))
+    };
+    let json = match op {
+        "ZRANGE" | "ZREVRANGE" => {
+            let mut ranked = zset.ranked();
+            if op == "ZREVRANGE" {
+                ranked.reverse();
+            }
+            let (skip, take) = window(ranked.len(), int(0)?, int(1)?);
+            scored(ranked.into_iter().skip(skip).take(take).collect())
+        }
+        "ZRANGEBYSCORE" => scored(zset.range_by_score(int(0)?, int(1)?)),
+        "ZRANK" => zset.rank(zset_member(args)?).into(),
+        _ => zset.score(zset_member(args)?).into(),
+    };
+    Ok(json)
+}
+
+fn scored(members: Vec<(&str, i64)>) -> serde_json::Value {
+    members
+        .into_iter()
+        .map(|(member, score)| serde_json::json!([member, score]))
+        .collect()
+}
+
+//a max or min register as plain JSON, None for the other types
+fn bound_json(data: &CRDTValue) -> Option<serde_json::Value> {
+    let ranked = |ranked: &Ranked| {
+        serde_json::json!({
+            "value": String::from_utf8_lossy(&ranked.value),
+            "payload": String::from_utf8_lossy(&ranked.payload),
+        })
+    };
+    match data {
+        CRDTValue::MaxInt(register) => Some(register.value.into()),
+        CRDTValue::MinInt(register) => Some(register.value.into()),
+        CRDTValue::MaxBytes(register) => Some(ranked(&register.value)),
+        CRDTValue::MinBytes(register) => Some(ranked(&register.value)),
+        _ => None,
+    }
+}
+
+fn doc_status(e: DocError) -> OpError {
+    match e {
+        DocError::NotFound(_) => OpError::not_found(e.to_string()),
+        DocError::BadIndex(_) => OpError::out_of_range(e.to_string()),
+        DocError::TypeMismatch { .. } | DocError::RootNotObject => {
+            OpError::failed_precondition(e.to_string())
+        }
+        DocError::Overflow(e) => overflow_status(e),
+    }
+}
+
+//the value at `path` in a map or document as plain JSON, the whole of it for an empty path
+//and null if there is nothing at the path. None for the other types
+fn json_at(data: &CRDTValue, path: &[String]) -> Option<serde_json::Value> {
+    let json = match data {
+        CRDTValue::Map(map) if path.is_empty() => Some(to_json(&CrdtValue::Map(map.clone()))),
+        CRDTValue::Map(map) => map.get(path).map(to_json),
+        CRDTValue::Doc(doc) => doc.get(path),
+        _ => return None,
+    };
+    Some(json.map(json_to_serde).unwrap_or(serde_json::Value::Null))
+}
+
+//a stored value in the form it is gossiped in
+fn value_message(stored: &StoredValue) -> StoredValueMessage {
+    StoredValueMessage {
+        value: Some(wire::encoded(&stored.data)),
+        version: stored.version.entries.clone(),
+    }
+}
+
+fn value_messages<'a>(
+    values: impl Iterator<Item = (&'a String, &'a StoredValue)>,
+) -> HashMap<String, StoredValueMessage> {
+    values
+        .map(|(key, stored)| (key.clone(), value_message(stored)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::NodeIdentity;
+
+    fn server(node_id: &str) -> ReplicationServer {
+        ReplicationServer {
+            store: Arc::new(DashMap::new()),
+            node_id: node_id.to_string(),
+            peers: Arc::new(DashMap::new()),
+            changes: ChangeFeed::new(),
+            pubsub: PubSub::new(),
+            batches: AtomicBatches::new(),
+            causal: None,
+            write_gate: Arc::new(RwLock::new(())),
+            retirements: Retirements::new(),
+            identities: Identities::new(node_id, "127.0.0.1:8000"),
+            handshakes: Handshakes::new(""),
+            cluster: ClusterStamp::new("").unwrap(),
+            session_wait: Duration::from_secs(4),
+        }
+    }
+
+    fn write(valuetype: &str, key: &str, value: Vec<u8>) -> Request<PropagateDataRequest> {
+        Request::new(PropagateDataRequest {
+            valuetype: valuetype.to_string(),
+            key: key.to_string(),
+            value,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn watchers_hear_local_writes_and_merges() {
+        let node = server("node_1");
+        let mut events = node
+            .watch(Request::new(WatchRequest {
+                keys: vec![String::from("views")],
+                prefixes: Vec::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        node.propagate_data(write("CSET", "other", 1u64.to_be_bytes().to_vec()))
+            .await
+            .unwrap();
+        node.propagate_data(write("CSET", "views", 5u64.to_be_bytes().to_vec()))
+            .await
+            .unwrap();
+        let remote = PNCounter::new(String::from("node_2"), 3, 0);
+        node.merge_remote(
+            String::from("views"),
+            StoredValueMessage {
+                value: Some(stored_value_message::Value::Counter(remote.into())),
+                version: HashMap::from([(String::from("node_2"), 1)]),
+            },
+        );
+
+        let local = events.next().await.unwrap().unwrap();
+        assert_eq!(local.key, "views");
+        assert_eq!(local.value, 5i64.to_be_bytes());
+        assert_eq!(local.origin, ChangeOrigin::Local as i32);
+        let merged = events.next().await.unwrap().unwrap();
+        assert_eq!(merged.value, 8i64.to_be_bytes());
+        assert_eq!(merged.origin, ChangeOrigin::Gossip as i32);
+    }
+
+    #[tokio::test]
+    async fn rights_only_go_to_nodes_that_said_hello() {
+        let node = server("node_1");
+        node.propagate_data(write("BSET", "seats", 10u64.to_be_bytes().to_vec()))
+            .await
+            .unwrap();
+        let transfer = |to: &str| {
+            Request::new(TransferRightsRequest {
+                key: String::from("seats"),
+                to: to.to_string(),
+                amount: 4,
+                reads_encoded: true,
+            })
+        };
+
+        let refused = node.transfer_rights(transfer("node_2")).await.unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::NotFound);
+
+        let other = server("node_2");
+        node.hello(Request::new(other.handshakes.hello(other.identities.own())))
+            .await
+            .unwrap();
+        let granted = node.transfer_rights(transfer("node_2")).await.unwrap();
+        assert_eq!(granted.into_inner().granted, 4);
+    }
+
+    #[tokio::test]
+    async fn only_merged_keys_are_acked() {
+        use stored_value_message::Value;
+        let node = server("node_1");
+        node.propagate_data(write("CSET", "views", 5u64.to_be_bytes().to_vec()))
+            .await
+            .unwrap();
+        let counter = |value| StoredValueMessage {
+            value: Some(value),
+            version: HashMap::from([(String::from("node_2"), 1)]),
+        };
+        let grow_only = GCounter::new(String::from("node_2"), 3).into();
+        let counted = PNCounter::new(String::from("node_2"), 3, 0).into();
+        let batch = GossipBatchRequest {
+            batch: HashMap::from([
+                (String::from("views"), counter(Value::GCounter(grow_only))),
+                (String::from("visits"), counter(Value::Counter(counted))),
+                (String::from("unknown"), StoredValueMessage::default()),
+            ]),
+            ..Default::default()
+        };
+
+        let response = node.apply_gossip(batch).await.unwrap();
+        assert_eq!(response.acked_keys, ["visits"]);
+        let mut refused = response.refused_keys;
+        refused.sort();
+        assert_eq!(refused, ["unknown", "views"]);
+        //the copy that refused the merge has not seen the writes of node_2 either
+        assert_eq!(node.store.get("views").unwrap().version.get("node_2"), 0);
+    }
+
+    fn gossiped(key: &str) -> GossipBatchRequest {
+        let counter = PNCounter::new(String::from("node_2"), 1, 0);
+        GossipBatchRequest {
+            batch: HashMap::from([(
+                key.to_string(),
+                StoredValueMessage {
+                    value: Some(stored_value_message::Value::Counter(counter.into())),
+                    version: HashMap::from([(String::from("node_2"), 1)]),
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_gossip_stream_is_read_no_further_than_the_window() {
+        let node = server("node_1");
+        let (requests, received) = mpsc::channel(16);
+        let mut responses = node.serve_gossip(ReceiverStream::new(received));
+        let keys: Vec<String> = (0..GOSSIP_WINDOW + 2).map(|i| format!("k{}", i)).collect();
+        for key in &keys {
+            requests.send(Ok(gossiped(key))).await.unwrap();
+        }
+
+        //a window of responses waits to be read, and the request after them to be answered
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(node.store.len(), GOSSIP_WINDOW + 1);
+
+        //every request is acked, in the order it was sent
+        for key in &keys {
+            let response = responses.next().await.unwrap().unwrap();
+            assert_eq!(response.acked_keys, [key.as_str()]);
+        }
+        assert_eq!(node.store.len(), keys.len());
+    }
+
+    #[tokio::test]
+    async fn a_gossip_stream_ends_at_the_first_request_that_fails() {
+        let node = server("node_1");
+        //a request from another node under our id is refused
+        let mut impostor = gossiped("b");
+        impostor.sender = Some(NodeIdentity {
+            node_id: String::from("node_1"),
+            listen_address: String::from("127.0.0.1:9000"),
+            instance_id: String::from("another"),
+        });
+        let stream = tokio_stream::iter([Ok(gossiped("a")), Ok(impostor), Ok(gossiped("c"))]);
+        let responses: Vec<_> = node.serve_gossip(stream).collect().await;
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].as_ref().unwrap().acked_keys, ["a"]);
+        let refused = responses[1].as_ref().unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::AlreadyExists);
+        //so nothing after it is acked before it
+        assert!(!node.store.contains_key("c"));
+
+        //nor is anything read past a stream that broke mid-round
+        let broken = tokio_stream::iter([
+            Ok(gossiped("d")),
+            Err(tonic::Status::unavailable("connection reset")),
+            Ok(gossiped("e")),
+        ]);
+        let responses: Vec<_> = node.serve_gossip(broken).collect().await;
+        assert_eq!(responses.len(), 1);
+        assert!(node.store.contains_key("d") && !node.store.contains_key("e"));
+    }
+
+    #[tokio::test]
+    async fn a_round_only_sends_what_changed_since_the_last_one() {
+        let node = server("node_1");
+        node.propagate_data(write("CSET", "views", 5u64.to_be_bytes().to_vec()))
+            .await
+            .unwrap();
+        let mut sync = PeerSync::new();
+
+        //the first round sends everything, and the peer that answered it is noted
+        assert_eq!(node.snapshot(&mut sync).await.0.len(), 1);
+        sync.heard_from("a");
+        assert!(node.snapshot(&mut sync).await.0.is_empty());
+
+        node.propagate_data(write("CSET", "likes", 1u64.to_be_bytes().to_vec()))
+            .await
+            .unwrap();
+        let (updates, _) = node.snapshot(&mut sync).await;
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].0, "likes");
+
+        //a peer that restarted gets everything again
+        sync.heard_from("b");
+        assert_eq!(node.snapshot(&mut sync).await.0.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn counter_ops_that_would_overflow_are_refused() {
+        let node = server("node_1");
+        let at = |valuetype: &str, key: &str, value: u64, path: &[&str]| {
+            let mut request = write(valuetype, key, value.to_be_bytes().to_vec());
+            request.get_mut().path = path.iter().map(|p| p.to_string()).collect();
+            request
+        };
+        node.propagate_data(at("BSET", "seats", u64::MAX, &[]))
+            .await
+            .unwrap();
+        node.propagate_data(at("MINC", "user", i64::MAX as u64, &["visits"]))
+            .await
+            .unwrap();
+        node.propagate_data(at("ZINCRBY", "board", i64::MAX as u64, &["alice"]))
+            .await
+            .unwrap();
+
+        for refused in [
+            at("BINC", "seats", 1, &[]),
+            at("MINC", "user", 1, &["visits"]),
+            at("ZINCRBY", "board", 1, &["alice"]),
+        ] {
+            let status = node.propagate_data(refused).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::OutOfRange);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_late_joiner_catches_up_in_causal_mode() {
+        let node_1 = ReplicationServer {
+            causal: Some(CausalDelivery::new("node_1")),
+            ..server("node_1")
+        };
+        node_1
+            .propagate_data(write("CSET", "orders", 7u64.to_be_bytes().to_vec()))
+            .await
+            .unwrap();
+
+        //node_2 joins, the first write it is sent is node_1's second
+        node_1
+            .peers
+            .insert(String::from("127.0.0.1:8001"), SystemTime::UNIX_EPOCH);
+        let node_2 = ReplicationServer {
+            causal: Some(CausalDelivery::new("node_2")),
+            identities: Identities::new("node_2", "127.0.0.1:8001"),
+            ..server("node_2")
+        };
+        let gossip_from_node_1 = || {
+            let causal = node_1.causal.as_ref().unwrap();
+            let causal_updates = causal.pending_for("127.0.0.1:8001", BATCH_SIZE);
+            causal.ack("127.0.0.1:8001", &causal_updates);
+            GossipBatchRequest {
+                causal_updates,
+                sender: Some(node_1.identities.own()),
+                ..Default::default()
+            }
+        };
+        let orders = |node: &ReplicationServer| {
+            node.store
+                .get("orders")
+                .and_then(|stored| stored.data.counter_value())
+        };
+        node_1
+            .propagate_data(write("CINC", "orders", 5u64.to_be_bytes().to_vec()))
+            .await
+            .unwrap();
+        node_2.apply_gossip(gossip_from_node_1()).await.unwrap();
+        assert_eq!(orders(&node_2), None);
+
+        let node_1_addr = endpoint("127.0.0.1:8000");
+        assert!(node_2.causal.as_ref().unwrap().is_behind(&node_1_addr));
+        let request = Request::new(CatchUpRequest {
+            sender: Some(node_2.identities.own()),
+        });
+        let response = node_1.catch_up(request).await.unwrap().into_inner();
+        node_2.caught_up("127.0.0.1:8000", response).await;
+        assert_eq!(orders(&node_2), Some(12));
+
+        //and goes on with node_1's writes from there
+        node_1
+            .propagate_data(write("CINC", "orders", 1u64.to_be_bytes().to_vec()))
+            .await
+            .unwrap();
+        node_2.apply_gossip(gossip_from_node_1()).await.unwrap();
+        assert_eq!(orders(&node_2), Some(13));
+        assert!(!node_2.causal.as_ref().unwrap().is_behind(&node_1_addr));
     }
 }
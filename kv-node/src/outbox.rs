@@ -0,0 +1,54 @@
+use dashmap::DashMap;
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+};
+
+//anything queued per peer until the peer acknowledges a GossipBatch carrying it
+pub trait Queued: Clone {
+    fn queue_id(&self) -> String;
+}
+
+//one queue per peer, items stay in it until acked so a failed gossip round sends them again
+#[derive(Debug, Clone)]
+pub struct Outbox<T> {
+    queues: Arc<DashMap<String, VecDeque<T>>>,
+    //a peer that stays down for long enough starts losing the oldest items queued for it
+    capacity: usize,
+}
+
+impl<T: Queued> Outbox<T> {
+    pub fn new(capacity: usize) -> Self {
+        Outbox {
+            queues: Arc::new(DashMap::new()),
+            capacity,
+        }
+    }
+
+    //returns false if the queue was full and the oldest item had to be dropped
+    pub fn push(&self, peer: &str, item: T) -> bool {
+        let mut queue = self.queues.entry(peer.to_string()).or_default();
+        queue.push_back(item);
+        if queue.len() > self.capacity {
+            queue.pop_front();
+            return false;
+        }
+        true
+    }
+
+    //up to `limit` items still waiting to be acknowledged by the peer, oldest first
+    pub fn pending_for(&self, peer: &str, limit: usize) -> Vec<T> {
+        self.queues
+            .get(peer)
+            .map(|queue| queue.iter().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    //the peer acknowledged a GossipBatch carrying these items
+    pub fn ack(&self, peer: &str, delivered: &[T]) {
+        let delivered: HashSet<String> = delivered.iter().map(|item| item.queue_id()).collect();
+        if let Some(mut queue) = self.queues.get_mut(peer) {
+            queue.retain(|item| !delivered.contains(&item.queue_id()));
+        }
+    }
+}
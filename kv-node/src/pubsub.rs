@@ -0,0 +1,180 @@
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{
+    communication::{PubSubMessage, SubscribeRequest},
+    feed::{forward, FEED_CAPACITY},
+    ids::{IdGenerator, SeenIds},
+    outbox::{Outbox, Queued},
+};
+
+//a peer that stays down for long enough starts losing the oldest messages queued for it
+const OUTBOX_CAPACITY: usize = 10_000;
+//how many message ids are remembered to drop redeliveries
+const SEEN_CAPACITY: usize = 10_000;
+
+//Delivery model: the publishing node queues each message once per configured peer and keeps
+//it there until that peer has acknowledged a GossipBatch carrying it. A failed round leaves
+//the message queued and it is simply sent again in the next one. A node that receives a
+//message for the first time queues it in turn for its own peers, except the one it came
+//from, so it reaches every member of a connected cluster, not only the publisher's direct
+//peers, at least once. Receivers remember recently seen ids so a resend does not reach local
+//subscribers twice, nor gets passed on again. A local subscriber that falls too far behind
+//to get every message has its stream ended with DATA_LOSS, see feed.rs, rather than
+//quietly missing some.
+#[derive(Debug, Clone)]
+pub struct PubSub {
+    sender: broadcast::Sender<PubSubMessage>,
+    outbox: Outbox<PubSubMessage>,
+    seen: Arc<Mutex<SeenIds>>,
+    ids: IdGenerator,
+}
+
+impl Default for PubSub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        PubSub {
+            sender,
+            outbox: Outbox::new(OUTBOX_CAPACITY),
+            seen: Arc::new(Mutex::new(SeenIds::new(SEEN_CAPACITY))),
+            ids: IdGenerator::new(),
+        }
+    }
+
+    //delivers to local subscribers and queues the message for every peer
+    pub fn publish(
+        &self,
+        node_id: &str,
+        channel: String,
+        payload: Vec<u8>,
+        peers: impl Iterator<Item = String>,
+    ) -> PubSubMessage {
+        let message = PubSubMessage {
+            id: self.ids.next(node_id),
+            channel,
+            payload,
+            origin: node_id.to_string(),
+        };
+
+        self.deliver(message.clone());
+        self.queue(&message, peers);
+        message
+    }
+
+    //a message gossiped by a peer, possibly one we have already delivered. `peers` are the
+    //ones to pass it on to, without the one it came from
+    pub fn receive(&self, message: PubSubMessage, peers: impl Iterator<Item = String>) {
+        if self.deliver(message.clone()) {
+            self.queue(&message, peers);
+        }
+    }
+
+    //up to `limit` messages still waiting to be acknowledged by the peer, oldest first
+    pub fn pending_for(&self, peer: &str, limit: usize) -> Vec<PubSubMessage> {
+        self.outbox.pending_for(peer, limit)
+    }
+
+    //the peer acknowledged a GossipBatch carrying these messages
+    pub fn ack(&self, peer: &str, delivered: &[PubSubMessage]) {
+        self.outbox.ack(peer, delivered);
+    }
+
+    pub fn subscribe(
+        &self,
+        request: SubscribeRequest,
+    ) -> ReceiverStream<Result<PubSubMessage, tonic::Status>> {
+        forward(&self.sender, move |message: &PubSubMessage| {
+            request.channels.contains(&message.channel)
+        })
+    }
+
+    //false if the message was delivered before
+    fn deliver(&self, message: PubSubMessage) -> bool {
+        let first_time = self
+            .seen
+            .lock()
+            .expect("pub/sub seen ids lock poisoned")
+            .insert(&message.id);
+        if first_time {
+            let _ = self.sender.send(message);
+        }
+        first_time
+    }
+
+    fn queue(&self, message: &PubSubMessage, peers: impl Iterator<Item = String>) {
+        for peer in peers {
+            if !self.outbox.push(&peer, message.clone()) {
+                println!(
+                    "pub/sub outbox for {} is full, dropped the oldest message",
+                    peer
+                );
+            }
+        }
+    }
+}
+
+impl Queued for PubSubMessage {
+    fn queue_id(&self) -> String {
+        self.id.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    fn peers(addrs: &[&str]) -> impl Iterator<Item = String> {
+        addrs
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    //one gossip round from `from` to `to`, which passes messages on to `onward`
+    fn gossip(from: &PubSub, to: &PubSub, to_addr: &str, onward: &[&str]) {
+        let messages = from.pending_for(to_addr, 100);
+        for message in messages.clone() {
+            to.receive(message, peers(onward));
+        }
+        from.ack(to_addr, &messages);
+    }
+
+    #[tokio::test]
+    async fn messages_reach_nodes_that_are_not_peers_of_the_publisher() {
+        //a - b - c, a and c do not know each other
+        let (a, b, c) = (PubSub::new(), PubSub::new(), PubSub::new());
+        let mut on_c = c.subscribe(SubscribeRequest {
+            channels: vec![String::from("news")],
+        });
+
+        let published = a.publish("node_a", String::from("news"), vec![1], peers(&["b"]));
+        gossip(&a, &b, "b", &["c"]);
+        gossip(&b, &c, "c", &[]);
+
+        let received = on_c.next().await.unwrap().unwrap();
+        assert_eq!(received.id, published.id);
+        assert!(a.pending_for("b", 100).is_empty());
+        assert!(b.pending_for("c", 100).is_empty());
+    }
+
+    #[tokio::test]
+    async fn redeliveries_are_not_passed_on_again() {
+        let (a, b) = (PubSub::new(), PubSub::new());
+        let published = a.publish("node_a", String::from("news"), vec![1], peers(&["b"]));
+
+        b.receive(published.clone(), peers(&["c"]));
+        b.ack("c", &b.pending_for("c", 100));
+        //a resend from a, e.g. after its round failed before the ack
+        b.receive(published, peers(&["c"]));
+        assert!(b.pending_for("c", 100).is_empty());
+    }
+}
@@ -0,0 +1,234 @@
+use dashmap::DashMap;
+use std::{collections::HashSet, sync::Arc};
+
+use crate::{communication::Retirement as RetirementMessage, network::CRDTValue};
+
+//Retirement of node ids that are gone for good, e.g. the ids of nodes started in interactive
+//mode or of containers that were replaced, so counters stop carrying an entry for each.
+//
+//An operator retires an id on the node whose entries are to absorb its counts, the survivor.
+//The retirement is pending at first: the survivor sends it to its peers with every gossip
+//round, and each peer answers with its ack and every counter it holds that still has entries
+//of the retired id. Once all of its peers acked, the survivor has seen everything the retired
+//id counted, so it folds those counts into its own entries and marks the retirement folded.
+//Folded retirements are gossiped in every round, and every node that learns about one
+//forgets the retired id in the counters it holds and in every counter it receives from then
+//on, which keeps stale copies from bringing the counts back on top of the folded ones.
+//
+//The survivor has to have every live node of the cluster as a peer. Two retirements of the
+//same id with different survivors settle on the lower survivor id, and nodes only ack the one
+//they settled on, so only one of them can ever be folded. Counters nested in maps and
+//documents, bounded counters and the version vectors of stored values keep their entries.
+#[derive(Debug, Clone, Default)]
+pub struct Retirements {
+    //by retired node id
+    known: Arc<DashMap<String, Retirement>>,
+}
+
+#[derive(Debug, Clone)]
+struct Retirement {
+    survivor: String,
+    folded: bool,
+    //peers that acked, only tracked by the survivor
+    acked_by: HashSet<String>,
+}
+
+impl Retirements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //starts retiring `retired` with this node as the survivor
+    pub fn propose(&self, retired: &str, survivor: &str) -> Result<(), String> {
+        if retired == survivor {
+            return Err(String::from("a node cannot retire its own id"));
+        }
+        let current = self
+            .known
+            .entry(retired.to_string())
+            .or_insert_with(|| Retirement {
+                survivor: survivor.to_string(),
+                folded: false,
+                acked_by: HashSet::new(),
+            });
+        match current.survivor == survivor {
+            true => Ok(()),
+            false => Err(format!(
+                "{} is already being retired into {}",
+                retired, current.survivor
+            )),
+        }
+    }
+
+    pub fn to_gossip(&self) -> Vec<RetirementMessage> {
+        self.known
+            .iter()
+            .map(|entry| RetirementMessage {
+                retired: entry.key().clone(),
+                survivor: entry.survivor.clone(),
+                folded: entry.folded,
+            })
+            .collect()
+    }
+
+    //merges retirements gossiped by a peer, returns the ids that are folded now and were
+    //not before, their entries have to be forgotten in the local counters
+    pub fn learn(&self, messages: &[RetirementMessage]) -> Vec<String> {
+        let mut newly_folded = Vec::new();
+        for message in messages {
+            let mut current = self
+                .known
+                .entry(message.retired.clone())
+                .or_insert_with(|| Retirement {
+                    survivor: message.survivor.clone(),
+                    folded: false,
+                    acked_by: HashSet::new(),
+                });
+            //a folded one is the only one that can have been folded, otherwise the lower
+            //survivor wins
+            let replace = match (current.folded, message.folded) {
+                (false, true) => true,
+                (false, false) => message.survivor < current.survivor,
+                (true, _) => false,
+            };
+            if replace {
+                current.survivor = message.survivor.clone();
+                current.acked_by.clear();
+            }
+            if message.folded && !current.folded {
+                current.folded = true;
+                newly_folded.push(message.retired.clone());
+            }
+        }
+        newly_folded
+    }
+
+    //pending retirements of the request this node agrees to, it never agrees to its own
+    pub fn acks_for(&self, messages: &[RetirementMessage], node_id: &str) -> Vec<String> {
+        messages
+            .iter()
+            .filter(|message| !message.folded && message.retired != node_id)
+            .filter(|message| {
+                self.known
+                    .get(&message.retired)
+                    .is_some_and(|current| !current.folded && current.survivor == message.survivor)
+            })
+            .map(|message| message.retired.clone())
+            .collect()
+    }
+
+    pub fn record_ack(&self, retired: &str, survivor: &str, peer_addr: &str) {
+        if let Some(mut current) = self.known.get_mut(retired) {
+            if !current.folded && current.survivor == survivor {
+                current.acked_by.insert(peer_addr.to_string());
+            }
+        }
+    }
+
+    //pending retirements into `survivor` that every one of `peers` acked
+    pub fn ready_to_fold(&self, survivor: &str, peers: &[String]) -> Vec<String> {
+        self.known
+            .iter()
+            .filter(|entry| !entry.folded && entry.survivor == survivor)
+            .filter(|entry| peers.iter().all(|peer| entry.acked_by.contains(peer)))
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    pub fn mark_folded(&self, retired: &str) {
+        if let Some(mut current) = self.known.get_mut(retired) {
+            current.folded = true;
+        }
+    }
+
+    pub fn folded(&self) -> Vec<String> {
+        self.known
+            .iter()
+            .filter(|entry| entry.folded)
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+}
+
+//drops the entries of `retired` from a counter, false if it had none or is not a counter
+pub fn forget(value: &mut CRDTValue, retired: &str) -> bool {
+    match value {
+        CRDTValue::Counter(counter) => counter.forget(retired),
+        CRDTValue::GCounter(counter) => counter.forget(retired),
+        CRDTValue::WCounter(counter) => counter.forget(retired),
+        _ => false,
+    }
+}
+
+//folds the entries of `retired` into the ones of `survivor`, false if there were none
+pub fn fold(value: &mut CRDTValue, retired: &str, survivor: &str) -> bool {
+    match value {
+        CRDTValue::Counter(counter) => counter.retire(retired, survivor),
+        CRDTValue::GCounter(counter) => counter.retire(retired, survivor),
+        CRDTValue::WCounter(counter) => counter.retire(retired, survivor),
+        _ => false,
+    }
+}
+
+//true if the value is a counter with entries of `retired`
+pub fn has_entries_of(value: &CRDTValue, retired: &str) -> bool {
+    match value {
+        CRDTValue::Counter(counter) => {
+            counter.p.entries.contains_key(retired) || counter.n.entries.contains_key(retired)
+        }
+        CRDTValue::GCounter(counter) => counter.counts.entries.contains_key(retired),
+        CRDTValue::WCounter(counter) => {
+            counter.p.contains_key(retired) || counter.n.contains_key(retired)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(retired: &str, survivor: &str, folded: bool) -> RetirementMessage {
+        RetirementMessage {
+            retired: retired.to_string(),
+            survivor: survivor.to_string(),
+            folded,
+        }
+    }
+
+    #[test]
+    fn concurrent_retirements_settle_on_one_survivor() {
+        let on_b = Retirements::new();
+        on_b.propose("tmp_1", "node_b").unwrap();
+        assert!(on_b.propose("tmp_1", "node_c").is_err());
+
+        //node_a's retirement of the same id wins, node_b only acks that one from now on
+        on_b.learn(&[message("tmp_1", "node_a", false)]);
+        let requests = [
+            message("tmp_1", "node_a", false),
+            message("tmp_1", "node_b", false),
+        ];
+        assert_eq!(on_b.acks_for(&requests, "node_b"), vec!["tmp_1"]);
+        assert!(on_b.acks_for(&requests[1..], "node_b").is_empty());
+        //nor does anyone ack their own retirement
+        assert!(on_b.acks_for(&requests, "tmp_1").is_empty());
+    }
+
+    #[test]
+    fn folds_once_every_peer_acked() {
+        let on_a = Retirements::new();
+        on_a.propose("tmp_1", "node_a").unwrap();
+        let peers = vec![String::from("10.0.0.2:8000"), String::from("10.0.0.3:8000")];
+
+        on_a.record_ack("tmp_1", "node_a", &peers[0]);
+        assert!(on_a.ready_to_fold("node_a", &peers).is_empty());
+        on_a.record_ack("tmp_1", "node_a", &peers[1]);
+        assert_eq!(on_a.ready_to_fold("node_a", &peers), vec!["tmp_1"]);
+        on_a.mark_folded("tmp_1");
+
+        let on_b = Retirements::new();
+        assert_eq!(on_b.learn(&on_a.to_gossip()), vec!["tmp_1"]);
+        assert!(on_b.learn(&on_a.to_gossip()).is_empty());
+        assert_eq!(on_b.folded(), vec!["tmp_1"]);
+    }
+}
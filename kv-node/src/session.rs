@@ -0,0 +1,112 @@
+use dashmap::DashMap;
+use kv_types::causal::VersionVector;
+use std::{collections::HashMap, time::Duration};
+use tokio::time::Instant;
+
+use crate::{
+    communication::{SessionToken, VersionVector as VersionVectorMessage},
+    network::StoredValue,
+};
+
+const SESSION_POLL: Duration = Duration::from_millis(50);
+
+//Session guarantees (read-your-writes and monotonic reads).
+//Every stored value has a version vector counting the writes each node made to it, joined on
+//merge. Responses hand the versions of the keys they touched back to the client, which joins
+//them into its session token and sends the entry for a key along with every read of it. A
+//node whose copy of the key is older than that waits up to session_wait_ms of the config
+//for gossip to catch up and otherwise rejects the read with UNAVAILABLE, never answers it
+//with the older copy, so the client can retry or fail over to another node.
+
+pub fn token_for(key: &str, version: &VersionVector) -> SessionToken {
+    SessionToken {
+        keys: HashMap::from([(
+            key.to_string(),
+            VersionVectorMessage {
+                entries: version.entries.clone(),
+            },
+        )]),
+    }
+}
+
+//resolves once this node's copy of `key` has everything the session has seen of it, fails
+//with UNAVAILABLE if that takes longer than `wait`
+pub async fn wait_until_seen(
+    store: &DashMap<String, StoredValue>,
+    key: &str,
+    session: Option<&SessionToken>,
+    wait: Duration,
+) -> Result<(), tonic::Status> {
+    let Some(seen) = session.and_then(|s| s.keys.get(key)) else {
+        return Ok(()); //nothing seen yet, any version will do
+    };
+    let seen = VersionVector::from(seen.entries.clone());
+
+    let deadline = Instant::now() + wait;
+    loop {
+        let caught_up = store
+            .get(key)
+            .is_some_and(|stored| stored.version.dominates(&seen));
+        if caught_up {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(tonic::Status::unavailable(format!(
+                "this node has not caught up with your session on {} yet, retry or use another node",
+                key
+            )));
+        }
+        tokio::time::sleep(SESSION_POLL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kv_types::pn_counter::PNCounter;
+    use std::time::SystemTime;
+
+    use crate::network::CRDTValue;
+
+    fn stored(writes: u64) -> StoredValue {
+        let mut version = VersionVector::new();
+        version.advance("node_1", writes);
+        StoredValue {
+            data: CRDTValue::Counter(PNCounter::new(String::from("node_1"), 1, 0)),
+            last_updated: SystemTime::now(),
+            version,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_node_behind_the_session_refuses_the_read_as_unavailable() {
+        let store = DashMap::from_iter([(String::from("k"), stored(1))]);
+        let mut seen = VersionVector::new();
+        seen.advance("node_1", 2);
+        let session = token_for("k", &seen);
+
+        let waited = wait_until_seen(&store, "k", Some(&session), Duration::from_millis(100));
+        assert_eq!(waited.await.unwrap_err().code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn the_read_goes_ahead_once_gossip_caught_up() {
+        let store = DashMap::from_iter([(String::from("k"), stored(1))]);
+        let mut seen = VersionVector::new();
+        seen.advance("node_1", 2);
+        let session = token_for("k", &seen);
+
+        let (waited, _) = tokio::join!(
+            wait_until_seen(&store, "k", Some(&session), Duration::from_secs(5)),
+            async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                store.insert(String::from("k"), stored(2));
+            }
+        );
+        assert!(waited.is_ok());
+        //nor does a read without a session wait for anything
+        assert!(wait_until_seen(&store, "x", None, Duration::ZERO)
+            .await
+            .is_ok());
+    }
+}
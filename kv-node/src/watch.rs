@@ -0,0 +1,80 @@
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{
+    communication::{ChangeOrigin, WatchEvent, WatchRequest},
+    feed::{forward, FEED_CAPACITY},
+};
+
+//every local write and every remote merge that changes a value is pushed into the feed,
+//each Watch rpc gets its own receiver and only forwards the keys it asked for
+#[derive(Debug, Clone)]
+pub struct ChangeFeed {
+    sender: broadcast::Sender<WatchEvent>,
+}
+
+impl Default for ChangeFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeFeed {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        ChangeFeed { sender }
+    }
+
+    pub fn notify(&self, key: &str, valuetype: &str, value: Vec<u8>, origin: ChangeOrigin) {
+        //nobody watching is not an error, the event is simply dropped
+        let _ = self.sender.send(WatchEvent {
+            key: key.to_string(),
+            valuetype: valuetype.to_string(),
+            value,
+            origin: origin as i32,
+        });
+    }
+
+    pub fn subscribe(
+        &self,
+        filter: WatchRequest,
+    ) -> ReceiverStream<Result<WatchEvent, tonic::Status>> {
+        forward(&self.sender, move |event: &WatchEvent| {
+            matches(&filter, &event.key)
+        })
+    }
+}
+
+fn matches(filter: &WatchRequest, key: &str) -> bool {
+    filter.keys.iter().any(|k| k == key) || filter.prefixes.iter().any(|p| key.starts_with(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    fn filter(keys: &[&str], prefixes: &[&str]) -> WatchRequest {
+        WatchRequest {
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+            prefixes: prefixes.iter().map(|prefix| prefix.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_watched_keys_and_prefixes_come_through() {
+        let feed = ChangeFeed::new();
+        let mut events = feed.subscribe(filter(&["views"], &["user:"]));
+
+        feed.notify("other", "COUNTER", vec![1], ChangeOrigin::Local);
+        feed.notify("views", "COUNTER", vec![2], ChangeOrigin::Local);
+        feed.notify("user:1", "MAP", vec![3], ChangeOrigin::Gossip);
+
+        let first = events.next().await.unwrap().unwrap();
+        assert_eq!((first.key.as_str(), first.value), ("views", vec![2]));
+        assert_eq!(first.origin, ChangeOrigin::Local as i32);
+        let second = events.next().await.unwrap().unwrap();
+        assert_eq!(second.key, "user:1");
+        assert_eq!(second.origin, ChangeOrigin::Gossip as i32);
+    }
+}
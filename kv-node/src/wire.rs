@@ -0,0 +1,777 @@
+use kv_types::{
+    bounded_counter::BoundedCounter,
+    causal::{Dot, DotContext},
+    codec::{self, DecodeError},
+    flag::{DisableWinsFlag, EnableWinsFlag},
+    g_counter::GCounter,
+    hyperloglog::HyperLogLog,
+    json_doc::{Json, JsonDoc},
+    lww_register::LwwRegister,
+    lww_set::LwwSet,
+    max_min_register::{MaxRegister, MinRegister, Ranked},
+    or_map::{MapEntry, ORMap},
+    or_set::ORSet,
+    pn_counter::PNCounter,
+    rga::{Element, ElementId, Rga},
+    sorted_set::SortedSet,
+    text::{Block, Text},
+    two_phase_set::TwoPhaseSet,
+    wide_counter::WideCounter,
+    CrdtValue, Scalar,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{
+    communication::{
+        crdt_value_message, scalar_message, stored_value_message, BoundedCounterMessage,
+        BytesBoundMessage, CrdtValueMessage, Dot as DotMessage, DotSet,
+        ElementId as ElementIdMessage, EncodedValue, FlagMessage, GCounterMessage,
+        HyperLogLogMessage, IntBoundMessage, ListElement, ListMessage, LwwRegisterMessage,
+        LwwSetMessage, LwwStamp, OrMapEntry, OrMapMessage, OrMapSibling, OrSetMessage,
+        PnCounterMessage, ScalarMessage, StoredValueMessage, TextBlock, TextMessage, TransferMap,
+        TwoPhaseSetMessage, Uint128, WideCounterMessage,
+    },
+    network::CRDTValue,
+};
+
+//Values are gossiped in the binary encoding of kv-types, as an EncodedValue. The messages
+//below are the form they were gossiped in before it, still sent to peers that do not know
+//"encoded" and read from them, and the conversions between those and the value types.
+
+// convert domain -> proto for sending
+impl From<PNCounter> for PnCounterMessage {
+    fn from(domain: PNCounter) -> Self {
+        Self {
+            p: domain.p.entries,
+            n: domain.n.entries,
+        }
+    }
+}
+
+// convert proto -> domain for receiving
+impl From<PnCounterMessage> for PNCounter {
+    fn from(wire: PnCounterMessage) -> Self {
+        Self {
+            p: wire.p.into(),
+            n: wire.n.into(),
+        }
+    }
+}
+
+impl From<GCounter> for GCounterMessage {
+    fn from(domain: GCounter) -> Self {
+        Self {
+            counts: domain.counts.entries,
+        }
+    }
+}
+
+impl From<GCounterMessage> for GCounter {
+    fn from(wire: GCounterMessage) -> Self {
+        Self {
+            counts: wire.counts.into(),
+        }
+    }
+}
+
+impl From<u128> for Uint128 {
+    fn from(n: u128) -> Self {
+        Self {
+            high: (n >> 64) as u64,
+            low: n as u64,
+        }
+    }
+}
+
+impl From<Uint128> for u128 {
+    fn from(wire: Uint128) -> Self {
+        (u128::from(wire.high) << 64) | u128::from(wire.low)
+    }
+}
+
+impl From<WideCounter> for WideCounterMessage {
+    fn from(domain: WideCounter) -> Self {
+        let half = |entries: HashMap<String, u128>| {
+            entries
+                .into_iter()
+                .map(|(node, n)| (node, n.into()))
+                .collect()
+        };
+        Self {
+            p: half(domain.p),
+            n: half(domain.n),
+        }
+    }
+}
+
+impl From<WideCounterMessage> for WideCounter {
+    fn from(wire: WideCounterMessage) -> Self {
+        let half = |entries: HashMap<String, Uint128>| {
+            entries
+                .into_iter()
+                .map(|(node, n)| (node, n.into()))
+                .collect()
+        };
+        Self {
+            p: half(wire.p),
+            n: half(wire.n),
+        }
+    }
+}
+
+impl From<BoundedCounter> for BoundedCounterMessage {
+    fn from(domain: BoundedCounter) -> Self {
+        Self {
+            p: domain.p.entries,
+            n: domain.n.entries,
+            transfers: domain
+                .transfers
+                .into_iter()
+                .map(|(from, to)| (from, TransferMap { to: to.entries }))
+                .collect(),
+        }
+    }
+}
+
+impl From<BoundedCounterMessage> for BoundedCounter {
+    fn from(wire: BoundedCounterMessage) -> Self {
+        Self {
+            p: wire.p.into(),
+            n: wire.n.into(),
+            transfers: wire
+                .transfers
+                .into_iter()
+                .map(|(from, moved)| (from, moved.to.into()))
+                .collect(),
+        }
+    }
+}
+
+impl From<HyperLogLog> for HyperLogLogMessage {
+    fn from(domain: HyperLogLog) -> Self {
+        Self {
+            registers: domain.registers,
+        }
+    }
+}
+
+impl From<HyperLogLogMessage> for HyperLogLog {
+    //a sketch of another size can only come from a node with another precision, it is
+    //dropped rather than merged register by register
+    fn from(wire: HyperLogLogMessage) -> Self {
+        HyperLogLog::from_registers(wire.registers).unwrap_or_default()
+    }
+}
+
+impl From<Ranked> for BytesBoundMessage {
+    fn from(domain: Ranked) -> Self {
+        Self {
+            value: domain.value,
+            payload: domain.payload,
+        }
+    }
+}
+
+impl From<BytesBoundMessage> for Ranked {
+    fn from(wire: BytesBoundMessage) -> Self {
+        Self {
+            value: wire.value,
+            payload: wire.payload,
+        }
+    }
+}
+
+impl From<Scalar> for ScalarMessage {
+    fn from(domain: Scalar) -> Self {
+        let value = match domain {
+            Scalar::Null => scalar_message::Value::Null(true),
+            Scalar::Bool(b) => scalar_message::Value::BoolValue(b),
+            Scalar::Int(n) => scalar_message::Value::IntValue(n),
+            Scalar::Float(f) => scalar_message::Value::FloatValue(f),
+            Scalar::String(s) => scalar_message::Value::StringValue(s),
+        };
+        Self { value: Some(value) }
+    }
+}
+
+impl From<ScalarMessage> for Scalar {
+    fn from(wire: ScalarMessage) -> Self {
+        match wire.value {
+            None | Some(scalar_message::Value::Null(_)) => Scalar::Null,
+            Some(scalar_message::Value::BoolValue(b)) => Scalar::Bool(b),
+            Some(scalar_message::Value::IntValue(n)) => Scalar::Int(n),
+            Some(scalar_message::Value::FloatValue(f)) => Scalar::Float(f),
+            Some(scalar_message::Value::StringValue(s)) => Scalar::String(s),
+        }
+    }
+}
+
+impl From<LwwRegister<Scalar>> for LwwRegisterMessage {
+    fn from(domain: LwwRegister<Scalar>) -> Self {
+        Self {
+            value: Some(domain.value.into()),
+            timestamp: domain.timestamp,
+            node_id: domain.node_id,
+        }
+    }
+}
+
+impl From<LwwRegisterMessage> for LwwRegister<Scalar> {
+    fn from(wire: LwwRegisterMessage) -> Self {
+        Self {
+            value: wire.value.map(Scalar::from).unwrap_or(Scalar::Null),
+            timestamp: wire.timestamp,
+            node_id: wire.node_id,
+        }
+    }
+}
+
+fn element_id_message((counter, node_id): ElementId) -> ElementIdMessage {
+    ElementIdMessage { counter, node_id }
+}
+
+impl From<Rga<CrdtValue>> for ListMessage {
+    fn from(domain: Rga<CrdtValue>) -> Self {
+        Self {
+            elements: domain
+                .elements
+                .into_iter()
+                .map(|(id, element)| ListElement {
+                    id: Some(element_id_message(id)),
+                    origin: element.origin.map(element_id_message),
+                    value: element.value.map(CrdtValueMessage::from),
+                    moved_to: element.moved_to.map(element_id_message),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<ListMessage> for Rga<CrdtValue> {
+    fn from(wire: ListMessage) -> Self {
+        Self {
+            elements: wire
+                .elements
+                .into_iter()
+                .filter_map(|element| {
+                    let id = element.id?;
+                    let element = Element {
+                        origin: element.origin.map(|o| (o.counter, o.node_id)),
+                        //a value of a type this node does not know reads as deleted
+                        value: element.value.and_then(|v| v.value).map(CrdtValue::from),
+                        moved_to: element.moved_to.map(|m| (m.counter, m.node_id)),
+                    };
+                    Some(((id.counter, id.node_id), element))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<Text> for TextMessage {
+    fn from(domain: Text) -> Self {
+        Self {
+            blocks: domain
+                .blocks
+                .into_iter()
+                .flat_map(|(node_id, blocks)| {
+                    blocks.into_iter().map(move |(counter, block)| TextBlock {
+                        id: Some(element_id_message((counter, node_id.clone()))),
+                        origin: block.origin.map(element_id_message),
+                        text: block.text,
+                        deleted: block.deleted,
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<TextMessage> for Text {
+    fn from(wire: TextMessage) -> Self {
+        let mut text = Text::new();
+        for block in wire.blocks {
+            let Some(id) = block.id else {
+                continue;
+            };
+            let origin = block.origin.map(|o| (o.counter, o.node_id));
+            text.blocks.entry(id.node_id).or_default().insert(
+                id.counter,
+                Block {
+                    origin,
+                    text: block.text,
+                    deleted: block.deleted,
+                },
+            );
+        }
+        text
+    }
+}
+
+fn dots_message(dots: HashSet<Dot>) -> Vec<DotMessage> {
+    dots.into_iter()
+        .map(|(node_id, counter)| DotMessage { node_id, counter })
+        .collect()
+}
+
+fn dots_domain(dots: Vec<DotMessage>) -> HashSet<Dot> {
+    dots.into_iter()
+        .map(|dot| (dot.node_id, dot.counter))
+        .collect()
+}
+
+impl From<ORSet<String>> for OrSetMessage {
+    fn from(domain: ORSet<String>) -> Self {
+        Self {
+            entries: domain
+                .entries
+                .into_iter()
+                .map(|(element, dots)| {
+                    let dots = dots_message(dots);
+                    (element, DotSet { dots })
+                })
+                .collect(),
+            context: domain.context.clock.entries,
+            context_cloud: dots_message(domain.context.cloud),
+        }
+    }
+}
+
+impl From<OrSetMessage> for ORSet<String> {
+    fn from(wire: OrSetMessage) -> Self {
+        Self {
+            entries: wire
+                .entries
+                .into_iter()
+                .map(|(element, dots)| (element, dots_domain(dots.dots)))
+                .collect(),
+            context: DotContext {
+                clock: wire.context.into(),
+                cloud: dots_domain(wire.context_cloud),
+            },
+        }
+    }
+}
+
+impl From<TwoPhaseSet<String>> for TwoPhaseSetMessage {
+    fn from(domain: TwoPhaseSet<String>) -> Self {
+        Self {
+            added: domain.added.into_iter().collect(),
+            removed: domain.removed.into_iter().collect(),
+        }
+    }
+}
+
+impl From<TwoPhaseSetMessage> for TwoPhaseSet<String> {
+    fn from(wire: TwoPhaseSetMessage) -> Self {
+        Self {
+            added: wire.added.into_iter().collect(),
+            removed: wire.removed.into_iter().collect(),
+        }
+    }
+}
+
+impl From<LwwSet<String>> for LwwSetMessage {
+    fn from(domain: LwwSet<String>) -> Self {
+        let stamps = |stamps: HashMap<String, (u64, String)>| {
+            stamps
+                .into_iter()
+                .map(|(element, (timestamp, node_id))| (element, LwwStamp { timestamp, node_id }))
+                .collect()
+        };
+        Self {
+            adds: stamps(domain.adds),
+            removes: stamps(domain.removes),
+        }
+    }
+}
+
+impl From<LwwSetMessage> for LwwSet<String> {
+    fn from(wire: LwwSetMessage) -> Self {
+        let stamps = |stamps: HashMap<String, LwwStamp>| {
+            stamps
+                .into_iter()
+                .map(|(element, stamp)| (element, (stamp.timestamp, stamp.node_id)))
+                .collect()
+        };
+        Self {
+            adds: stamps(wire.adds),
+            removes: stamps(wire.removes),
+        }
+    }
+}
+
+impl From<ORSet<bool>> for FlagMessage {
+    fn from(mut domain: ORSet<bool>) -> Self {
+        let mut dots_of = |value| dots_message(domain.entries.remove(&value).unwrap_or_default());
+        Self {
+            enabled: dots_of(true),
+            disabled: dots_of(false),
+            context: domain.context.clock.entries,
+            context_cloud: dots_message(domain.context.cloud),
+        }
+    }
+}
+
+impl From<FlagMessage> for ORSet<bool> {
+    fn from(wire: FlagMessage) -> Self {
+        let entries = [(true, wire.enabled), (false, wire.disabled)]
+            .into_iter()
+            .filter(|(_, dots)| !dots.is_empty())
+            .map(|(value, dots)| (value, dots_domain(dots)))
+            .collect();
+        Self {
+            entries,
+            context: DotContext {
+                clock: wire.context.into(),
+                cloud: dots_domain(wire.context_cloud),
+            },
+        }
+    }
+}
+
+impl From<ORMap> for OrMapMessage {
+    fn from(domain: ORMap) -> Self {
+        Self {
+            entries: domain
+                .entries
+                .into_iter()
+                .map(|(field, entry)| {
+                    let entry = OrMapEntry {
+                        dots: dots_message(entry.dots),
+                        value: Some(CrdtValueMessage::from(entry.value)),
+                        siblings: entry
+                            .siblings
+                            .into_iter()
+                            .map(|((node_id, counter), value)| OrMapSibling {
+                                dot: Some(DotMessage { node_id, counter }),
+                                value: Some(CrdtValueMessage::from(value)),
+                            })
+                            .collect(),
+                    };
+                    (field, entry)
+                })
+                .collect(),
+            context: domain.context.clock.entries,
+            context_cloud: dots_message(domain.context.cloud),
+        }
+    }
+}
+
+impl From<OrMapMessage> for ORMap {
+    fn from(wire: OrMapMessage) -> Self {
+        Self {
+            entries: wire
+                .entries
+                .into_iter()
+                .filter_map(|(field, entry)| {
+                    let siblings: BTreeMap<Dot, CrdtValue> = entry
+                        .siblings
+                        .into_iter()
+                        .filter_map(|sibling| {
+                            let dot = sibling.dot?;
+                            let value = sibling.value?.value?;
+                            Some(((dot.node_id, dot.counter), CrdtValue::from(value)))
+                        })
+                        .collect();
+                    if !siblings.is_empty() {
+                        return Some((field, MapEntry::from_siblings(siblings)?));
+                    }
+                    //a field without a value can only come from a newer node's type, skip it
+                    let value = entry.value?.value?;
+                    let entry = MapEntry {
+                        dots: dots_domain(entry.dots),
+                        value: CrdtValue::from(value),
+                        siblings: BTreeMap::new(),
+                    };
+                    Some((field, entry))
+                })
+                .collect(),
+            context: DotContext {
+                clock: wire.context.into(),
+                cloud: dots_domain(wire.context_cloud),
+            },
+        }
+    }
+}
+
+impl From<CrdtValue> for CrdtValueMessage {
+    fn from(domain: CrdtValue) -> Self {
+        let value = match domain {
+            CrdtValue::Counter(counter) => crdt_value_message::Value::Counter(counter.into()),
+            CrdtValue::GrowOnlyCounter(counter) => {
+                crdt_value_message::Value::GCounter(counter.into())
+            }
+            CrdtValue::BoundedCounter(counter) => {
+                crdt_value_message::Value::BoundedCounter(counter.into())
+            }
+            CrdtValue::Register(register) => crdt_value_message::Value::Register(register.into()),
+            CrdtValue::Set(set) => crdt_value_message::Value::Set(set.into()),
+            CrdtValue::Map(map) => crdt_value_message::Value::Map(map.into()),
+            CrdtValue::List(list) => crdt_value_message::Value::List(list.into()),
+        };
+        Self { value: Some(value) }
+    }
+}
+
+impl From<crdt_value_message::Value> for CrdtValue {
+    fn from(wire: crdt_value_message::Value) -> Self {
+        match wire {
+            crdt_value_message::Value::Counter(counter) => CrdtValue::Counter(counter.into()),
+            crdt_value_message::Value::GCounter(counter) => {
+                CrdtValue::GrowOnlyCounter(counter.into())
+            }
+            crdt_value_message::Value::BoundedCounter(counter) => {
+                CrdtValue::BoundedCounter(counter.into())
+            }
+            crdt_value_message::Value::Register(register) => CrdtValue::Register(register.into()),
+            crdt_value_message::Value::Set(set) => CrdtValue::Set(set.into()),
+            crdt_value_message::Value::Map(map) => CrdtValue::Map(map.into()),
+            crdt_value_message::Value::List(list) => CrdtValue::List(list.into()),
+        }
+    }
+}
+
+//the value in the form it is gossiped in
+pub fn encoded(value: &CRDTValue) -> stored_value_message::Value {
+    stored_value_message::Value::Encoded(EncodedValue {
+        value_type: value_type(value).to_string(),
+        state: codec::encode(value),
+    })
+}
+
+//the message as a peer that does not know "encoded" reads it
+pub fn unencoded(message: StoredValueMessage) -> Result<StoredValueMessage, DecodeError> {
+    let value = match message.value {
+        Some(stored_value_message::Value::Encoded(encoded)) => {
+            let value: CRDTValue = codec::decode(&encoded.state)?;
+            Some(value.into())
+        }
+        other => other,
+    };
+    Ok(StoredValueMessage {
+        value,
+        version: message.version,
+    })
+}
+
+//name of the StoredValueMessage field the value goes in without the encoding
+fn value_type(value: &CRDTValue) -> &'static str {
+    match value {
+        CRDTValue::Counter(_) => "counter",
+        CRDTValue::GCounter(_) => "g_counter",
+        CRDTValue::BCounter(_) => "bounded_counter",
+        CRDTValue::WCounter(_) => "wide_counter",
+        CRDTValue::Map(_) => "map",
+        CRDTValue::Doc(_) => "doc",
+        CRDTValue::List(_) => "list",
+        CRDTValue::Text(_) => "text",
+        CRDTValue::ZSet(_) => "zset",
+        CRDTValue::Hll(_) => "hll",
+        CRDTValue::MaxInt(_) => "max_int",
+        CRDTValue::MinInt(_) => "min_int",
+        CRDTValue::MaxBytes(_) => "max_bytes",
+        CRDTValue::MinBytes(_) => "min_bytes",
+        CRDTValue::EwFlag(_) => "enable_wins_flag",
+        CRDTValue::DwFlag(_) => "disable_wins_flag",
+        CRDTValue::ASet(_) => "set",
+        CRDTValue::TwoPhaseSet(_) => "two_phase_set",
+        CRDTValue::LwwSet(_) => "lww_set",
+    }
+}
+
+impl From<CRDTValue> for stored_value_message::Value {
+    fn from(domain: CRDTValue) -> Self {
+        match domain {
+            CRDTValue::Counter(counter) => stored_value_message::Value::Counter(counter.into()),
+            CRDTValue::GCounter(counter) => stored_value_message::Value::GCounter(counter.into()),
+            CRDTValue::BCounter(counter) => {
+                stored_value_message::Value::BoundedCounter(counter.into())
+            }
+            CRDTValue::WCounter(counter) => {
+                stored_value_message::Value::WideCounter(counter.into())
+            }
+            CRDTValue::Map(map) => stored_value_message::Value::Map(map.into()),
+            CRDTValue::Doc(doc) => stored_value_message::Value::Doc(doc.root.into()),
+            CRDTValue::List(list) => stored_value_message::Value::List(list.into()),
+            CRDTValue::Text(text) => stored_value_message::Value::Text(text.into()),
+            CRDTValue::ZSet(zset) => stored_value_message::Value::Zset(zset.members.into()),
+            CRDTValue::Hll(sketch) => stored_value_message::Value::Hll(sketch.into()),
+            CRDTValue::MaxInt(register) => stored_value_message::Value::MaxInt(IntBoundMessage {
+                value: register.value,
+            }),
+            CRDTValue::MinInt(register) => stored_value_message::Value::MinInt(IntBoundMessage {
+                value: register.value,
+            }),
+            CRDTValue::MaxBytes(register) => {
+                stored_value_message::Value::MaxBytes(register.value.into())
+            }
+            CRDTValue::MinBytes(register) => {
+                stored_value_message::Value::MinBytes(register.value.into())
+            }
+            CRDTValue::EwFlag(flag) => {
+                stored_value_message::Value::EnableWinsFlag(flag.state.into())
+            }
+            CRDTValue::DwFlag(flag) => {
+                stored_value_message::Value::DisableWinsFlag(flag.state.into())
+            }
+            CRDTValue::ASet(set) => stored_value_message::Value::Set(set.into()),
+            CRDTValue::TwoPhaseSet(set) => stored_value_message::Value::TwoPhaseSet(set.into()),
+            CRDTValue::LwwSet(set) => stored_value_message::Value::LwwSet(set.into()),
+        }
+    }
+}
+
+impl TryFrom<stored_value_message::Value> for CRDTValue {
+    type Error = DecodeError;
+
+    fn try_from(wire: stored_value_message::Value) -> Result<Self, DecodeError> {
+        let value = match wire {
+            stored_value_message::Value::Encoded(encoded) => return codec::decode(&encoded.state),
+            stored_value_message::Value::Counter(counter) => CRDTValue::Counter(counter.into()),
+            stored_value_message::Value::GCounter(counter) => CRDTValue::GCounter(counter.into()),
+            stored_value_message::Value::WideCounter(counter) => {
+                CRDTValue::WCounter(counter.into())
+            }
+            stored_value_message::Value::BoundedCounter(counter) => {
+                CRDTValue::BCounter(counter.into())
+            }
+            stored_value_message::Value::Map(map) => CRDTValue::Map(map.into()),
+            stored_value_message::Value::Doc(root) => CRDTValue::Doc(JsonDoc { root: root.into() }),
+            stored_value_message::Value::List(list) => CRDTValue::List(list.into()),
+            stored_value_message::Value::Text(text) => CRDTValue::Text(text.into()),
+            stored_value_message::Value::Hll(sketch) => CRDTValue::Hll(sketch.into()),
+            stored_value_message::Value::MaxInt(bound) => {
+                CRDTValue::MaxInt(MaxRegister::new(bound.value))
+            }
+            stored_value_message::Value::MinInt(bound) => {
+                CRDTValue::MinInt(MinRegister::new(bound.value))
+            }
+            stored_value_message::Value::MaxBytes(bound) => {
+                CRDTValue::MaxBytes(MaxRegister::new(bound.into()))
+            }
+            stored_value_message::Value::MinBytes(bound) => {
+                CRDTValue::MinBytes(MinRegister::new(bound.into()))
+            }
+            stored_value_message::Value::EnableWinsFlag(flag) => {
+                CRDTValue::EwFlag(EnableWinsFlag { state: flag.into() })
+            }
+            stored_value_message::Value::DisableWinsFlag(flag) => {
+                CRDTValue::DwFlag(DisableWinsFlag { state: flag.into() })
+            }
+            stored_value_message::Value::Zset(members) => CRDTValue::ZSet(SortedSet {
+                members: members.into(),
+            }),
+            stored_value_message::Value::Set(set) => CRDTValue::ASet(set.into()),
+            stored_value_message::Value::TwoPhaseSet(set) => CRDTValue::TwoPhaseSet(set.into()),
+            stored_value_message::Value::LwwSet(set) => CRDTValue::LwwSet(set.into()),
+        };
+        Ok(value)
+    }
+}
+
+//documents are read and written as JSON text by clients
+pub fn json_to_serde(json: Json) -> serde_json::Value {
+    match json {
+        Json::Scalar(Scalar::Null) => serde_json::Value::Null,
+        Json::Scalar(Scalar::Bool(b)) => b.into(),
+        Json::Scalar(Scalar::Int(n)) => n.into(),
+        Json::Scalar(Scalar::Float(f)) => f.into(),
+        Json::Scalar(Scalar::String(s)) => s.into(),
+        Json::Array(items) => items.into_iter().map(json_to_serde).collect(),
+        Json::Object(fields) => fields
+            .into_iter()
+            .map(|(name, value)| (name, json_to_serde(value)))
+            .collect::<serde_json::Map<String, serde_json::Value>>()
+            .into(),
+    }
+}
+
+//numbers that do not fit an i64 are kept as floats
+pub fn json_from_serde(value: serde_json::Value) -> Json {
+    match value {
+        serde_json::Value::Null => Json::Scalar(Scalar::Null),
+        serde_json::Value::Bool(b) => Json::Scalar(Scalar::Bool(b)),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(n) => Json::Scalar(Scalar::Int(n)),
+            None => Json::Scalar(Scalar::Float(n.as_f64().unwrap_or(f64::NAN))),
+        },
+        serde_json::Value::String(s) => Json::Scalar(Scalar::String(s)),
+        serde_json::Value::Array(items) => {
+            Json::Array(items.into_iter().map(json_from_serde).collect())
+        }
+        serde_json::Value::Object(fields) => Json::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name, json_from_serde(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake;
+
+    fn one_of_each() -> Vec<CRDTValue> {
+        let node = || String::from("node_1");
+        let ranked = Ranked {
+            value: b"v".to_vec(),
+            payload: Vec::new(),
+        };
+        vec![
+            CRDTValue::Counter(PNCounter::new(node(), 3, 1)),
+            CRDTValue::GCounter(GCounter::new(node(), 3)),
+            CRDTValue::BCounter(BoundedCounter::new(node(), 3)),
+            CRDTValue::WCounter(WideCounter::new(node(), -3)),
+            CRDTValue::ASet(ORSet::new()),
+            CRDTValue::TwoPhaseSet(TwoPhaseSet::new()),
+            CRDTValue::LwwSet(LwwSet::new()),
+            CRDTValue::Map(ORMap::new()),
+            CRDTValue::Doc(JsonDoc::new()),
+            CRDTValue::List(Rga::new()),
+            CRDTValue::Text(Text::new()),
+            CRDTValue::ZSet(SortedSet::new()),
+            CRDTValue::Hll(HyperLogLog::new()),
+            CRDTValue::EwFlag(EnableWinsFlag::new()),
+            CRDTValue::DwFlag(DisableWinsFlag::new()),
+            CRDTValue::MaxInt(MaxRegister::new(3)),
+            CRDTValue::MinInt(MinRegister::new(3)),
+            CRDTValue::MaxBytes(MaxRegister::new(ranked.clone())),
+            CRDTValue::MinBytes(MinRegister::new(ranked)),
+        ]
+    }
+
+    #[test]
+    fn encoded_values_name_the_field_they_would_go_in() {
+        for value in one_of_each() {
+            let message = StoredValueMessage {
+                value: Some(encoded(&value)),
+                version: HashMap::new(),
+            };
+            let unencoded = unencoded(message).unwrap().value.unwrap();
+            assert_eq!(value_type(&value), handshake::value_type(&unencoded));
+        }
+    }
+
+    #[test]
+    fn both_forms_read_back_the_same() {
+        for value in one_of_each() {
+            let from_encoded = CRDTValue::try_from(encoded(&value)).unwrap();
+            let message = stored_value_message::Value::from(value.clone());
+            let from_message = CRDTValue::try_from(message).unwrap();
+            assert_eq!(from_encoded.read_bytes(), value.read_bytes());
+            assert_eq!(from_message.read_bytes(), value.read_bytes());
+        }
+
+        let garbage = stored_value_message::Value::Encoded(EncodedValue {
+            value_type: String::from("counter"),
+            state: vec![codec::FORMAT_VERSION + 1],
+        });
+        assert_eq!(
+            CRDTValue::try_from(garbage).unwrap_err(),
+            DecodeError::UnknownVersion(codec::FORMAT_VERSION + 1)
+        );
+    }
+}
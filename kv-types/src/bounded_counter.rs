@@ -0,0 +1,205 @@
+use super::{causal::VersionVector, pn_counter::CounterOverflow, Merge};
+use std::collections::HashMap;
+use std::fmt;
+
+//Escrow counter that can never go below zero, for stock or quota style values.
+//The value is split into rights held by the nodes. An increment gives its amount of rights
+//to the node that made it, a decrement uses up rights of the node making it and is refused
+//if that node does not hold enough, and a node can hand some of its rights to another one.
+//Since every node only spends rights it holds, concurrent decrements on different sides of
+//a partition can never take the total below zero. All three parts only grow, so merging
+//takes the max per entry like PNCounter does:
+//  rights(x) = p[x] + transfers into x - transfers out of x - n[x]
+//  value     = sum(p) - sum(n)
+
+type NodeId = String;
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoundedCounter {
+    pub p: VersionVector,
+    pub n: VersionVector,
+    //from -> to -> total amount of rights ever moved between the two
+    pub transfers: HashMap<NodeId, VersionVector>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsufficientRights {
+    pub available: u64,
+    pub requested: u64,
+}
+
+impl fmt::Display for InsufficientRights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "not enough rights on this node: {} requested, {} held",
+            self.requested, self.available
+        )
+    }
+}
+
+impl std::error::Error for InsufficientRights {}
+
+impl Merge for BoundedCounter {
+    fn merge(&mut self, other: &Self) {
+        self.p.join(&other.p);
+        self.n.join(&other.n);
+        for (from, moved) in other.transfers.iter() {
+            self.transfers.entry(from.clone()).or_default().join(moved);
+        }
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        let none = VersionVector::new();
+        other.p.dominates(&self.p)
+            && other.n.dominates(&self.n)
+            && self
+                .transfers
+                .iter()
+                .all(|(from, moved)| other.transfers.get(from).unwrap_or(&none).dominates(moved))
+    }
+}
+
+impl BoundedCounter {
+    //all of the initial value starts out as rights of `node_id`
+    pub fn new(node_id: String, initial: u64) -> Self {
+        let mut counter = BoundedCounter::default();
+        counter.p.advance(&node_id, initial);
+        counter
+    }
+
+    pub fn increment(&mut self, node_id: String, amt: u64) {
+        self.p.advance(&node_id, amt);
+    }
+
+    //refuses the increment if the entry of the node or the value would pass u64::MAX
+    pub fn checked_increment(&mut self, node_id: String, amt: u64) -> Result<(), CounterOverflow> {
+        let value = self.p.total().saturating_sub(self.n.total());
+        let fits = self.p.get(&node_id).checked_add(amt).is_some()
+            && value + u128::from(amt) <= u128::from(u64::MAX);
+        if !fits {
+            return Err(CounterOverflow {
+                value: value as i128,
+                amount: u128::from(amt),
+                decrement: false,
+            });
+        }
+        self.p.advance(&node_id, amt);
+        Ok(())
+    }
+
+    pub fn decrement(&mut self, node_id: String, amt: u64) -> Result<(), InsufficientRights> {
+        self.check_rights(&node_id, amt)?;
+        self.n.advance(&node_id, amt);
+        Ok(())
+    }
+
+    //moves `amt` of the rights held by `from` over to `to`
+    pub fn transfer(
+        &mut self,
+        from: String,
+        to: String,
+        amt: u64,
+    ) -> Result<(), InsufficientRights> {
+        self.check_rights(&from, amt)?;
+        self.transfers.entry(from).or_default().advance(&to, amt);
+        Ok(())
+    }
+
+    //how much `node_id` can still decrement or hand over
+    pub fn rights(&self, node_id: &str) -> u64 {
+        let received: u128 = self
+            .transfers
+            .values()
+            .map(|moved| u128::from(moved.get(node_id)))
+            .sum();
+        let sent: u128 = self
+            .transfers
+            .get(node_id)
+            .map(VersionVector::total)
+            .unwrap_or(0);
+        let gained = u128::from(self.p.get(node_id)) + received;
+        let spent = u128::from(self.n.get(node_id)) + sent;
+        u64::try_from(gained.saturating_sub(spent)).unwrap_or(u64::MAX)
+    }
+
+    pub fn value(&self) -> u64 {
+        u64::try_from(self.p.total().saturating_sub(self.n.total())).unwrap_or(u64::MAX)
+    }
+
+    fn check_rights(&self, node_id: &str, amt: u64) -> Result<(), InsufficientRights> {
+        let available = self.rights(node_id);
+        if available < amt {
+            return Err(InsufficientRights {
+                available,
+                requested: amt,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decrement_is_limited_to_local_rights() {
+        let node_a = String::from("node_1");
+        let node_b = String::from("node_2");
+        let mut replica_a = BoundedCounter::new(node_a.clone(), 5);
+        let mut replica_b = replica_a.clone();
+
+        //node_2 holds no rights, even though the value is 5
+        assert_eq!(
+            replica_b.decrement(node_b.clone(), 1),
+            Err(InsufficientRights {
+                available: 0,
+                requested: 1
+            })
+        );
+
+        replica_a.decrement(node_a.clone(), 4).unwrap();
+        assert!(replica_a.decrement(node_a.clone(), 2).is_err());
+
+        replica_b.merge(&replica_a);
+        assert_eq!(replica_b.value(), 1);
+    }
+
+    #[test]
+    fn transferred_rights_survive_concurrent_decrements() {
+        let node_a = String::from("node_1");
+        let node_b = String::from("node_2");
+        let mut replica_a = BoundedCounter::new(node_a.clone(), 10);
+        replica_a
+            .transfer(node_a.clone(), node_b.clone(), 4)
+            .unwrap();
+        let mut replica_b = replica_a.clone();
+        assert_eq!(replica_b.rights(&node_b), 4);
+
+        //both sides of a partition spend everything they hold
+        replica_a.decrement(node_a.clone(), 6).unwrap();
+        replica_b.decrement(node_b.clone(), 4).unwrap();
+
+        replica_a.merge(&replica_b);
+        replica_b.merge(&replica_a);
+        assert_eq!(replica_a.value(), 0);
+        assert_eq!(replica_b.value(), 0);
+        assert_eq!(replica_a.rights(&node_a), 0);
+        assert_eq!(replica_a.rights(&node_b), 0);
+    }
+
+    #[test]
+    fn increment_past_u64_is_refused() {
+        let node_a = String::from("node_1");
+        let node_b = String::from("node_2");
+        let mut counter = BoundedCounter::new(node_a.clone(), u64::MAX - 1);
+
+        assert!(counter.checked_increment(node_b.clone(), 2).is_err());
+        counter.decrement(node_a.clone(), 5).unwrap();
+        counter.checked_increment(node_b.clone(), 2).unwrap();
+        assert_eq!(counter.value(), u64::MAX - 4);
+        assert_eq!(counter.rights(&node_b), 2);
+    }
+}
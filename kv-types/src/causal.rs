@@ -0,0 +1,315 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicU64, Ordering as AtomicOrdering},
+    time::SystemTime,
+};
+
+//Causality shared by the CRDTs and the replication layer: version vectors, dots and the
+//context of dots a replica has seen, and a hybrid logical clock for last-writer-wins types.
+
+pub type NodeId = String;
+//a single event, the `counter`th one made by `node`
+pub type Dot = (NodeId, u64);
+
+//Per node, how many events of that node have been seen. Joining takes the max per node, and
+//one vector dominates another if it has seen at least as many events of every node. Two
+//vectors where neither dominates are concurrent, which `partial_cmp` reports as None.
+//
+//Counters use the same structure, with the amount each node added in place of its events.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VersionVector {
+    pub entries: HashMap<NodeId, u64>,
+}
+
+impl VersionVector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, node_id: &str) -> u64 {
+        self.entries.get(node_id).copied().unwrap_or(0)
+    }
+
+    //moves the entry of `node_id` forward by `amt` and returns it. Entries stick at u64::MAX
+    //instead of wrapping around to a value every other replica would ignore
+    pub fn advance(&mut self, node_id: &str, amt: u64) -> u64 {
+        let entry = self.entries.entry(node_id.to_string()).or_insert(0);
+        *entry = entry.saturating_add(amt);
+        *entry
+    }
+
+    //the dot `node_id` hands out next
+    pub fn next_dot(&self, node_id: &str) -> Dot {
+        (node_id.to_string(), self.get(node_id) + 1)
+    }
+
+    //records the dot and, as vectors do not have gaps, every earlier one of its node
+    pub fn observe(&mut self, dot: &Dot) {
+        let entry = self.entries.entry(dot.0.clone()).or_insert(0);
+        *entry = (*entry).max(dot.1);
+    }
+
+    pub fn has_seen(&self, dot: &Dot) -> bool {
+        self.get(&dot.0) >= dot.1
+    }
+
+    //pointwise max, stored in `self`
+    pub fn join(&mut self, other: &VersionVector) {
+        for (node, n) in &other.entries {
+            let entry = self.entries.entry(node.clone()).or_insert(0);
+            *entry = (*entry).max(*n);
+        }
+    }
+
+    //true if `self` has seen every event `other` has
+    pub fn dominates(&self, other: &VersionVector) -> bool {
+        other.entries.iter().all(|(node, n)| self.get(node) >= *n)
+    }
+
+    //adds the entry of `retired` to the one of `survivor` and drops it, false if `retired`
+    //had no entry. Only meaningful for counters, see PNCounter::retire
+    pub fn fold(&mut self, retired: &str, survivor: &str) -> bool {
+        let Some(n) = self.entries.remove(retired) else {
+            return false;
+        };
+        self.advance(survivor, n);
+        true
+    }
+
+    //sum of the entries, wide enough that it cannot wrap
+    pub fn total(&self) -> u128 {
+        self.entries.values().map(|n| u128::from(*n)).sum()
+    }
+}
+
+impl PartialOrd for VersionVector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self.dominates(other), other.dominates(self)) {
+            (true, true) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Greater),
+            (false, true) => Some(Ordering::Less),
+            (false, false) => None,
+        }
+    }
+}
+
+impl From<HashMap<NodeId, u64>> for VersionVector {
+    fn from(entries: HashMap<NodeId, u64>) -> Self {
+        VersionVector { entries }
+    }
+}
+
+//Dots a replica has seen. Whole states only ever carry dots without gaps, which the version
+//vector holds, but a part of a state (a delta) can carry a dot without the ones before it.
+//Those are kept on the side in the cloud until the gap is filled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DotContext {
+    pub clock: VersionVector,
+    pub cloud: HashSet<Dot>,
+}
+
+impl DotContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //the dot `node_id` hands out next. A node always has seen all of its own dots, so they
+    //never end up in the cloud
+    pub fn next_dot(&self, node_id: &str) -> Dot {
+        self.clock.next_dot(node_id)
+    }
+
+    pub fn insert(&mut self, dot: Dot) {
+        if !self.contains(&dot) {
+            self.cloud.insert(dot);
+            self.compact();
+        }
+    }
+
+    //records a dot along with every earlier one of its node, for dots that never leave
+    //gaps that matter: the ones a node hands out itself, or the ones a map hands out to the
+    //values nested in it, where the dots in between went to other fields
+    pub fn observe(&mut self, dot: &Dot) {
+        self.clock.observe(dot);
+        self.compact();
+    }
+
+    pub fn contains(&self, dot: &Dot) -> bool {
+        self.clock.has_seen(dot) || self.cloud.contains(dot)
+    }
+
+    pub fn join(&mut self, other: &DotContext) {
+        self.clock.join(&other.clock);
+        self.cloud.extend(other.cloud.iter().cloned());
+        self.compact();
+    }
+
+    //true if `other` has seen every dot this context has
+    pub fn leq(&self, other: &DotContext) -> bool {
+        let clock = self.clock.entries.iter().all(|(node, n)| {
+            let seen = other.clock.get(node);
+            //the rest has to be in the cloud, which cannot hold more than it has
+            *n <= seen
+                || (*n - seen <= other.cloud.len() as u64
+                    && (seen + 1..=*n)
+                        .all(|counter| other.cloud.contains(&(node.clone(), counter))))
+        });
+        clock && self.cloud.iter().all(|dot| other.contains(dot))
+    }
+
+    //moves the dots of the cloud that follow on from the clock into it
+    fn compact(&mut self) {
+        loop {
+            let next = self
+                .cloud
+                .iter()
+                .find(|dot| dot.1 <= self.clock.get(&dot.0) + 1)
+                .cloned();
+            let Some(dot) = next else {
+                return;
+            };
+            self.cloud.remove(&dot);
+            self.clock.observe(&dot);
+        }
+    }
+}
+
+//dots of one element after a merge: the ones both sides still have, plus the ones only one
+//side has that the other side never saw, and so cannot have removed
+pub fn surviving_dots(
+    ours: &HashSet<Dot>,
+    our_context: &DotContext,
+    theirs: &HashSet<Dot>,
+    their_context: &DotContext,
+) -> HashSet<Dot> {
+    let kept_ours = ours
+        .iter()
+        .filter(|dot| theirs.contains(*dot) || !their_context.contains(dot));
+    let kept_theirs = theirs
+        .iter()
+        .filter(|dot| !ours.contains(*dot) && !our_context.contains(dot));
+    kept_ours.chain(kept_theirs).cloned().collect()
+}
+
+//true if merging the dots of one element into the ones of another side leaves those as they
+//are: every dot only we have was removed there, and every dot only they have is new to us
+pub fn dots_leq(
+    ours: &HashSet<Dot>,
+    our_context: &DotContext,
+    theirs: &HashSet<Dot>,
+    their_context: &DotContext,
+) -> bool {
+    ours.iter()
+        .all(|dot| theirs.contains(dot) || their_context.contains(dot))
+        && theirs
+            .iter()
+            .all(|dot| ours.contains(dot) || !our_context.contains(dot))
+}
+
+//the low bits of a clock reading count events within the same millisecond
+const LOGICAL_BITS: u32 = 16;
+
+//Hybrid logical clock. Readings are milliseconds since the unix epoch in the high bits and a
+//logical count in the low ones, so they order like wall-clock time while the clocks of the
+//nodes agree. Every reading is past the previous one and past every reading observed from
+//other nodes, so a write made after seeing another one always gets the higher timestamp,
+//even if the local clock is behind.
+#[derive(Debug, Default)]
+pub struct HybridClock {
+    last: AtomicU64,
+}
+
+//the clock of this process, shared by every value on the node
+pub static CLOCK: HybridClock = HybridClock::new();
+
+impl HybridClock {
+    pub const fn new() -> Self {
+        HybridClock {
+            last: AtomicU64::new(0),
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        let physical = physical_millis() << LOGICAL_BITS;
+        let previous = self
+            .last
+            .fetch_update(AtomicOrdering::SeqCst, AtomicOrdering::SeqCst, |last| {
+                Some(physical.max(last.saturating_add(1)))
+            })
+            .expect("the update always returns a value");
+        //a reading observed from a node whose clock is far ahead can take this one to the
+        //end of the range, where it stays rather than wrapping around to the past
+        physical.max(previous.saturating_add(1))
+    }
+
+    //makes the next reading come after `remote`
+    pub fn observe(&self, remote: u64) {
+        self.last.fetch_max(remote, AtomicOrdering::SeqCst);
+    }
+}
+
+//milliseconds since the unix epoch a reading was taken at
+pub fn wall_millis(reading: u64) -> u64 {
+    reading >> LOGICAL_BITS
+}
+
+fn physical_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(entries: &[(&str, u64)]) -> VersionVector {
+        entries
+            .iter()
+            .map(|(node, n)| (node.to_string(), *n))
+            .collect::<HashMap<_, _>>()
+            .into()
+    }
+
+    #[test]
+    fn vectors_order_partially_and_contexts_fill_gaps() {
+        let a = vector(&[("node_1", 2), ("node_2", 1)]);
+        let b = vector(&[("node_1", 1)]);
+        let c = vector(&[("node_3", 1)]);
+        assert!(a > b && b.partial_cmp(&a) == Some(Ordering::Less));
+        assert_eq!(a.partial_cmp(&c), None);
+        let mut joined = a.clone();
+        joined.join(&c);
+        assert!(joined.dominates(&a) && joined.dominates(&c));
+
+        let mut context = DotContext::new();
+        context.insert((String::from("node_1"), 2));
+        assert!(!context.contains(&(String::from("node_1"), 1)));
+        assert_eq!(context.cloud.len(), 1);
+        context.insert((String::from("node_1"), 1));
+        assert!(context.cloud.is_empty());
+        assert_eq!(context.clock.get("node_1"), 2);
+    }
+
+    #[test]
+    fn clock_readings_pass_what_was_observed() {
+        let clock = HybridClock::new();
+        let first = clock.now();
+        assert!(clock.now() > first);
+
+        //a node whose clock is an hour ahead
+        let remote = first + (3_600_000 << LOGICAL_BITS);
+        clock.observe(remote);
+        let next = clock.now();
+        assert!(next > remote);
+        assert_eq!(wall_millis(next), wall_millis(remote));
+
+        //nor does a reading at the end of the range make it wrap around
+        clock.observe(u64::MAX);
+        assert_eq!(clock.now(), u64::MAX);
+    }
+}
@@ -0,0 +1,98 @@
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+
+//Binary encoding of whole CRDT states, for anything that stores or ships them: a write-ahead
+//log, snapshots, or gossip between nodes. An encoding is the version of the format in one
+//byte, then the state in bincode with integers as varints, so the small numbers counters,
+//dots and clocks are mostly made of take a byte or two. Decoding refuses a version it does
+//not know instead of misreading it.
+//
+//Any change to how a type serializes, e.g. a new field, needs a new FORMAT_VERSION, and
+//decode has to keep reading the versions before it.
+
+pub const FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    Empty,
+    //written by a newer node, or not an encoding at all
+    UnknownVersion(u8),
+    Malformed(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "the encoding is empty"),
+            DecodeError::UnknownVersion(version) => {
+                write!(f, "unknown format version {}", version)
+            }
+            DecodeError::Malformed(reason) => write!(f, "malformed encoding: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+//spelled out instead of relying on bincode's defaults, they are part of the format
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_little_endian()
+        .with_varint_encoding()
+        .reject_trailing_bytes()
+}
+
+pub fn encode<T: Serialize>(state: &T) -> Vec<u8> {
+    let mut bytes = vec![FORMAT_VERSION];
+    options()
+        .serialize_into(&mut bytes, state)
+        .expect("every collection in a state knows its length");
+    bytes
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let (version, state) = bytes.split_first().ok_or(DecodeError::Empty)?;
+    match *version {
+        FORMAT_VERSION => options()
+            .deserialize(state)
+            .map_err(|e| DecodeError::Malformed(e.to_string())),
+        other => Err(DecodeError::UnknownVersion(other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{or_map::ORMap, pn_counter::PNCounter, Merge};
+
+    //every type is round tripped on random states with the merge laws, see merge_laws.rs
+
+    #[test]
+    fn unknown_versions_and_garbage_are_refused() {
+        let mut bytes = encode(&ORMap::new());
+        assert!(decode::<ORMap>(&bytes).is_ok());
+
+        bytes[0] = FORMAT_VERSION + 1;
+        assert_eq!(
+            decode::<ORMap>(&bytes).unwrap_err(),
+            DecodeError::UnknownVersion(FORMAT_VERSION + 1)
+        );
+        assert_eq!(decode::<ORMap>(&[]).unwrap_err(), DecodeError::Empty);
+        assert!(matches!(
+            decode::<ORMap>(&[FORMAT_VERSION, 0xff, 0xff]),
+            Err(DecodeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn small_numbers_take_few_bytes() {
+        let counter = PNCounter::new(String::from("node_1"), 5, 0);
+        let bytes = encode(&counter);
+        //version, two entry counts, the node id with its length, and two one byte counts
+        assert_eq!(bytes.len(), 1 + 1 + 1 + 6 + 1 + 1 + 1 + 6 + 1);
+
+        let decoded: PNCounter = decode(&bytes).unwrap();
+        assert!(decoded.leq(&counter) && counter.leq(&decoded));
+    }
+}
@@ -0,0 +1,122 @@
+use super::{or_set::ORSet, Merge};
+
+//Flags that several nodes can flip at once, e.g. feature flags. Both kinds keep which way the
+//flag was last set as an observed-remove set of true and false: setting it replaces the
+//values this replica has seen with the new one, so after a merge a value is only there if
+//some node set it concurrently with every other change. Both start out disabled, and they
+//differ in what a concurrent enable and disable read as.
+
+//enabled if any node enabled it concurrently with the last disable
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EnableWinsFlag {
+    pub state: ORSet<bool>,
+}
+
+//disabled if any node disabled it concurrently with the last enable
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisableWinsFlag {
+    pub state: ORSet<bool>,
+}
+
+impl EnableWinsFlag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enable(&mut self, node_id: &str) {
+        set(&mut self.state, node_id, true);
+    }
+
+    pub fn disable(&mut self, node_id: &str) {
+        set(&mut self.state, node_id, false);
+    }
+
+    pub fn value(&self) -> bool {
+        self.state.contains(&true)
+    }
+}
+
+impl DisableWinsFlag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enable(&mut self, node_id: &str) {
+        set(&mut self.state, node_id, true);
+    }
+
+    pub fn disable(&mut self, node_id: &str) {
+        set(&mut self.state, node_id, false);
+    }
+
+    pub fn value(&self) -> bool {
+        self.state.contains(&true) && !self.state.contains(&false)
+    }
+}
+
+fn set(state: &mut ORSet<bool>, node_id: &str, on: bool) {
+    state.remove(&!on);
+    state.add(node_id, on);
+}
+
+impl Merge for EnableWinsFlag {
+    fn merge(&mut self, other: &Self) {
+        self.state.merge(&other.state);
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        self.state.leq(&other.state)
+    }
+}
+
+impl Merge for DisableWinsFlag {
+    fn merge(&mut self, other: &Self) {
+        self.state.merge(&other.state);
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        self.state.leq(&other.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_enable_and_disable() {
+        let mut ew_a = EnableWinsFlag::new();
+        let mut dw_a = DisableWinsFlag::new();
+        assert!(!ew_a.value() && !dw_a.value());
+        ew_a.enable("node_1");
+        dw_a.enable("node_1");
+        let (mut ew_b, mut dw_b) = (ew_a.clone(), dw_a.clone());
+
+        //a disables and enables again, b disables at the same time
+        ew_a.disable("node_1");
+        ew_a.enable("node_1");
+        dw_a.disable("node_1");
+        dw_a.enable("node_1");
+        ew_b.disable("node_2");
+        dw_b.disable("node_2");
+
+        ew_a.merge(&ew_b);
+        dw_a.merge(&dw_b);
+        assert!(ew_a.value());
+        assert!(!dw_a.value());
+    }
+
+    #[test]
+    fn later_change_wins_either_way() {
+        let mut replica_a = DisableWinsFlag::new();
+        replica_a.disable("node_1");
+        let mut replica_b = replica_a.clone();
+        replica_b.enable("node_2");
+
+        //b enabled after seeing the disable, so nothing is concurrent
+        replica_a.merge(&replica_b);
+        assert!(replica_a.value());
+    }
+}
@@ -0,0 +1,96 @@
+use super::{causal::VersionVector, pn_counter::CounterOverflow, Merge};
+
+//Grow-only counter, for metrics that must never go down (page views, bytes served, ...).
+//Every node only ever adds to its own entry, merging takes the max per node, and the value
+//is the sum of all the entries. It is the positive half of a PNCounter on its own.
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GCounter {
+    pub counts: VersionVector,
+}
+
+impl Merge for GCounter {
+    fn merge(&mut self, other: &Self) {
+        self.counts.join(&other.counts);
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        other.counts.dominates(&self.counts)
+    }
+}
+
+impl GCounter {
+    pub fn new(node_id: String, initial: u64) -> Self {
+        let mut counter = GCounter::default();
+        counter.counts.advance(&node_id, initial);
+        counter
+    }
+
+    pub fn increment(&mut self, node_id: String, amt: u64) {
+        self.counts.advance(&node_id, amt);
+    }
+
+    //refuses the increment if the value would pass u64::MAX, see PNCounter
+    pub fn checked_increment(&mut self, node_id: String, amt: u64) -> Result<(), CounterOverflow> {
+        let total = self.counts.total();
+        if total + u128::from(amt) > u128::from(u64::MAX) {
+            return Err(CounterOverflow {
+                value: total as i128,
+                amount: u128::from(amt),
+                decrement: false,
+            });
+        }
+        self.counts.advance(&node_id, amt);
+        Ok(())
+    }
+
+    //same as PNCounter::retire
+    pub fn retire(&mut self, retired: &str, survivor: &str) -> bool {
+        self.counts.fold(retired, survivor)
+    }
+
+    pub fn forget(&mut self, retired: &str) -> bool {
+        self.counts.entries.remove(retired).is_some()
+    }
+
+    //sums wider than u64 stick at u64::MAX instead of wrapping around to a smaller value
+    pub fn value(&self) -> u64 {
+        u64::try_from(self.counts.total()).unwrap_or(u64::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_takes_the_max_per_node() {
+        let mut replica_a = GCounter::new(String::from("node_1"), 2);
+        let mut replica_b = GCounter::new(String::from("node_2"), 3);
+        replica_b.increment(String::from("node_1"), 1); //stale view of node_1
+
+        replica_a.merge(&replica_b);
+        assert_eq!(replica_a.value(), 5);
+
+        //merging the same state again changes nothing
+        replica_a.merge(&replica_b);
+        assert_eq!(replica_a.value(), 5);
+    }
+
+    #[test]
+    fn value_does_not_wrap() {
+        let mut counter = GCounter::new(String::from("node_1"), u64::MAX);
+        counter.increment(String::from("node_2"), 10);
+        assert_eq!(counter.value(), u64::MAX);
+
+        let mut counter = GCounter::new(String::from("node_1"), u64::MAX - 1);
+        counter
+            .checked_increment(String::from("node_2"), 1)
+            .unwrap();
+        assert!(counter
+            .checked_increment(String::from("node_2"), 1)
+            .is_err());
+        assert_eq!(counter.counts.get("node_2"), 1);
+    }
+}
@@ -0,0 +1,134 @@
+use super::Merge;
+
+//HyperLogLog sketch, estimates how many distinct elements were added without storing them.
+//Each element is hashed, the first PRECISION bits of the hash pick a register and the
+//register keeps the longest run of leading zeros seen in the rest. The state is REGISTERS
+//bytes whatever the number of elements, with a standard error of about 1.6%, and merging
+//two sketches is the register-wise max, which gives the sketch of the union.
+//
+//The hash is spelled out here instead of using std's, which is not guaranteed to be the
+//same across Rust versions, so nodes built with different compilers still agree.
+
+const PRECISION: u32 = 12;
+pub const REGISTERS: usize = 1 << PRECISION;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HyperLogLog {
+    pub registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //None unless there are exactly REGISTERS registers
+    pub fn from_registers(registers: Vec<u8>) -> Option<Self> {
+        (registers.len() == REGISTERS).then_some(HyperLogLog { registers })
+    }
+
+    //true if the sketch changed, i.e. the element was definitely not added before
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let hash = hash(element);
+        let index = (hash >> (64 - PRECISION)) as usize;
+        //a sentinel bit keeps the run finite when the remaining bits are all zero
+        let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+            return true;
+        }
+        false
+    }
+
+    //estimated number of distinct elements added
+    pub fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|r| 2f64.powi(-i32::from(*r)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+
+        //small counts leave registers empty, and counting those is more accurate there
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            return (m * (m / zeros as f64).ln()).round() as u64;
+        }
+        estimate.round() as u64
+    }
+}
+
+impl Merge for HyperLogLog {
+    fn merge(&mut self, other: &Self) {
+        for (ours, theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *ours = (*ours).max(*theirs);
+        }
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        self.registers
+            .iter()
+            .zip(&other.registers)
+            .all(|(ours, theirs)| ours <= theirs)
+    }
+}
+
+//64 bit FNV-1a, with the splitmix64 finalizer so the high bits that pick the register are
+//spread well
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(count: u64, expected: u64) {
+        let error = (count as f64 - expected as f64).abs() / expected as f64;
+        assert!(error < 0.05, "counted {} for {}", count, expected);
+    }
+
+    #[test]
+    fn estimates_distinct_elements() {
+        let mut sketch = HyperLogLog::new();
+        assert_eq!(sketch.count(), 0);
+        for n in 0..50_000 {
+            sketch.add(format!("visitor:{}", n % 20_000).as_bytes());
+        }
+        assert_close(sketch.count(), 20_000);
+        assert!(!sketch.add(b"visitor:7"));
+    }
+
+    #[test]
+    fn merge_counts_the_union() {
+        let mut replica_a = HyperLogLog::new();
+        let mut replica_b = HyperLogLog::new();
+        for n in 0..3000 {
+            replica_a.add(format!("visitor:{}", n).as_bytes());
+            replica_b.add(format!("visitor:{}", n + 2000).as_bytes());
+        }
+
+        replica_a.merge(&replica_b);
+        assert_close(replica_a.count(), 5000);
+        assert_eq!(replica_a.registers.len(), REGISTERS);
+    }
+}
@@ -0,0 +1,468 @@
+use super::{
+    causal::Dot,
+    lww_register::LwwRegister,
+    or_map::ORMap,
+    pn_counter::{CounterOverflow, PNCounter},
+    rga::{IndexOutOfBounds, Rga},
+    CrdtValue, Merge, Scalar,
+};
+use std::fmt;
+
+//JSON-like document, for storing whole nested documents under one key and editing parts
+//of them from several nodes at once.
+//
+//Objects are ORMaps, arrays are RGA lists, numbers, strings, bools and null are LWW
+//registers, and counters are PNCounters that only come from increments. A path names one
+//value in the document, every segment is a field name for objects and an index for lists,
+//e.g. ["servers", "0", "port"]. Every edit takes a dot from the root map and tags every
+//object field it goes through with it, so an edit deep inside the document wins over a
+//concurrent delete of any object it is in.
+
+//a plain JSON value, what goes into a document and what is read back from it. Objects keep
+//their fields sorted by name
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Scalar(Scalar),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JsonDoc {
+    pub root: ORMap,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DocError {
+    NotFound(String),
+    //a segment that is not a number, or is past the end of the list
+    BadIndex(String),
+    //the path goes through, or ends at, a value of the wrong type
+    TypeMismatch {
+        segment: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    //the document itself is always an object
+    RootNotObject,
+    Overflow(CounterOverflow),
+}
+
+impl fmt::Display for DocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocError::NotFound(segment) => write!(f, "{} does not exist", segment),
+            DocError::BadIndex(segment) => write!(f, "{} is not a valid index here", segment),
+            DocError::TypeMismatch {
+                segment,
+                expected,
+                found,
+            } => write!(f, "{} is a {}, not a {}", segment, found, expected),
+            DocError::RootNotObject => write!(f, "the document has to be an object"),
+            DocError::Overflow(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for DocError {}
+
+//the container the last segment of a path is looked up in
+enum Parent<'a> {
+    Object(&'a mut ORMap),
+    List(&'a mut Rga<CrdtValue>),
+}
+
+impl JsonDoc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //sets the value at `path`, replacing whatever was there. Missing objects on the way are
+    //created, an empty path replaces the whole document
+    pub fn set(&mut self, node_id: &str, path: &[String], value: Json) -> Result<(), DocError> {
+        let dot = self.root.next_dot(node_id);
+        let Some((last, rest)) = path.split_last() else {
+            let Json::Object(fields) = value else {
+                return Err(DocError::RootNotObject);
+            };
+            let stale: Vec<String> = self
+                .root
+                .entries
+                .keys()
+                .filter(|field| !fields.iter().any(|(name, _)| name == *field))
+                .cloned()
+                .collect();
+            for field in stale {
+                self.root.remove(&[field]);
+            }
+            for (name, value) in fields {
+                let value = build(node_id, &dot, value);
+                self.root.assign(dot.clone(), &name, value);
+            }
+            return Ok(());
+        };
+
+        let value = build(node_id, &dot, value);
+        match walk(Parent::Object(&mut self.root), &dot, rest, true)? {
+            Parent::Object(map) => map.assign(dot, last, value),
+            Parent::List(list) => {
+                let index = parse_index(last, list.len())?;
+                *list.get_mut(index).expect("index was checked") = value;
+            }
+        }
+        Ok(())
+    }
+
+    //inserts into a list, the last segment of `path` is the index the value ends up at, the
+    //length of the list appends
+    pub fn insert(&mut self, node_id: &str, path: &[String], value: Json) -> Result<(), DocError> {
+        let (last, rest) = path.split_last().ok_or(DocError::RootNotObject)?;
+        let dot = self.root.next_dot(node_id);
+        let value = build(node_id, &dot, value);
+        match walk(Parent::Object(&mut self.root), &dot, rest, false)? {
+            Parent::List(list) => {
+                let index = parse_index(last, list.len() + 1)?;
+                list.insert(node_id, index, value)
+                    .map_err(|_| DocError::BadIndex(last.clone()))?;
+                Ok(())
+            }
+            Parent::Object(_) => Err(DocError::TypeMismatch {
+                segment: path_name(rest),
+                expected: "list",
+                found: "map",
+            }),
+        }
+    }
+
+    //appends to the list at `path`
+    pub fn push(&mut self, node_id: &str, path: &[String], value: Json) -> Result<(), DocError> {
+        let len = match self.value_at(path) {
+            Some(CrdtValue::List(list)) => list.len(),
+            Some(other) => {
+                return Err(DocError::TypeMismatch {
+                    segment: path_name(path),
+                    expected: "list",
+                    found: other.type_name(),
+                })
+            }
+            None => return Err(DocError::NotFound(path_name(path))),
+        };
+        let mut path = path.to_vec();
+        path.push(len.to_string());
+        self.insert(node_id, &path, value)
+    }
+
+    //removes a field of an object or an element of a list
+    pub fn delete(&mut self, node_id: &str, path: &[String]) -> Result<(), DocError> {
+        let (last, rest) = path.split_last().ok_or(DocError::RootNotObject)?;
+        let dot = self.root.next_dot(node_id);
+        match walk(Parent::Object(&mut self.root), &dot, rest, false)? {
+            Parent::Object(map) => {
+                if !map.remove(std::slice::from_ref(last)) {
+                    return Err(DocError::NotFound(path_name(path)));
+                }
+            }
+            Parent::List(list) => {
+                let index = parse_index(last, list.len())?;
+                list.delete(index)
+                    .map_err(|_: IndexOutOfBounds| DocError::BadIndex(last.clone()))?;
+            }
+        }
+        Ok(())
+    }
+
+    //adds `amt` to the counter at `path`, a missing field of an object becomes a counter.
+    //An op that would overflow the counter is refused and leaves the document as it was
+    pub fn increment(&mut self, node_id: &str, path: &[String], amt: i64) -> Result<(), DocError> {
+        let (last, rest) = path.split_last().ok_or(DocError::RootNotObject)?;
+        let mut updated = match self.value_at(path) {
+            Some(CrdtValue::Counter(counter)) => counter.clone(),
+            _ => PNCounter::new(node_id.to_string(), 0, 0),
+        };
+        let result = if amt >= 0 {
+            updated.checked_increment(node_id.to_string(), amt as u64)
+        } else {
+            updated.checked_decrement(node_id.to_string(), amt.unsigned_abs())
+        };
+        result.map_err(DocError::Overflow)?;
+
+        let dot = self.root.next_dot(node_id);
+        let value = match walk(Parent::Object(&mut self.root), &dot, rest, true)? {
+            Parent::Object(map) => map.field_mut(dot, last, || {
+                CrdtValue::Counter(PNCounter::new(node_id.to_string(), 0, 0))
+            }),
+            Parent::List(list) => {
+                let index = parse_index(last, list.len())?;
+                list.get_mut(index).expect("index was checked")
+            }
+        };
+        match value {
+            CrdtValue::Counter(counter) => *counter = updated,
+            other => {
+                return Err(DocError::TypeMismatch {
+                    segment: path_name(path),
+                    expected: "counter",
+                    found: other.type_name(),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    //the value at `path` as plain JSON, the whole document for an empty path
+    pub fn get(&self, path: &[String]) -> Option<Json> {
+        if path.is_empty() {
+            return Some(to_json(&CrdtValue::Map(self.root.clone())));
+        }
+        self.value_at(path).map(to_json)
+    }
+
+    fn value_at(&self, path: &[String]) -> Option<&CrdtValue> {
+        let (first, rest) = path.split_first()?;
+        let mut value = &self.root.entries.get(first)?.value;
+        for segment in rest {
+            value = match value {
+                CrdtValue::Map(map) => &map.entries.get(segment)?.value,
+                CrdtValue::List(list) => list.get(segment.parse().ok()?)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+}
+
+impl Merge for JsonDoc {
+    fn merge(&mut self, other: &Self) {
+        self.root.merge(&other.root);
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        self.root.leq(&other.root)
+    }
+}
+
+//follows `path` down from `parent` and returns the container its last segment is in.
+//Object fields on the way are tagged with `dot`, and missing ones are created as objects
+//if `create` is set
+fn walk<'a>(
+    parent: Parent<'a>,
+    dot: &Dot,
+    path: &[String],
+    create: bool,
+) -> Result<Parent<'a>, DocError> {
+    let Some((segment, rest)) = path.split_first() else {
+        return Ok(parent);
+    };
+
+    let child = match parent {
+        Parent::Object(map) => {
+            if !create && !map.entries.contains_key(segment) {
+                return Err(DocError::NotFound(segment.clone()));
+            }
+            map.field_mut(dot.clone(), segment, || CrdtValue::Map(ORMap::new()))
+        }
+        Parent::List(list) => {
+            let index = parse_index(segment, list.len())?;
+            list.get_mut(index).expect("index was checked")
+        }
+    };
+
+    let next = match child {
+        CrdtValue::Map(map) => Parent::Object(map),
+        CrdtValue::List(list) => Parent::List(list),
+        other => {
+            return Err(DocError::TypeMismatch {
+                segment: segment.clone(),
+                expected: "map or list",
+                found: other.type_name(),
+            })
+        }
+    };
+    walk(next, dot, rest, create)
+}
+
+//`segment` as an index below `bound`
+fn parse_index(segment: &str, bound: usize) -> Result<usize, DocError> {
+    segment
+        .parse::<usize>()
+        .ok()
+        .filter(|index| *index < bound)
+        .ok_or_else(|| DocError::BadIndex(segment.to_string()))
+}
+
+fn path_name(path: &[String]) -> String {
+    if path.is_empty() {
+        return String::from("the document");
+    }
+    path.join(".")
+}
+
+//the CRDT a JSON value is stored as, objects nested in it are tagged with `dot`
+fn build(node_id: &str, dot: &Dot, value: Json) -> CrdtValue {
+    match value {
+        Json::Scalar(scalar) => CrdtValue::Register(LwwRegister::new(node_id.to_string(), scalar)),
+        Json::Array(items) => {
+            let mut list = Rga::new();
+            let mut last = None;
+            for item in items {
+                let item = build(node_id, dot, item);
+                last = Some(list.insert_after(node_id, last, item));
+            }
+            CrdtValue::List(list)
+        }
+        Json::Object(fields) => {
+            let mut map = ORMap::new();
+            for (name, value) in fields {
+                let value = build(node_id, dot, value);
+                map.assign(dot.clone(), &name, value);
+            }
+            CrdtValue::Map(map)
+        }
+    }
+}
+
+//reads any value back as plain JSON, counters read as their value and sets as lists of
+//their members
+pub fn to_json(value: &CrdtValue) -> Json {
+    match value {
+        CrdtValue::Counter(counter) => Json::Scalar(Scalar::Int(counter.value())),
+        CrdtValue::GrowOnlyCounter(counter) => Json::Scalar(Scalar::Int(
+            i64::try_from(counter.value()).unwrap_or(i64::MAX),
+        )),
+        CrdtValue::BoundedCounter(counter) => Json::Scalar(Scalar::Int(
+            i64::try_from(counter.value()).unwrap_or(i64::MAX),
+        )),
+        CrdtValue::Register(register) => Json::Scalar(register.value.clone()),
+        CrdtValue::Set(set) => {
+            let mut members: Vec<&String> = set.elements().collect();
+            members.sort();
+            Json::Array(
+                members
+                    .into_iter()
+                    .map(|m| Json::Scalar(Scalar::String(m.clone())))
+                    .collect(),
+            )
+        }
+        CrdtValue::Map(map) => {
+            let mut fields: Vec<(String, Json)> = map
+                .entries
+                .iter()
+                .map(|(name, entry)| (name.clone(), to_json(&entry.value)))
+                .collect();
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            Json::Object(fields)
+        }
+        CrdtValue::List(list) => Json::Array(list.iter().map(to_json).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(p: &str) -> Vec<String> {
+        p.split('.').map(String::from).collect()
+    }
+
+    fn int(n: i64) -> Json {
+        Json::Scalar(Scalar::Int(n))
+    }
+
+    fn string(s: &str) -> Json {
+        Json::Scalar(Scalar::String(s.to_string()))
+    }
+
+    fn config() -> Json {
+        Json::Object(vec![
+            (String::from("name"), string("api")),
+            (
+                String::from("servers"),
+                Json::Array(vec![Json::Object(vec![(String::from("port"), int(80))])]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn set_and_read_back_nested_values() {
+        let mut doc = JsonDoc::new();
+        doc.set("node_1", &[], config()).unwrap();
+        doc.set("node_1", &path("servers.0.port"), int(8080))
+            .unwrap();
+        doc.push("node_1", &path("servers"), Json::Object(vec![]))
+            .unwrap();
+        doc.set("node_1", &path("limits.rps"), int(100)).unwrap();
+
+        assert_eq!(doc.get(&path("servers.0.port")), Some(int(8080)));
+        assert_eq!(
+            doc.get(&path("limits")),
+            Some(Json::Object(vec![(String::from("rps"), int(100))]))
+        );
+        assert_eq!(
+            doc.get(&path("servers")),
+            Some(Json::Array(vec![
+                Json::Object(vec![(String::from("port"), int(8080))]),
+                Json::Object(vec![]),
+            ]))
+        );
+        assert_eq!(
+            doc.set("node_1", &path("name.first"), int(1)),
+            Err(DocError::TypeMismatch {
+                segment: String::from("name"),
+                expected: "map or list",
+                found: "register",
+            })
+        );
+
+        //a counter that would pass i64 is refused and stays as it was
+        doc.increment("node_1", &path("hits"), i64::MAX).unwrap();
+        let before = doc.clone();
+        assert!(matches!(
+            doc.increment("node_2", &path("hits"), 1),
+            Err(DocError::Overflow(_))
+        ));
+        assert!(doc.leq(&before) && before.leq(&doc));
+    }
+
+    #[test]
+    fn concurrent_edits_merge() {
+        let mut replica_a = JsonDoc::new();
+        replica_a.set("node_1", &[], config()).unwrap();
+        replica_a.increment("node_1", &path("hits"), 1).unwrap();
+        let mut replica_b = replica_a.clone();
+
+        replica_a.increment("node_1", &path("hits"), 2).unwrap();
+        replica_a
+            .insert("node_1", &path("servers.0"), Json::Object(vec![]))
+            .unwrap();
+        replica_b.increment("node_2", &path("hits"), 3).unwrap();
+        replica_b
+            .set("node_2", &path("servers.0.port"), int(443))
+            .unwrap();
+
+        let mut merged_a = replica_a.clone();
+        merged_a.merge(&replica_b);
+        replica_b.merge(&replica_a);
+        assert_eq!(merged_a.get(&[]), replica_b.get(&[]));
+        assert_eq!(merged_a.get(&path("hits")), Some(int(6)));
+        assert_eq!(merged_a.get(&path("servers.1.port")), Some(int(443)));
+    }
+
+    #[test]
+    fn edit_wins_over_concurrent_delete_of_its_object() {
+        let mut replica_a = JsonDoc::new();
+        replica_a.set("node_1", &[], config()).unwrap();
+        replica_a
+            .set("node_1", &path("limits.rps"), int(10))
+            .unwrap();
+        let mut replica_b = replica_a.clone();
+
+        replica_a.delete("node_1", &path("limits")).unwrap();
+        replica_b
+            .set("node_2", &path("limits.burst"), int(5))
+            .unwrap();
+
+        replica_a.merge(&replica_b);
+        assert_eq!(replica_a.get(&path("limits.burst")), Some(int(5)));
+    }
+}
@@ -1,14 +1,138 @@
 pub mod aw_set;
+pub mod bounded_counter;
+pub mod causal;
+#[cfg(feature = "serde")]
+pub mod codec;
+pub mod flag;
+pub mod g_counter;
+pub mod hyperloglog;
+pub mod json_doc;
+pub mod lww_register;
 pub mod lww_set;
+pub mod max_min_register;
+#[cfg(test)]
+mod merge_laws;
+pub mod or_map;
+pub mod or_set;
+#[cfg(feature = "serde")]
+mod pairs;
 pub mod pn_counter;
+pub mod rga;
+pub mod sorted_set;
+pub mod text;
+pub mod two_phase_set;
+pub mod wide_counter;
 
-pub trait Merge {
-    fn merge(&mut self, other: &mut Self);
+//The states of a type form a join semilattice: merging moves a state up to the least state
+//that is at or above both, so replicas that merged each other's states in any order, any
+//number of times, end up the same.
+pub trait Merge: Clone {
+    //moves `self` up to the join of both states, `other` is left as it is
+    fn merge(&mut self, other: &Self);
+
+    //true if `self` is at or below `other` in the order merging follows, so merging `self`
+    //into `other` would not change it
+    fn leq(&self, other: &Self) -> bool;
+
+    //the join of both states as a new value
+    fn join(&self, other: &Self) -> Self {
+        let mut joined = self.clone();
+        joined.merge(other);
+        joined
+    }
+
+    //merges `other` in and returns what it brought that `self` did not have, None if
+    //nothing. Types that cannot pick out the new part return all of `other`
+    fn merge_delta(&mut self, other: &Self) -> Option<Self> {
+        if other.leq(self) {
+            return None;
+        }
+        self.merge(other);
+        Some(other.clone())
+    }
 }
 
 //this enum is the value, so mergeDB really would be storing key : CrdtValue
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CrdtValue {
     Counter(pn_counter::PNCounter),
-    Register(lww_set::LwwSet),
-    Set(aw_set::AWSet<String>), //for now its String
+    GrowOnlyCounter(g_counter::GCounter),
+    BoundedCounter(bounded_counter::BoundedCounter),
+    Register(lww_register::LwwRegister<Scalar>),
+    Set(or_set::ORSet<String>), //for now its String
+    Map(or_map::ORMap),
+    List(rga::Rga<CrdtValue>),
+}
+
+//what a register holds, the leaf values of a JSON document
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Scalar {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl CrdtValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            CrdtValue::Counter(_) => "counter",
+            CrdtValue::GrowOnlyCounter(_) => "grow-only counter",
+            CrdtValue::BoundedCounter(_) => "bounded counter",
+            CrdtValue::Register(_) => "register",
+            CrdtValue::Set(_) => "set",
+            CrdtValue::Map(_) => "map",
+            CrdtValue::List(_) => "list",
+        }
+    }
+
+    //decides between two values of different types, e.g. a field of an ORMap that two nodes
+    //created concurrently with different types. Every replica keeps the same one
+    fn rank(&self) -> u8 {
+        match self {
+            CrdtValue::Counter(_) => 0,
+            CrdtValue::GrowOnlyCounter(_) => 1,
+            CrdtValue::BoundedCounter(_) => 2,
+            CrdtValue::Register(_) => 3,
+            CrdtValue::Set(_) => 4,
+            CrdtValue::Map(_) => 5,
+            CrdtValue::List(_) => 6,
+        }
+    }
+}
+
+impl Merge for CrdtValue {
+    fn merge(&mut self, other: &Self) {
+        match (self, other) {
+            (CrdtValue::Counter(a), CrdtValue::Counter(b)) => a.merge(b),
+            (CrdtValue::GrowOnlyCounter(a), CrdtValue::GrowOnlyCounter(b)) => a.merge(b),
+            (CrdtValue::BoundedCounter(a), CrdtValue::BoundedCounter(b)) => a.merge(b),
+            (CrdtValue::Register(a), CrdtValue::Register(b)) => a.merge(b),
+            (CrdtValue::Set(a), CrdtValue::Set(b)) => a.merge(b),
+            (CrdtValue::Map(a), CrdtValue::Map(b)) => a.merge(b),
+            (CrdtValue::List(a), CrdtValue::List(b)) => a.merge(b),
+            (ours, theirs) => {
+                if theirs.rank() > ours.rank() {
+                    *ours = theirs.clone();
+                }
+            }
+        }
+    }
+
+    //a value of another type is below one that would replace it
+    fn leq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (CrdtValue::Counter(a), CrdtValue::Counter(b)) => a.leq(b),
+            (CrdtValue::GrowOnlyCounter(a), CrdtValue::GrowOnlyCounter(b)) => a.leq(b),
+            (CrdtValue::BoundedCounter(a), CrdtValue::BoundedCounter(b)) => a.leq(b),
+            (CrdtValue::Register(a), CrdtValue::Register(b)) => a.leq(b),
+            (CrdtValue::Set(a), CrdtValue::Set(b)) => a.leq(b),
+            (CrdtValue::Map(a), CrdtValue::Map(b)) => a.leq(b),
+            (CrdtValue::List(a), CrdtValue::List(b)) => a.leq(b),
+            (ours, theirs) => ours.rank() < theirs.rank(),
+        }
+    }
 }
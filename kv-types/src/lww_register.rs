@@ -0,0 +1,77 @@
+use super::{causal::CLOCK, Merge};
+
+//Last-writer-wins register, holds a single value and a merge keeps the write with the
+//highest timestamp. Two writes with the same timestamp are ordered by node id, so every
+//replica picks the same one. Timestamps are hybrid clock readings, so a write made after
+//seeing another one wins over it even if the clock of its node is behind.
+
+type NodeId = String;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LwwRegister<T: Clone> {
+    pub value: T,
+    //hybrid clock reading when the value was written
+    pub timestamp: u64,
+    pub node_id: NodeId,
+}
+
+impl<T: Clone> Merge for LwwRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        CLOCK.observe(other.timestamp);
+        if (other.timestamp, &other.node_id) > (self.timestamp, &self.node_id) {
+            *self = other.clone();
+        }
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        (self.timestamp, &self.node_id) <= (other.timestamp, &other.node_id)
+    }
+}
+
+impl<T: Clone> LwwRegister<T> {
+    pub fn new(node_id: String, value: T) -> Self {
+        LwwRegister {
+            value,
+            timestamp: CLOCK.now(),
+            node_id,
+        }
+    }
+
+    //a write always wins over the value it replaces, even one the clock has not observed
+    pub fn set(&mut self, node_id: String, value: T) {
+        self.timestamp = CLOCK.now().max(self.timestamp + 1);
+        self.node_id = node_id;
+        self.value = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_write_wins_and_ties_go_to_the_higher_node_id() {
+        let mut replica_a = LwwRegister::new(String::from("node_1"), String::from("ada"));
+        let mut replica_b = replica_a.clone();
+        replica_b.set(String::from("node_2"), String::from("grace"));
+
+        replica_a.merge(&replica_b);
+        assert_eq!(replica_a.value, "grace");
+
+        let mut tie_a = LwwRegister {
+            value: 1,
+            timestamp: 10,
+            node_id: String::from("node_1"),
+        };
+        let mut tie_b = LwwRegister {
+            value: 2,
+            timestamp: 10,
+            node_id: String::from("node_2"),
+        };
+        tie_a.merge(&tie_b);
+        tie_b.merge(&tie_a);
+        assert_eq!(tie_a.value, 2);
+        assert_eq!(tie_b.value, 2);
+    }
+}
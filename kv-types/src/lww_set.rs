@@ -1,3 +1,156 @@
-pub struct LwwSet {
-    
-}
\ No newline at end of file
+use super::{causal::CLOCK, Merge};
+use std::{collections::HashMap, hash::Hash};
+
+//Last-writer-wins element set, e.g. a presence list where the latest check-in or check-out of
+//each member should stick. Every element keeps the timestamp of its latest add and of its
+//latest remove, and it is in the set if the add is later. Stamps are ordered like
+//LwwRegister's, by timestamp then node id, and an add and remove with the same stamp count
+//as added. Unlike the two-phase set an element can be removed and added again any number
+//of times, and unlike the add-wins set the outcome of concurrent changes depends on the
+//clocks of the nodes that made them.
+
+type NodeId = String;
+//hybrid clock reading, and the node that made the change
+pub type Stamp = (u64, NodeId);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LwwSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    pub adds: HashMap<T, Stamp>,
+    pub removes: HashMap<T, Stamp>,
+}
+
+impl<T> Default for LwwSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        LwwSet {
+            adds: HashMap::new(),
+            removes: HashMap::new(),
+        }
+    }
+}
+
+impl<T> LwwSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, node_id: &str, element: T) {
+        let stamp = self.next_stamp(node_id, &element);
+        self.adds.insert(element, stamp);
+    }
+
+    //false if the element was not in the set
+    pub fn remove(&mut self, node_id: &str, element: T) -> bool {
+        let present = self.contains(&element);
+        let stamp = self.next_stamp(node_id, &element);
+        self.removes.insert(element, stamp);
+        present
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        match (self.adds.get(element), self.removes.get(element)) {
+            (Some(added), Some(removed)) => added >= removed,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &T> {
+        self.adds.keys().filter(|element| self.contains(element))
+    }
+
+    //a change always wins over the ones this replica has seen for the element, even ones the
+    //clock has not observed
+    fn next_stamp(&self, node_id: &str, element: &T) -> Stamp {
+        let latest = [self.adds.get(element), self.removes.get(element)]
+            .into_iter()
+            .flatten()
+            .map(|(timestamp, _)| timestamp + 1)
+            .max()
+            .unwrap_or(0);
+        (CLOCK.now().max(latest), node_id.to_string())
+    }
+}
+
+impl<T> Merge for LwwSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    fn merge(&mut self, other: &Self) {
+        for (ours, theirs) in [
+            (&mut self.adds, &other.adds),
+            (&mut self.removes, &other.removes),
+        ] {
+            for (element, stamp) in theirs {
+                CLOCK.observe(stamp.0);
+                match ours.get(element) {
+                    Some(current) if current >= stamp => {}
+                    _ => {
+                        ours.insert(element.clone(), stamp.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        [(&self.adds, &other.adds), (&self.removes, &other.removes)]
+            .into_iter()
+            .all(|(ours, theirs)| {
+                ours.iter()
+                    .all(|(element, stamp)| theirs.get(element).is_some_and(|s| stamp <= s))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latest_change_to_an_element_wins() {
+        let mut set = LwwSet::new();
+        set.add("node_1", "alice");
+        assert!(set.remove("node_1", "alice"));
+        assert!(!set.contains(&"alice"));
+        set.add("node_1", "alice");
+        set.add("node_1", "bob");
+        assert!(!set.remove("node_1", "carol"));
+
+        let mut members: Vec<_> = set.elements().collect();
+        members.sort();
+        assert_eq!(members, vec![&"alice", &"bob"]);
+    }
+
+    #[test]
+    fn merge_keeps_the_later_stamp_either_way() {
+        let mut replica_a = LwwSet::new();
+        let mut replica_b = LwwSet::new();
+        //stamps set by hand, the clocks of two nodes are not ordered in a test
+        replica_a.adds.insert("alice", (10, String::from("node_1")));
+        replica_b
+            .removes
+            .insert("alice", (20, String::from("node_2")));
+        replica_b.adds.insert("bob", (20, String::from("node_2")));
+        replica_a
+            .removes
+            .insert("bob", (20, String::from("node_2")));
+
+        let mut merged = replica_a.clone();
+        merged.merge(&replica_b);
+        replica_b.merge(&replica_a);
+        assert_eq!(merged, replica_b);
+        assert!(!merged.contains(&"alice"));
+        //same stamp on the add and the remove, the add wins
+        assert!(merged.contains(&"bob"));
+    }
+}
@@ -0,0 +1,116 @@
+use super::Merge;
+
+//Registers that only move one way, e.g. the latest sequence number seen or the lowest price.
+//A write only takes effect if it is past the current value, and a merge keeps the higher (or
+//lower) of the two, so unlike LwwRegister the result does not depend on any clock.
+//
+//Besides integers they hold byte strings, compared byte by byte, that can carry a payload
+//which does not take part in the order, e.g. a timestamp with the event it was seen in. Two
+//values with the same bytes are ordered by their payload, so every replica keeps the same one.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaxRegister<T> {
+    pub value: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MinRegister<T> {
+    pub value: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ranked {
+    pub value: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+impl<T: Ord> MaxRegister<T> {
+    pub fn new(value: T) -> Self {
+        MaxRegister { value }
+    }
+
+    //false if `value` is not higher than the current one, which is then kept
+    pub fn set(&mut self, value: T) -> bool {
+        if value > self.value {
+            self.value = value;
+            return true;
+        }
+        false
+    }
+}
+
+impl<T: Ord> MinRegister<T> {
+    pub fn new(value: T) -> Self {
+        MinRegister { value }
+    }
+
+    //false if `value` is not lower than the current one, which is then kept
+    pub fn set(&mut self, value: T) -> bool {
+        if value < self.value {
+            self.value = value;
+            return true;
+        }
+        false
+    }
+}
+
+impl<T: Ord + Clone> Merge for MaxRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        self.set(other.value.clone());
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        self.value <= other.value
+    }
+}
+
+impl<T: Ord + Clone> Merge for MinRegister<T> {
+    fn merge(&mut self, other: &Self) {
+        self.set(other.value.clone());
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        self.value >= other.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_extreme_value() {
+        let mut highest = MaxRegister::new(7i64);
+        let mut lowest = MinRegister::new(7i64);
+        assert!(highest.set(9));
+        assert!(!highest.set(8));
+        assert!(lowest.set(-3));
+        assert!(!lowest.set(4));
+
+        let other = MaxRegister::new(12);
+        highest.merge(&other);
+        assert_eq!(highest.value, 12);
+        assert_eq!(lowest.value, -3);
+    }
+
+    #[test]
+    fn byte_strings_order_by_value_then_payload() {
+        let ranked = |value: &str, payload: &str| Ranked {
+            value: value.as_bytes().to_vec(),
+            payload: payload.as_bytes().to_vec(),
+        };
+        let mut replica_a = MinRegister::new(ranked("0042.10", "order:7"));
+        let mut replica_b = replica_a.clone();
+        replica_a.set(ranked("0041.99", "order:9"));
+        replica_b.set(ranked("0041.99", "order:8"));
+
+        let mut merged = replica_a.clone();
+        merged.merge(&replica_b);
+        replica_b.merge(&replica_a);
+        assert_eq!(merged, replica_b);
+        assert_eq!(merged.value, ranked("0041.99", "order:8"));
+    }
+}
@@ -0,0 +1,821 @@
+use super::{
+    bounded_counter::BoundedCounter,
+    causal::{DotContext, VersionVector},
+    flag::{DisableWinsFlag, EnableWinsFlag},
+    g_counter::GCounter,
+    hyperloglog::HyperLogLog,
+    json_doc::{Json, JsonDoc},
+    lww_register::LwwRegister,
+    lww_set::LwwSet,
+    max_min_register::{MaxRegister, MinRegister},
+    or_map::ORMap,
+    or_set::ORSet,
+    pn_counter::PNCounter,
+    rga::Rga,
+    sorted_set::SortedSet,
+    text::Text,
+    two_phase_set::TwoPhaseSet,
+    wide_counter::WideCounter,
+    Merge, Scalar,
+};
+use proptest::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+};
+
+//Laws every CRDT has to satisfy for replicas to converge, checked on random histories. A
+//history is a list of steps on a few replicas, each either an op made on one of them or one
+//replica merging in the state of another, so the replicas end up with a mix of shared and
+//concurrent changes. On the states they end up in, merging has to be commutative,
+//associative and idempotent, and replicas that merged in all the others, each in its own
+//order, have to read the same.
+//
+//With the serde feature every state the replicas end up in also has to read the same after
+//going through the binary encoding in codec.rs.
+//
+//A type takes part by implementing Replicated, and gets a test in the list at the bottom.
+
+const REPLICAS: usize = 3;
+
+trait Replicated: Merge + Clone + Debug {
+    type Op: Clone + Debug;
+    //what replicas that converged have to agree on, the whole state wherever it can be
+    //compared
+    type Observed: PartialEq + Debug;
+
+    fn empty() -> Self;
+    fn op() -> BoxedStrategy<Self::Op>;
+    fn apply(&mut self, node_id: &str, op: &Self::Op);
+    fn observe(&self) -> Self::Observed;
+}
+
+#[derive(Debug, Clone)]
+enum Step<Op> {
+    Apply(usize, Op),
+    Sync { from: usize, to: usize },
+}
+
+fn history<T: Replicated>() -> impl Strategy<Value = Vec<Step<T::Op>>> {
+    let step = prop_oneof![
+        3 => (0..REPLICAS, T::op()).prop_map(|(replica, op)| Step::Apply(replica, op)),
+        1 => (0..REPLICAS, 0..REPLICAS).prop_map(|(from, to)| Step::Sync { from, to }),
+    ];
+    proptest::collection::vec(step, 0..24)
+}
+
+fn run<T: Replicated>(history: &[Step<T::Op>]) -> Vec<T> {
+    let mut replicas = vec![T::empty(); REPLICAS];
+    for step in history {
+        match step {
+            Step::Apply(replica, op) => replicas[*replica].apply(&node(*replica), op),
+            Step::Sync { from, to } => {
+                let state = replicas[*from].clone();
+                replicas[*to].merge(&state);
+            }
+        }
+    }
+    replicas
+}
+
+fn node(replica: usize) -> String {
+    format!("node_{}", replica)
+}
+
+fn check_laws<T: Replicated>(history: &[Step<T::Op>]) -> Result<(), TestCaseError> {
+    let replicas = run::<T>(history);
+    let (a, b, c) = (&replicas[0], &replicas[1], &replicas[2]);
+
+    prop_assert_eq!(
+        a.join(b).observe(),
+        b.join(a).observe(),
+        "merge is not commutative"
+    );
+    prop_assert_eq!(
+        a.join(b).join(c).observe(),
+        a.join(&b.join(c)).observe(),
+        "merge is not associative"
+    );
+    prop_assert_eq!(a.join(a).observe(), a.observe(), "merge is not idempotent");
+
+    //the join is above both sides, and a side below the other one adds nothing to it
+    let joined = a.join(b);
+    prop_assert!(a.leq(a), "leq is not reflexive");
+    prop_assert!(
+        a.leq(&joined) && b.leq(&joined),
+        "join is not above both sides"
+    );
+    if a.leq(b) {
+        prop_assert_eq!(
+            joined.observe(),
+            b.observe(),
+            "leq holds but merge changed the state"
+        );
+    }
+    prop_assert_eq!(
+        a.clone().merge_delta(b).is_none(),
+        b.leq(a),
+        "merge_delta disagrees with leq"
+    );
+
+    //every replica merges in the others, starting with the one after it
+    let converged: Vec<T::Observed> = (0..REPLICAS)
+        .map(|start| {
+            let mut state = replicas[start].clone();
+            for offset in 1..REPLICAS {
+                state.merge(&replicas[(start + offset) % REPLICAS]);
+            }
+            state.observe()
+        })
+        .collect();
+    for observed in &converged[1..] {
+        prop_assert_eq!(observed, &converged[0], "replicas did not converge");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+fn check_round_trip<T>(history: &[Step<T::Op>]) -> Result<(), TestCaseError>
+where
+    T: Replicated + serde::Serialize + serde::de::DeserializeOwned,
+{
+    for replica in run::<T>(history) {
+        let decoded: T = crate::codec::decode(&crate::codec::encode(&replica))
+            .map_err(|e| TestCaseError::fail(e.to_string()))?;
+        prop_assert_eq!(decoded.observe(), replica.observe(), "the state changed");
+        prop_assert!(
+            decoded.leq(&replica) && replica.leq(&decoded),
+            "the state moved in the merge order"
+        );
+    }
+    Ok(())
+}
+
+//ops pick their elements and fields from a few values, so replicas touch the same ones
+fn small() -> impl Strategy<Value = u8> {
+    0u8..4
+}
+
+fn field() -> impl Strategy<Value = Vec<String>> {
+    prop_oneof![
+        Just(vec![String::from("a")]),
+        Just(vec![String::from("b")]),
+        Just(vec![String::from("m"), String::from("a")]),
+    ]
+}
+
+fn sorted<'a, T: Ord + Clone + 'a>(elements: impl Iterator<Item = &'a T>) -> Vec<T> {
+    elements
+        .cloned()
+        .collect::<BTreeSet<T>>()
+        .into_iter()
+        .collect()
+}
+
+//version vectors and dot contexts compared by what they have seen, entries at 0 included
+fn vector(vector: &VersionVector) -> BTreeMap<String, u64> {
+    vector
+        .entries
+        .iter()
+        .filter(|(_, n)| **n > 0)
+        .map(|(node, n)| (node.clone(), *n))
+        .collect()
+}
+
+fn context(context: &DotContext) -> (BTreeMap<String, u64>, Vec<(String, u64)>) {
+    (vector(&context.clock), sorted(context.cloud.iter()))
+}
+
+impl Replicated for PNCounter {
+    type Op = (bool, u8);
+    type Observed = (i64, BTreeMap<String, u64>, BTreeMap<String, u64>);
+
+    fn empty() -> Self {
+        PNCounter {
+            p: VersionVector::new(),
+            n: VersionVector::new(),
+        }
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        (any::<bool>(), any::<u8>()).boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, (up, amt): &Self::Op) {
+        match up {
+            true => self.increment(node_id.to_string(), u64::from(*amt)),
+            false => self.decrement(node_id.to_string(), u64::from(*amt)),
+        }
+    }
+
+    fn observe(&self) -> Self::Observed {
+        (self.value(), vector(&self.p), vector(&self.n))
+    }
+}
+
+impl Replicated for WideCounter {
+    type Op = (bool, u64);
+    type Observed = (i128, BTreeMap<String, u128>, BTreeMap<String, u128>);
+
+    fn empty() -> Self {
+        WideCounter::default()
+    }
+
+    //amounts past u8, so values leave i64
+    fn op() -> BoxedStrategy<Self::Op> {
+        (any::<bool>(), any::<u64>()).boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, (up, amt): &Self::Op) {
+        let amt = u128::from(*amt) << 32;
+        let _ = match up {
+            true => self.increment(node_id.to_string(), amt),
+            false => self.decrement(node_id.to_string(), amt),
+        };
+    }
+
+    fn observe(&self) -> Self::Observed {
+        let entries = |half: &std::collections::HashMap<String, u128>| {
+            half.iter()
+                .filter(|(_, n)| **n > 0)
+                .map(|(node, n)| (node.clone(), *n))
+                .collect()
+        };
+        (self.value(), entries(&self.p), entries(&self.n))
+    }
+}
+
+impl Replicated for GCounter {
+    type Op = u8;
+    type Observed = (u64, BTreeMap<String, u64>);
+
+    fn empty() -> Self {
+        GCounter::default()
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        any::<u8>().boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, amt: &Self::Op) {
+        self.increment(node_id.to_string(), u64::from(*amt));
+    }
+
+    fn observe(&self) -> Self::Observed {
+        (self.value(), vector(&self.counts))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum BoundedOp {
+    Increment(u8),
+    Decrement(u8),
+    Transfer(usize, u8),
+}
+
+impl Replicated for BoundedCounter {
+    type Op = BoundedOp;
+    type Observed = (u64, Vec<u64>);
+
+    fn empty() -> Self {
+        BoundedCounter::default()
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        prop_oneof![
+            any::<u8>().prop_map(BoundedOp::Increment),
+            any::<u8>().prop_map(BoundedOp::Decrement),
+            (0..REPLICAS, any::<u8>()).prop_map(|(to, amt)| BoundedOp::Transfer(to, amt)),
+        ]
+        .boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, op: &Self::Op) {
+        //ops a node does not hold the rights for are refused, and leave the state as it was
+        let _ = match op {
+            BoundedOp::Increment(amt) => {
+                self.increment(node_id.to_string(), u64::from(*amt));
+                Ok(())
+            }
+            BoundedOp::Decrement(amt) => self.decrement(node_id.to_string(), u64::from(*amt)),
+            BoundedOp::Transfer(to, amt) => {
+                self.transfer(node_id.to_string(), node(*to), u64::from(*amt))
+            }
+        };
+    }
+
+    fn observe(&self) -> Self::Observed {
+        let rights = (0..REPLICAS).map(|r| self.rights(&node(r))).collect();
+        (self.value(), rights)
+    }
+}
+
+impl Replicated for LwwRegister<u8> {
+    type Op = u8;
+    type Observed = (u8, u64, String);
+
+    fn empty() -> Self {
+        LwwRegister {
+            value: 0,
+            timestamp: 0,
+            node_id: String::new(),
+        }
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        any::<u8>().boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, value: &Self::Op) {
+        self.set(node_id.to_string(), *value);
+    }
+
+    fn observe(&self) -> Self::Observed {
+        (self.value, self.timestamp, self.node_id.clone())
+    }
+}
+
+impl Replicated for ORSet<u8> {
+    type Op = (bool, u8);
+    type Observed = (
+        Vec<(u8, Vec<(String, u64)>)>,
+        (BTreeMap<String, u64>, Vec<(String, u64)>),
+    );
+
+    fn empty() -> Self {
+        ORSet::new()
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        (any::<bool>(), small()).boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, (add, element): &Self::Op) {
+        match add {
+            true => self.add(node_id, *element),
+            false => {
+                self.remove(element);
+            }
+        }
+    }
+
+    fn observe(&self) -> Self::Observed {
+        let mut entries: Vec<(u8, Vec<(String, u64)>)> = self
+            .entries
+            .iter()
+            .map(|(element, dots)| (*element, sorted(dots.iter())))
+            .collect();
+        entries.sort();
+        (entries, context(&self.context))
+    }
+}
+
+impl Replicated for TwoPhaseSet<u8> {
+    type Op = (bool, u8);
+    type Observed = Self;
+
+    fn empty() -> Self {
+        TwoPhaseSet::new()
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        (any::<bool>(), small()).boxed()
+    }
+
+    fn apply(&mut self, _: &str, (add, element): &Self::Op) {
+        match add {
+            true => {
+                self.add(*element);
+            }
+            false => {
+                self.remove(*element);
+            }
+        }
+    }
+
+    fn observe(&self) -> Self::Observed {
+        self.clone()
+    }
+}
+
+impl Replicated for LwwSet<u8> {
+    type Op = (bool, u8);
+    type Observed = Self;
+
+    fn empty() -> Self {
+        LwwSet::new()
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        (any::<bool>(), small()).boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, (add, element): &Self::Op) {
+        match add {
+            true => self.add(node_id, *element),
+            false => {
+                self.remove(node_id, *element);
+            }
+        }
+    }
+
+    fn observe(&self) -> Self::Observed {
+        self.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum MapOp {
+    Increment(Vec<String>, u8),
+    SetRegister(Vec<String>, i64),
+    AddToSet(Vec<String>, u8),
+    RemoveFromSet(Vec<String>, u8),
+    Remove(Vec<String>),
+}
+
+impl Replicated for ORMap {
+    type Op = MapOp;
+    type Observed = (Option<Json>, (BTreeMap<String, u64>, Vec<(String, u64)>));
+
+    fn empty() -> Self {
+        ORMap::new()
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        prop_oneof![
+            (field(), any::<u8>()).prop_map(|(path, amt)| MapOp::Increment(path, amt)),
+            (field(), any::<i64>()).prop_map(|(path, n)| MapOp::SetRegister(path, n)),
+            (field(), small()).prop_map(|(path, member)| MapOp::AddToSet(path, member)),
+            (field(), small()).prop_map(|(path, member)| MapOp::RemoveFromSet(path, member)),
+            field().prop_map(MapOp::Remove),
+        ]
+        .boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, op: &Self::Op) {
+        //a field can hold a different type on each replica, ops on the wrong one are refused
+        let _ = match op {
+            MapOp::Increment(path, amt) => self.increment(node_id, path, u64::from(*amt)),
+            MapOp::SetRegister(path, n) => self.set_register(node_id, path, Scalar::Int(*n)),
+            MapOp::AddToSet(path, member) => self.add_to_set(node_id, path, member.to_string()),
+            MapOp::RemoveFromSet(path, member) => {
+                self.remove_from_set(node_id, path, &member.to_string())
+            }
+            MapOp::Remove(path) => {
+                self.remove(path);
+                Ok(())
+            }
+        };
+    }
+
+    fn observe(&self) -> Self::Observed {
+        let document = JsonDoc { root: self.clone() };
+        (document.get(&[]), context(&self.context))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum DocOp {
+    Set(Vec<String>, i64),
+    Increment(Vec<String>, i8),
+    Push(Vec<String>, i64),
+    Delete(Vec<String>),
+}
+
+impl Replicated for JsonDoc {
+    type Op = DocOp;
+    type Observed = Option<Json>;
+
+    fn empty() -> Self {
+        JsonDoc::new()
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        prop_oneof![
+            (field(), any::<i64>()).prop_map(|(path, n)| DocOp::Set(path, n)),
+            (field(), any::<i8>()).prop_map(|(path, amt)| DocOp::Increment(path, amt)),
+            (field(), any::<i64>()).prop_map(|(path, n)| DocOp::Push(path, n)),
+            field().prop_map(DocOp::Delete),
+        ]
+        .boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, op: &Self::Op) {
+        let _ = match op {
+            DocOp::Set(path, n) => self.set(node_id, path, Json::Scalar(Scalar::Int(*n))),
+            DocOp::Increment(path, amt) => self.increment(node_id, path, i64::from(*amt)),
+            DocOp::Push(path, n) => self.push(node_id, path, Json::Scalar(Scalar::Int(*n))),
+            DocOp::Delete(path) => self.delete(node_id, path),
+        };
+    }
+
+    fn observe(&self) -> Self::Observed {
+        self.get(&[])
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ListOp {
+    Insert(usize, u8),
+    Delete(usize),
+    Move(usize, usize),
+    Increment(usize, u8),
+}
+
+impl Replicated for Rga<GCounter> {
+    type Op = ListOp;
+    type Observed = Vec<u64>;
+
+    fn empty() -> Self {
+        Rga::new()
+    }
+
+    //indexes are taken modulo the length of the list they are applied to
+    fn op() -> BoxedStrategy<Self::Op> {
+        prop_oneof![
+            (any::<usize>(), any::<u8>()).prop_map(|(i, n)| ListOp::Insert(i, n)),
+            any::<usize>().prop_map(ListOp::Delete),
+            (any::<usize>(), any::<usize>()).prop_map(|(from, to)| ListOp::Move(from, to)),
+            (any::<usize>(), any::<u8>()).prop_map(|(i, amt)| ListOp::Increment(i, amt)),
+        ]
+        .boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, op: &Self::Op) {
+        let len = self.len();
+        match op {
+            ListOp::Insert(i, n) => {
+                let item = GCounter::new(node_id.to_string(), u64::from(*n));
+                let _ = self.insert(node_id, i % (len + 1), item);
+            }
+            _ if len == 0 => {}
+            ListOp::Delete(i) => {
+                let _ = self.delete(i % len);
+            }
+            ListOp::Move(from, to) => {
+                let _ = self.move_to(node_id, from % len, to % len);
+            }
+            ListOp::Increment(i, amt) => {
+                if let Some(item) = self.get_mut(i % len) {
+                    item.increment(node_id.to_string(), u64::from(*amt));
+                }
+            }
+        }
+    }
+
+    fn observe(&self) -> Self::Observed {
+        self.iter().map(GCounter::value).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum TextOp {
+    Insert(usize, String),
+    Delete(usize, usize),
+    Update(String),
+}
+
+impl Replicated for Text {
+    type Op = TextOp;
+    type Observed = String;
+
+    fn empty() -> Self {
+        Text::new()
+    }
+
+    //positions are taken modulo the length of the text they are applied to
+    fn op() -> BoxedStrategy<Self::Op> {
+        prop_oneof![
+            (any::<usize>(), "[ab]{1,3}").prop_map(|(i, text)| TextOp::Insert(i, text)),
+            (any::<usize>(), 1usize..4).prop_map(|(i, count)| TextOp::Delete(i, count)),
+            "[abc]{0,5}".prop_map(TextOp::Update),
+        ]
+        .boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, op: &Self::Op) {
+        let len = self.len();
+        match op {
+            TextOp::Insert(i, text) => {
+                let _ = self.insert(node_id, i % (len + 1), text);
+            }
+            TextOp::Delete(_, _) if len == 0 => {}
+            TextOp::Delete(i, count) => {
+                let start = i % len;
+                let _ = self.delete(start, (*count).min(len - start));
+            }
+            TextOp::Update(text) => self.update(node_id, text),
+        }
+    }
+
+    fn observe(&self) -> Self::Observed {
+        self.to_string()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ZSetOp {
+    Add(u8, i8),
+    Increment(u8, i8),
+    Remove(u8),
+}
+
+impl Replicated for SortedSet {
+    type Op = ZSetOp;
+    type Observed = Vec<(String, i64)>;
+
+    fn empty() -> Self {
+        SortedSet::new()
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        prop_oneof![
+            (small(), any::<i8>()).prop_map(|(member, score)| ZSetOp::Add(member, score)),
+            (small(), any::<i8>()).prop_map(|(member, amt)| ZSetOp::Increment(member, amt)),
+            small().prop_map(ZSetOp::Remove),
+        ]
+        .boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, op: &Self::Op) {
+        match op {
+            //scores stay far from the bounds of i64, nothing is refused
+            ZSetOp::Add(member, score) => self
+                .add(node_id, &member.to_string(), i64::from(*score))
+                .expect("small score"),
+            ZSetOp::Increment(member, amt) => {
+                self.increment(node_id, &member.to_string(), i64::from(*amt))
+                    .expect("small score");
+            }
+            ZSetOp::Remove(member) => {
+                self.remove(&member.to_string());
+            }
+        }
+    }
+
+    fn observe(&self) -> Self::Observed {
+        self.ranked()
+            .into_iter()
+            .map(|(member, score)| (member.to_string(), score))
+            .collect()
+    }
+}
+
+impl Replicated for HyperLogLog {
+    type Op = u16;
+    type Observed = Self;
+
+    fn empty() -> Self {
+        HyperLogLog::new()
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        any::<u16>().boxed()
+    }
+
+    fn apply(&mut self, _: &str, element: &Self::Op) {
+        self.add(&element.to_be_bytes());
+    }
+
+    fn observe(&self) -> Self::Observed {
+        self.clone()
+    }
+}
+
+impl Replicated for EnableWinsFlag {
+    type Op = bool;
+    type Observed = bool;
+
+    fn empty() -> Self {
+        EnableWinsFlag::new()
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        any::<bool>().boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, enable: &Self::Op) {
+        match enable {
+            true => self.enable(node_id),
+            false => self.disable(node_id),
+        }
+    }
+
+    fn observe(&self) -> Self::Observed {
+        self.value()
+    }
+}
+
+impl Replicated for DisableWinsFlag {
+    type Op = bool;
+    type Observed = bool;
+
+    fn empty() -> Self {
+        DisableWinsFlag::new()
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        any::<bool>().boxed()
+    }
+
+    fn apply(&mut self, node_id: &str, enable: &Self::Op) {
+        match enable {
+            true => self.enable(node_id),
+            false => self.disable(node_id),
+        }
+    }
+
+    fn observe(&self) -> Self::Observed {
+        self.value()
+    }
+}
+
+impl Replicated for MaxRegister<i64> {
+    type Op = i64;
+    type Observed = Self;
+
+    fn empty() -> Self {
+        MaxRegister::new(i64::MIN)
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        any::<i64>().boxed()
+    }
+
+    fn apply(&mut self, _: &str, value: &Self::Op) {
+        self.set(*value);
+    }
+
+    fn observe(&self) -> Self::Observed {
+        self.clone()
+    }
+}
+
+impl Replicated for MinRegister<i64> {
+    type Op = i64;
+    type Observed = Self;
+
+    fn empty() -> Self {
+        MinRegister::new(i64::MAX)
+    }
+
+    fn op() -> BoxedStrategy<Self::Op> {
+        any::<i64>().boxed()
+    }
+
+    fn apply(&mut self, _: &str, value: &Self::Op) {
+        self.set(*value);
+    }
+
+    fn observe(&self) -> Self::Observed {
+        self.clone()
+    }
+}
+
+macro_rules! merge_laws {
+    ($($name:ident: $type:ty,)*) => {
+        proptest! {
+            $(
+                #[test]
+                fn $name(history in history::<$type>()) {
+                    check_laws::<$type>(&history)?;
+                }
+            )*
+        }
+
+        #[cfg(feature = "serde")]
+        mod round_trip {
+            use super::*;
+
+            proptest! {
+                $(
+                    #[test]
+                    fn $name(history in history::<$type>()) {
+                        check_round_trip::<$type>(&history)?;
+                    }
+                )*
+            }
+        }
+    };
+}
+
+merge_laws! {
+    pn_counter: PNCounter,
+    g_counter: GCounter,
+    wide_counter: WideCounter,
+    bounded_counter: BoundedCounter,
+    lww_register: LwwRegister<u8>,
+    or_set: ORSet<u8>,
+    two_phase_set: TwoPhaseSet<u8>,
+    lww_set: LwwSet<u8>,
+    or_map: ORMap,
+    json_doc: JsonDoc,
+    rga: Rga<GCounter>,
+    text: Text,
+    sorted_set: SortedSet,
+    hyperloglog: HyperLogLog,
+    enable_wins_flag: EnableWinsFlag,
+    disable_wins_flag: DisableWinsFlag,
+    max_register: MaxRegister<i64>,
+    min_register: MinRegister<i64>,
+}
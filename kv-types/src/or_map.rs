@@ -0,0 +1,562 @@
+use super::{
+    causal::{dots_leq, surviving_dots, Dot, DotContext},
+    lww_register::LwwRegister,
+    pn_counter::{CounterOverflow, PNCounter},
+    CrdtValue, Merge, Scalar,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+};
+
+//Observed-remove map from field names to CRDT values, e.g. a user profile with a visits
+//counter, a tags set and a name register under one key. Fields are addressed by a path, so
+//a field can itself be a map ("address.city").
+//
+//Every update of a field tags it with a new dot, and so does every map it goes through on
+//the way. Removing a field drops it together with the dots this replica has seen, and a
+//merge keeps a field if either side has a dot the other has not seen. So an update that is
+//concurrent with a remove of the field (or one of its parents) wins.
+//
+//The value of a field is kept per dot: an update folds the values of all the dots into one
+//under its new dot, and a merge keeps the value of every dot that survives. A read is the
+//merge of those values. So a field that was removed and then created again starts over
+//instead of bringing back its old state, and a remove that drops some of the dots of a field
+//updated concurrently takes out exactly what those updates added, whichever order the
+//replicas merge in.
+//
+//All the dots come from the map at the top, so values nested in it never reuse one.
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ORMap {
+    pub entries: HashMap<String, MapEntry>,
+    //every dot this replica has seen, same as in ORSet
+    pub context: DotContext,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MapEntry {
+    pub dots: HashSet<Dot>,
+    //what reads see, the merge of the values of all the dots
+    pub value: CrdtValue,
+    //the value of each dot, only kept while there is more than one. With a single dot its
+    //value is `value`
+    #[cfg_attr(feature = "serde", serde(with = "crate::pairs"))]
+    pub siblings: BTreeMap<Dot, CrdtValue>,
+}
+
+impl MapEntry {
+    fn new(value: CrdtValue) -> Self {
+        MapEntry {
+            dots: HashSet::new(),
+            value,
+            siblings: BTreeMap::new(),
+        }
+    }
+
+    //the entry of a field with the given value per dot, None if there are no dots left.
+    //Values are merged in dot order, so values of different types settle the same way on
+    //every replica
+    pub fn from_siblings(mut siblings: BTreeMap<Dot, CrdtValue>) -> Option<Self> {
+        if siblings.len() <= 1 {
+            let (dot, value) = siblings.pop_first()?;
+            return Some(MapEntry {
+                dots: HashSet::from([dot]),
+                value,
+                siblings,
+            });
+        }
+        let mut values = siblings.values().cloned();
+        let mut value = values.next().expect("there are at least two siblings");
+        for other in values {
+            value.merge(&other);
+        }
+        Some(MapEntry {
+            dots: siblings.keys().cloned().collect(),
+            value,
+            siblings,
+        })
+    }
+
+    //the value of each dot
+    fn into_siblings(self) -> BTreeMap<Dot, CrdtValue> {
+        if !self.siblings.is_empty() {
+            return self.siblings;
+        }
+        let dot = self.dots.into_iter().next();
+        dot.map(|dot| (dot, self.value)).into_iter().collect()
+    }
+
+    //the value of a single dot of the field
+    fn value_of(&self, dot: &Dot) -> Option<&CrdtValue> {
+        match self.siblings.is_empty() {
+            true => self.dots.contains(dot).then_some(&self.value),
+            false => self.siblings.get(dot),
+        }
+    }
+
+    //tags the field with `dot` alone, its value now covers the values of the old dots
+    fn collapse(&mut self, dot: Dot) {
+        self.dots = HashSet::from([dot]);
+        self.siblings.clear();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapError {
+    EmptyPath,
+    //the field exists, but holds another type than the op works on
+    TypeMismatch {
+        field: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    Overflow(CounterOverflow),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::EmptyPath => write!(f, "the path to the field is empty"),
+            MapError::TypeMismatch {
+                field,
+                expected,
+                found,
+            } => write!(f, "field {} is a {}, not a {}", field, found, expected),
+            MapError::Overflow(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+impl ORMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //the dot the next update made by `node_id` is tagged with
+    pub fn next_dot(&self, node_id: &str) -> Dot {
+        self.context.next_dot(node_id)
+    }
+
+    //the value of a single field, created with `create` if missing, with the field tagged
+    //with `dot` as updated. For types built on top of the map that walk paths themselves
+    pub fn field_mut(
+        &mut self,
+        dot: Dot,
+        field: &str,
+        create: impl FnOnce() -> CrdtValue,
+    ) -> &mut CrdtValue {
+        self.context.observe(&dot);
+        let entry = self
+            .entries
+            .entry(field.to_string())
+            .or_insert_with(|| MapEntry::new(create()));
+        entry.collapse(dot);
+        &mut entry.value
+    }
+
+    //replaces the value of a single field, whatever it held before
+    pub fn assign(&mut self, dot: Dot, field: &str, value: CrdtValue) {
+        let mut value = Some(value);
+        let current = self.field_mut(dot, field, || value.take().expect("taken once"));
+        if let Some(value) = value {
+            *current = value;
+        }
+    }
+
+    //creates the counter at `path` if there is none yet, refuses an op that would overflow it
+    pub fn increment(&mut self, node_id: &str, path: &[String], amt: u64) -> Result<(), MapError> {
+        self.update_counter(node_id, path, |counter| {
+            counter.checked_increment(node_id.to_string(), amt)
+        })
+    }
+
+    pub fn decrement(&mut self, node_id: &str, path: &[String], amt: u64) -> Result<(), MapError> {
+        self.update_counter(node_id, path, |counter| {
+            counter.checked_decrement(node_id.to_string(), amt)
+        })
+    }
+
+    pub fn set_register(
+        &mut self,
+        node_id: &str,
+        path: &[String],
+        value: Scalar,
+    ) -> Result<(), MapError> {
+        let dot = self.context.next_dot(node_id);
+        let mut value = Some(value);
+        self.update(
+            dot,
+            path,
+            || CrdtValue::Register(LwwRegister::new(node_id.to_string(), Scalar::Null)),
+            |current, _| match current {
+                CrdtValue::Register(register) => {
+                    let value = value.take().expect("op runs once");
+                    register.set(node_id.to_string(), value);
+                    Ok(())
+                }
+                other => Err(other.type_name()),
+            },
+            "register",
+        )
+    }
+
+    pub fn add_to_set(
+        &mut self,
+        node_id: &str,
+        path: &[String],
+        member: String,
+    ) -> Result<(), MapError> {
+        let dot = self.context.next_dot(node_id);
+        self.update(
+            dot,
+            path,
+            || CrdtValue::Set(Default::default()),
+            |current, dot| match current {
+                CrdtValue::Set(set) => {
+                    set.add_with_dot(dot.clone(), member);
+                    Ok(())
+                }
+                other => Err(other.type_name()),
+            },
+            "set",
+        )
+    }
+
+    pub fn remove_from_set(
+        &mut self,
+        node_id: &str,
+        path: &[String],
+        member: &String,
+    ) -> Result<(), MapError> {
+        let dot = self.context.next_dot(node_id);
+        self.update(
+            dot,
+            path,
+            || CrdtValue::Set(Default::default()),
+            |current, _| match current {
+                CrdtValue::Set(set) => {
+                    set.remove(member);
+                    Ok(())
+                }
+                other => Err(other.type_name()),
+            },
+            "set",
+        )
+    }
+
+    //removes the field at `path`, returns false if there was none
+    pub fn remove(&mut self, path: &[String]) -> bool {
+        match path {
+            [] => false,
+            [field] => self.entries.remove(field).is_some(),
+            [field, rest @ ..] => match self.entries.get_mut(field) {
+                Some(MapEntry {
+                    value: CrdtValue::Map(inner),
+                    siblings,
+                    ..
+                }) => {
+                    //a later merge rebuilds the value from the siblings, so they lose the
+                    //field too
+                    for sibling in siblings.values_mut() {
+                        if let CrdtValue::Map(sibling) = sibling {
+                            sibling.remove(rest);
+                        }
+                    }
+                    inner.remove(rest)
+                }
+                _ => false,
+            },
+        }
+    }
+
+    pub fn get(&self, path: &[String]) -> Option<&CrdtValue> {
+        let (field, rest) = path.split_first()?;
+        let value = &self.entries.get(field)?.value;
+        match (rest.is_empty(), value) {
+            (true, value) => Some(value),
+            (false, CrdtValue::Map(inner)) => inner.get(rest),
+            (false, _) => None,
+        }
+    }
+
+    fn update_counter(
+        &mut self,
+        node_id: &str,
+        path: &[String],
+        op: impl FnOnce(&mut PNCounter) -> Result<(), CounterOverflow>,
+    ) -> Result<(), MapError> {
+        //the op is tried on a copy first, a refused one must not touch the map
+        let mut updated = match self.get(path) {
+            Some(CrdtValue::Counter(counter)) => counter.clone(),
+            _ => PNCounter::new(node_id.to_string(), 0, 0),
+        };
+        op(&mut updated).map_err(MapError::Overflow)?;
+
+        let dot = self.context.next_dot(node_id);
+        self.update(
+            dot,
+            path,
+            || CrdtValue::Counter(PNCounter::new(node_id.to_string(), 0, 0)),
+            |current, _| match current {
+                CrdtValue::Counter(counter) => {
+                    *counter = updated;
+                    Ok(())
+                }
+                other => Err(other.type_name()),
+            },
+            "counter",
+        )
+    }
+
+    //applies `op` to the value at `path`, creating it with `create`, and any map missing on
+    //the way, if needed. `op` returns the type it found if it cannot work on it
+    fn update(
+        &mut self,
+        dot: Dot,
+        path: &[String],
+        create: impl FnOnce() -> CrdtValue,
+        op: impl FnOnce(&mut CrdtValue, &Dot) -> Result<(), &'static str>,
+        expected: &'static str,
+    ) -> Result<(), MapError> {
+        let (field, rest) = path.split_first().ok_or(MapError::EmptyPath)?;
+
+        let result = if rest.is_empty() {
+            let entry = self
+                .entries
+                .entry(field.clone())
+                .or_insert_with(|| MapEntry::new(create()));
+            op(&mut entry.value, &dot).map_err(|found| MapError::TypeMismatch {
+                field: field.clone(),
+                expected,
+                found,
+            })
+        } else {
+            let entry = self
+                .entries
+                .entry(field.clone())
+                .or_insert_with(|| MapEntry::new(CrdtValue::Map(ORMap::new())));
+            match &mut entry.value {
+                CrdtValue::Map(inner) => inner.update(dot.clone(), rest, create, op, expected),
+                value => Err(MapError::TypeMismatch {
+                    field: field.clone(),
+                    expected: "map",
+                    found: value.type_name(),
+                }),
+            }
+        };
+
+        let entry = self
+            .entries
+            .get_mut(field)
+            .expect("field was inserted above");
+        match result {
+            Ok(()) => {
+                entry.collapse(dot.clone());
+                self.context.observe(&dot);
+                Ok(())
+            }
+            Err(e) => {
+                if entry.dots.is_empty() {
+                    self.entries.remove(field); //created just now for the failed op
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Merge for ORMap {
+    fn merge(&mut self, other: &Self) {
+        let fields: HashSet<String> = self
+            .entries
+            .keys()
+            .chain(other.entries.keys())
+            .cloned()
+            .collect();
+        let empty = HashSet::new();
+
+        for field in fields {
+            let ours = self.entries.remove(&field);
+            let theirs = other.entries.get(&field);
+            let dots = surviving_dots(
+                ours.as_ref().map(|e| &e.dots).unwrap_or(&empty),
+                &self.context,
+                theirs.map(|e| &e.dots).unwrap_or(&empty),
+                &other.context,
+            );
+
+            //only the values of the dots that survived are kept, a dot both sides have can
+            //hold different values if a nested field was removed on one side
+            let mut siblings = BTreeMap::new();
+            for (dot, value) in ours.map(MapEntry::into_siblings).unwrap_or_default() {
+                if dots.contains(&dot) {
+                    siblings.insert(dot, value);
+                }
+            }
+            for (dot, value) in theirs
+                .cloned()
+                .map(MapEntry::into_siblings)
+                .unwrap_or_default()
+            {
+                if !dots.contains(&dot) {
+                    continue;
+                }
+                match siblings.get_mut(&dot) {
+                    Some(ours) => ours.merge(&value),
+                    None => {
+                        siblings.insert(dot, value);
+                    }
+                }
+            }
+            if let Some(entry) = MapEntry::from_siblings(siblings) {
+                self.entries.insert(field, entry);
+            }
+        }
+        self.context.join(&other.context);
+    }
+
+    //besides the dots, the value of every dot both sides have has to be below theirs
+    fn leq(&self, other: &Self) -> bool {
+        let empty = HashSet::new();
+        self.context.leq(&other.context)
+            && self
+                .entries
+                .keys()
+                .chain(other.entries.keys())
+                .all(|field| {
+                    let ours = self.entries.get(field);
+                    let theirs = other.entries.get(field);
+                    let dots_below = dots_leq(
+                        ours.map(|e| &e.dots).unwrap_or(&empty),
+                        &self.context,
+                        theirs.map(|e| &e.dots).unwrap_or(&empty),
+                        &other.context,
+                    );
+                    let (Some(ours), Some(theirs)) = (ours, theirs) else {
+                        return dots_below;
+                    };
+                    dots_below
+                        && ours.dots.iter().all(|dot| {
+                            match (ours.value_of(dot), theirs.value_of(dot)) {
+                                (Some(our_value), Some(their_value)) => our_value.leq(their_value),
+                                _ => true,
+                            }
+                        })
+                })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(p: &str) -> Vec<String> {
+        p.split('.').map(String::from).collect()
+    }
+
+    fn visits(map: &ORMap) -> Option<i64> {
+        match map.get(&path("visits")) {
+            Some(CrdtValue::Counter(counter)) => Some(counter.value()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn nested_values_merge_recursively() {
+        let mut replica_a = ORMap::new();
+        replica_a.increment("node_1", &path("visits"), 1).unwrap();
+        replica_a
+            .set_register(
+                "node_1",
+                &path("address.city"),
+                Scalar::String(String::from("Pune")),
+            )
+            .unwrap();
+        let mut replica_b = replica_a.clone();
+
+        replica_a.increment("node_1", &path("visits"), 2).unwrap();
+        replica_b.increment("node_2", &path("visits"), 4).unwrap();
+        replica_b
+            .add_to_set("node_2", &path("tags"), String::from("admin"))
+            .unwrap();
+
+        replica_a.merge(&replica_b);
+        assert_eq!(visits(&replica_a), Some(7));
+        assert!(matches!(
+            replica_a.get(&path("tags")),
+            Some(CrdtValue::Set(set)) if set.contains(&String::from("admin"))
+        ));
+        assert!(matches!(
+            replica_a.get(&path("address.city")),
+            Some(CrdtValue::Register(city)) if city.value == Scalar::String(String::from("Pune"))
+        ));
+    }
+
+    #[test]
+    fn update_wins_over_concurrent_remove() {
+        let mut replica_a = ORMap::new();
+        replica_a.increment("node_1", &path("visits"), 1).unwrap();
+        let mut replica_b = replica_a.clone();
+
+        assert!(replica_a.remove(&path("visits")));
+        replica_b.increment("node_2", &path("visits"), 1).unwrap();
+
+        replica_a.merge(&replica_b);
+        replica_b.merge(&replica_a);
+        assert_eq!(visits(&replica_a), Some(2));
+        assert_eq!(visits(&replica_b), Some(2));
+    }
+
+    #[test]
+    fn recreated_field_starts_over() {
+        let mut replica_a = ORMap::new();
+        replica_a.increment("node_1", &path("visits"), 5).unwrap();
+        let mut replica_b = replica_a.clone();
+
+        replica_a.remove(&path("visits"));
+        replica_a.increment("node_1", &path("visits"), 1).unwrap();
+
+        replica_b.merge(&replica_a);
+        assert_eq!(visits(&replica_b), Some(1));
+    }
+
+    #[test]
+    fn type_mismatch_leaves_the_map_alone() {
+        let mut map = ORMap::new();
+        map.set_register("node_1", &path("name"), Scalar::String(String::from("ada")))
+            .unwrap();
+
+        assert_eq!(
+            map.increment("node_1", &path("name.first"), 1),
+            Err(MapError::TypeMismatch {
+                field: String::from("name"),
+                expected: "map",
+                found: "register",
+            })
+        );
+        assert_eq!(
+            map.add_to_set("node_1", &path("tags"), String::from("x"))
+                .and_then(|_| map.increment("node_1", &path("tags"), 1)),
+            Err(MapError::TypeMismatch {
+                field: String::from("tags"),
+                expected: "counter",
+                found: "set",
+            })
+        );
+        assert!(map.get(&path("name.first")).is_none());
+
+        //an op that would overflow a counter is refused the same way
+        let before = map.clone();
+        assert!(matches!(
+            map.decrement("node_1", &path("stats.visits"), u64::MAX),
+            Err(MapError::Overflow(_))
+        ));
+        assert!(map.get(&path("stats")).is_none());
+        assert!(map.leq(&before) && before.leq(&map));
+    }
+}
@@ -0,0 +1,152 @@
+use super::{
+    causal::{dots_leq, surviving_dots, Dot, DotContext},
+    Merge,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+//Observed-remove set. Every add is tagged with a dot, a (node id, counter) pair unique to
+//that add, and a remove drops the dots of the element this replica has seen. When merging,
+//a dot missing on one side is only dropped if that side has seen it (its context covers
+//it), so an add concurrent with a remove wins, AWSet is another name for it. Its dots can
+//also come from a map it is nested in, which is how ORMap uses it for its set fields.
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ORSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    pub entries: HashMap<T, HashSet<Dot>>,
+    //every dot this replica has seen, of adds still live or not
+    pub context: DotContext,
+}
+
+impl<T> Default for ORSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        ORSet {
+            entries: HashMap::new(),
+            context: DotContext::new(),
+        }
+    }
+}
+
+impl<T> ORSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, node_id: &str, element: T) {
+        let dot = self.context.next_dot(node_id);
+        self.add_with_dot(dot, element);
+    }
+
+    //for sets nested in an ORMap, which hands out the dots of the whole map
+    pub fn add_with_dot(&mut self, dot: Dot, element: T) {
+        self.context.observe(&dot);
+        //the new dot replaces the observed ones, they are covered by the context anyway
+        self.entries.insert(element, HashSet::from([dot]));
+    }
+
+    //returns false if the element was not in the set
+    pub fn remove(&mut self, element: &T) -> bool {
+        self.entries.remove(element).is_some()
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.entries.contains_key(element)
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &T> {
+        self.entries.keys()
+    }
+}
+
+impl<T> Merge for ORSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    fn merge(&mut self, other: &Self) {
+        let elements: HashSet<T> = self
+            .entries
+            .keys()
+            .chain(other.entries.keys())
+            .cloned()
+            .collect();
+        let empty = HashSet::new();
+
+        for element in elements {
+            let dots = surviving_dots(
+                self.entries.get(&element).unwrap_or(&empty),
+                &self.context,
+                other.entries.get(&element).unwrap_or(&empty),
+                &other.context,
+            );
+            if dots.is_empty() {
+                self.entries.remove(&element);
+            } else {
+                self.entries.insert(element, dots);
+            }
+        }
+        self.context.join(&other.context);
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        let empty = HashSet::new();
+        self.context.leq(&other.context)
+            && self
+                .entries
+                .keys()
+                .chain(other.entries.keys())
+                .all(|element| {
+                    dots_leq(
+                        self.entries.get(element).unwrap_or(&empty),
+                        &self.context,
+                        other.entries.get(element).unwrap_or(&empty),
+                        &other.context,
+                    )
+                })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_add_wins_over_remove() {
+        let mut replica_a: ORSet<String> = ORSet::new();
+        replica_a.add("node_1", String::from("hiking"));
+        let mut replica_b = replica_a.clone();
+
+        replica_a.remove(&String::from("hiking"));
+        replica_b.add("node_2", String::from("hiking"));
+
+        replica_a.merge(&replica_b);
+        replica_b.merge(&replica_a);
+        assert!(replica_a.contains(&String::from("hiking")));
+        assert!(replica_b.contains(&String::from("hiking")));
+    }
+
+    #[test]
+    fn observed_remove_sticks() {
+        let mut replica_a: ORSet<String> = ORSet::new();
+        replica_a.add("node_1", String::from("rafting"));
+        replica_a.add("node_1", String::from("hiking"));
+        let mut replica_b = replica_a.clone();
+
+        replica_b.remove(&String::from("rafting"));
+        replica_a.merge(&replica_b);
+        let mut elements: Vec<&String> = replica_a.elements().collect();
+        elements.sort();
+        assert_eq!(elements, vec!["hiking"]);
+    }
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Deserializer, Serializer};
+
+//Serializes a map as a list of (key, value) pairs, for maps keyed by dots or element ids,
+//which formats like JSON cannot use as object keys.
+
+pub fn serialize<'a, M, K, V, S>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+where
+    &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+    K: serde::Serialize + 'a,
+    V: serde::Serialize + 'a,
+    S: Serializer,
+{
+    serializer.collect_seq(map)
+}
+
+pub fn deserialize<'de, M, K, V, D>(deserializer: D) -> Result<M, D::Error>
+where
+    M: FromIterator<(K, V)>,
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    let pairs = Vec::<(K, V)>::deserialize(deserializer)?;
+    Ok(pairs.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{json_doc::to_json, or_map::ORMap, rga::Rga, CrdtValue, Merge};
+
+    fn path(p: &str) -> Vec<String> {
+        p.split('.').map(String::from).collect()
+    }
+
+    #[test]
+    fn states_read_the_same_after_a_round_trip() {
+        //a field updated on two replicas keeps a value per dot, those have to survive too
+        let mut replica_a = ORMap::new();
+        replica_a.increment("node_1", &path("visits"), 2).unwrap();
+        let mut replica_b = ORMap::new();
+        replica_b.increment("node_2", &path("visits"), 3).unwrap();
+        replica_a.merge(&replica_b);
+
+        let json = serde_json::to_string(&replica_a).unwrap();
+        let decoded: ORMap = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.entries["visits"].siblings.len(), 2);
+        //each is at or below the other, so they are the same state
+        assert!(decoded.leq(&replica_a) && replica_a.leq(&decoded));
+    }
+
+    #[test]
+    fn lists_keyed_by_element_ids_round_trip() {
+        let mut list = Rga::new();
+        list.insert("node_1", 0, CrdtValue::Map(ORMap::new()))
+            .unwrap();
+        list.insert("node_2", 1, CrdtValue::Map(ORMap::new()))
+            .unwrap();
+
+        let json = serde_json::to_string(&list).unwrap();
+        let decoded: Rga<CrdtValue> = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            decoded.iter().map(to_json).collect::<Vec<_>>(),
+            list.iter().map(to_json).collect::<Vec<_>>()
+        );
+        assert!(serde_json::from_str::<Rga<CrdtValue>>("{}").is_err());
+    }
+}
@@ -1,59 +1,152 @@
-use super::Merge;
-use std::collections::HashMap;
-use std::cmp;
+use super::{causal::VersionVector, Merge};
+use std::fmt;
 
 //Follows a (node_id, count) model, for the positive and negative counters. An example to make this clear:
-//if node_a increments a key, say called "likes", corresponding to which the value is a PNCounter, 
+//if node_a increments a key, say called "likes", corresponding to which the value is a PNCounter,
 //the state of this value becomes {p: {"node_a": 1}, n: 0}, assuming the value initially was {p: 0, n: 0}.
-//Now, node_b also did the same increment independetly to get {p: {"node_b": 1}, n:0}, Then if node_a did 
+//Now, node_b also did the same increment independetly to get {p: {"node_b": 1}, n:0}, Then if node_a did
 //another increment, it becomes {p: {"node_a": 2}, n: 0}. Now upon merging say node_b with node_a, we get
-//{p: {"node_a": 2, "node_b": 1}, n: 0}. This is obtained by taking the max across the nodes for the value 
-//of p or n, and the union-ising it. Then the final value reflected will be 2 + 1 = 3. 
+//{p: {"node_a": 2, "node_b": 1}, n: 0}. This is obtained by taking the max across the nodes for the value
+//of p or n, and the union-ising it. Then the final value reflected will be 2 + 1 = 3.
 
+//Both halves are version vectors, with the amount each node added in place of its events.
+//
+//Without anything else both would keep an entry for every node id that ever wrote, so ids
+//that are gone for good can be retired: a surviving node adds what the retired id counted
+//to its own entries and drops the retired ones. Only the survivor writes its entries, so
+//its folded ones are above every copy other replicas hold, and once those replicas forget
+//the retired id the value is the same everywhere. This is only safe once the survivor has
+//seen everything the retired id counted, and if every replica keeps forgetting it in the
+//states it receives afterwards, which the node makes sure of before retiring an id.
 
-type NodeId = String;
+//`increment` and `decrement` saturate, an entry sticks at u64::MAX and the value at the
+//bounds of i64. The checked ones refuse an op instead if the entry of the node would pass
+//u64::MAX or the value would leave i64, so a client can tell its write did not land. A
+//merge can still take the value past i64 through concurrent ops of other nodes, which no
+//single node can refuse; it reads as the bound then, use a WideCounter if that can happen.
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PNCounter {
-    pub p: HashMap<NodeId, u64>,
-    pub n: HashMap<NodeId, u64>,
+    pub p: VersionVector,
+    pub n: VersionVector,
 }
 
+//a checked op that would take a counter past what it can hold, shared by the counter types
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterOverflow {
+    //the value the counter was at, which the op left as it was
+    pub value: i128,
+    pub amount: u128,
+    pub decrement: bool,
+}
+
+impl fmt::Display for CounterOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = if self.decrement {
+            "decrementing"
+        } else {
+            "incrementing"
+        };
+        write!(
+            f,
+            "{} {} by {} would overflow the counter",
+            op, self.value, self.amount
+        )
+    }
+}
+
+impl std::error::Error for CounterOverflow {}
+
 impl Merge for PNCounter {
     //when merged, both the replicas get to a common state
-    fn merge(&mut self, other: &mut Self) {
+    fn merge(&mut self, other: &Self) {
         //merge positive counts
-        for (node, cnt) in other.p.iter() {
-            let entry = self.p.entry(node.clone()).or_insert(0);
-            *entry = cmp::max(*entry, cnt.clone());
-        }
-        
+        self.p.join(&other.p);
+
         //merge negative counts
-        for (node, cnt) in other.n.iter() {
-            let entry = self.n.entry(node.clone()).or_insert(0);
-            *entry = cmp::max(*entry, cnt.clone());
-        }
+        self.n.join(&other.n);
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        other.p.dominates(&self.p) && other.n.dominates(&self.n)
     }
 }
 
 impl PNCounter {
     pub fn new(node_id: String, p: u64, n: u64) -> Self {
-        PNCounter { p: HashMap::from([(node_id.clone(), p)]), n: HashMap::from([(node_id.clone(), n)]) }
+        let mut counter = PNCounter {
+            p: VersionVector::new(),
+            n: VersionVector::new(),
+        };
+        counter.p.advance(&node_id, p);
+        counter.n.advance(&node_id, n);
+        counter
     }
 
     pub fn increment(&mut self, node_id: String, amt: u64) {
-        *self.p.entry(node_id).or_insert(0) += amt;
+        self.p.advance(&node_id, amt);
     }
 
     pub fn decrement(&mut self, node_id: String, amt: u64) {
-        *self.n.entry(node_id).or_insert(0) += amt;
+        self.n.advance(&node_id, amt);
+    }
+
+    pub fn checked_increment(&mut self, node_id: String, amt: u64) -> Result<(), CounterOverflow> {
+        let value = self.exact_value();
+        let fits = self.p.get(&node_id).checked_add(amt).is_some()
+            && value + i128::from(amt) <= i128::from(i64::MAX);
+        if !fits {
+            return Err(CounterOverflow {
+                value,
+                amount: u128::from(amt),
+                decrement: false,
+            });
+        }
+        self.p.advance(&node_id, amt);
+        Ok(())
+    }
+
+    pub fn checked_decrement(&mut self, node_id: String, amt: u64) -> Result<(), CounterOverflow> {
+        let value = self.exact_value();
+        let fits = self.n.get(&node_id).checked_add(amt).is_some()
+            && value - i128::from(amt) >= i128::from(i64::MIN);
+        if !fits {
+            return Err(CounterOverflow {
+                value,
+                amount: u128::from(amt),
+                decrement: true,
+            });
+        }
+        self.n.advance(&node_id, amt);
+        Ok(())
+    }
+
+    //run by `survivor`, folds the counts of `retired` into its own. False if there were none
+    pub fn retire(&mut self, retired: &str, survivor: &str) -> bool {
+        let p = self.p.fold(retired, survivor);
+        let n = self.n.fold(retired, survivor);
+        p || n
+    }
+
+    //run by every other replica once `retired` was folded away. False if it had no entries
+    pub fn forget(&mut self, retired: &str) -> bool {
+        let p = self.p.entries.remove(retired).is_some();
+        let n = self.n.entries.remove(retired).is_some();
+        p || n
     }
 
     //for the user of the node to see the value of the counter
+    //summed in i128 so large counts cannot wrap around, anything outside of i64 sticks at
+    //its min or max
     pub fn value(&self) -> i64 {
-        let p_sum: u64 = self.p.values().sum();
-        let n_sum: u64 = self.n.values().sum();
-        (p_sum as i64) - (n_sum as i64)
+        self.exact_value().clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+
+    //the sums of both halves fit in i128 with room to spare, u64 entries would need more
+    //than 2^64 nodes to pass it
+    fn exact_value(&self) -> i128 {
+        self.p.total() as i128 - self.n.total() as i128
     }
 }
 
@@ -83,7 +176,7 @@ mod tests {
         replica_b.increment(node_id_b.clone(), 1); //becomes 2 now
 
         //merge b's state to a
-        replica_a.merge(&mut replica_b);
+        replica_a.merge(&replica_b);
 
         assert_eq!(replica_a.value(), 3); //as it should get b's value now
 
@@ -99,7 +192,7 @@ mod tests {
         replica_d.increment(node_id_d.clone(), 1);
         replica_d.increment(node_id_d.clone(), 1);
 
-        replica_c.merge(&mut replica_d);
+        replica_c.merge(&replica_d);
         assert_eq!(replica_c.value(), 4);
     }
 
@@ -114,12 +207,77 @@ mod tests {
         replica_b.decrement(node_id_b.clone(), 1);
 
         let mut a_then_b = replica_a.clone();
-        a_then_b.merge(&mut replica_b);
+        a_then_b.merge(&replica_b);
 
         let mut b_then_a = replica_b.clone();
-        b_then_a.merge(&mut replica_a);
+        b_then_a.merge(&replica_a);
 
         //the final state must be identical regardless of merge order
         assert_eq!(a_then_b.value(), b_then_a.value());
     }
+
+    #[test]
+    fn value_does_not_wrap() {
+        let mut counter = PNCounter::new(String::from("node_1"), u64::MAX, 0);
+        counter.increment(String::from("node_2"), u64::MAX);
+        assert_eq!(counter.value(), i64::MAX);
+
+        let mut counter = PNCounter::new(String::from("node_1"), 0, u64::MAX);
+        counter.decrement(String::from("node_2"), 1);
+        assert_eq!(counter.value(), i64::MIN);
+    }
+
+    #[test]
+    fn checked_ops_refuse_to_overflow() {
+        let mut counter = PNCounter::new(String::from("node_1"), i64::MAX as u64 - 1, 0);
+        counter
+            .checked_increment(String::from("node_2"), 1)
+            .unwrap();
+        let err = counter
+            .checked_increment(String::from("node_2"), 1)
+            .unwrap_err();
+        assert_eq!(err.value, i128::from(i64::MAX));
+        assert_eq!(counter.value(), i64::MAX);
+
+        //back down to i64::MIN and no further
+        counter
+            .checked_decrement(String::from("node_1"), u64::MAX)
+            .unwrap();
+        assert_eq!(counter.value(), i64::MIN);
+        assert!(counter
+            .checked_decrement(String::from("node_2"), 1)
+            .is_err());
+        //the entry of node_1 is full, even though the value has room
+        assert!(counter.checked_decrement(String::from("node_1"), 0).is_ok());
+        counter
+            .checked_increment(String::from("node_3"), 5)
+            .unwrap();
+        assert!(counter
+            .checked_decrement(String::from("node_1"), 1)
+            .is_err());
+    }
+
+    #[test]
+    fn retired_ids_fold_into_the_survivor_without_changing_the_value() {
+        let mut survivor = PNCounter::new(String::from("node_1"), 2, 0);
+        let mut gone = PNCounter::new(String::from("tmp_7"), 5, 1);
+        gone.merge(&survivor);
+        survivor.merge(&gone);
+        //a replica that still has the retired entries and a stale copy of the survivor's
+        let mut stale = survivor.clone();
+        survivor.increment(String::from("node_1"), 1);
+
+        assert!(survivor.retire("tmp_7", "node_1"));
+        assert_eq!(survivor.value(), 7);
+        assert!(!survivor.p.entries.contains_key("tmp_7"));
+
+        stale.merge(&survivor);
+        assert!(stale.forget("tmp_7"));
+        assert_eq!(stale.value(), 7);
+        //a copy still carrying the retired entries changes nothing once it is forgotten
+        let mut late = gone.clone();
+        late.forget("tmp_7");
+        stale.merge(&late);
+        assert_eq!(stale.value(), 7);
+    }
 }
@@ -0,0 +1,379 @@
+use super::Merge;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+//Replicated growable array, an ordered list that several nodes can edit at once.
+//Every element is inserted right after another one (its origin, None for the head of the
+//list) and gets an id made of a counter higher than any id the inserting replica has seen,
+//and its node id. The list order is a walk of the tree formed by the origins, where the
+//children of an element are visited from the highest id down. A new element therefore ends
+//up right after its origin, and elements inserted concurrently at the same place come out
+//in the same order on every replica. Deleted elements stay as tombstones, so elements that
+//were inserted after them still have an origin.
+//Moving an element inserts an empty anchor at the new place and points the element at it.
+//The pointer is last writer wins on the anchor id, which is higher than any id the mover has
+//seen, so concurrent moves of the same element leave it at exactly one place instead of
+//duplicating it, and a concurrent delete still removes it.
+
+type NodeId = String;
+//(counter, node id), compared in that order
+pub type ElementId = (u64, NodeId);
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize",
+        deserialize = "T: serde::Deserialize<'de>"
+    ))
+)]
+pub struct Rga<T> {
+    #[cfg_attr(feature = "serde", serde(with = "crate::pairs"))]
+    pub elements: HashMap<ElementId, Element<T>>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Element<T> {
+    pub origin: Option<ElementId>,
+    //None once the element is deleted, and always None for move anchors
+    pub value: Option<T>,
+    //anchor the element was last moved to, it is shown there instead of at its own place
+    pub moved_to: Option<ElementId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexOutOfBounds {
+    pub index: usize,
+    pub len: usize,
+}
+
+impl fmt::Display for IndexOutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "index {} is out of bounds for a list of length {}",
+            self.index, self.len
+        )
+    }
+}
+
+impl std::error::Error for IndexOutOfBounds {}
+
+impl<T> Default for Rga<T> {
+    fn default() -> Self {
+        Rga {
+            elements: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Rga<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //number of elements that are not deleted
+    pub fn len(&self) -> usize {
+        self.elements.values().filter(|e| e.value.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    //inserts `value` so that it ends up at `index`, `index == len()` appends
+    pub fn insert(
+        &mut self,
+        node_id: &str,
+        index: usize,
+        value: T,
+    ) -> Result<ElementId, IndexOutOfBounds> {
+        let origin = match index {
+            0 => None,
+            _ => Some(
+                self.place_of(&self.id_at(index - 1).ok_or(IndexOutOfBounds {
+                    index,
+                    len: self.len(),
+                })?),
+            ),
+        };
+        Ok(self.insert_after(node_id, origin, value))
+    }
+
+    //inserts `value` right after the element `origin`, or at the head for None
+    pub fn insert_after(
+        &mut self,
+        node_id: &str,
+        origin: Option<ElementId>,
+        value: T,
+    ) -> ElementId {
+        self.add_element(node_id, origin, Some(value))
+    }
+
+    //moves the element at `from` so that it ends up at `to`
+    pub fn move_to(
+        &mut self,
+        node_id: &str,
+        from: usize,
+        to: usize,
+    ) -> Result<(), IndexOutOfBounds> {
+        let len = self.len();
+        let mut ids = self.visible_ids();
+        if from >= len || to >= len {
+            return Err(IndexOutOfBounds {
+                index: from.max(to),
+                len,
+            });
+        }
+        let id = ids.remove(from);
+        let origin = match to {
+            0 => None,
+            _ => Some(self.place_of(&ids[to - 1])),
+        };
+        let anchor = self.add_element(node_id, origin, None);
+        self.elements
+            .get_mut(&id)
+            .expect("id comes from the list")
+            .moved_to = Some(anchor);
+        Ok(())
+    }
+
+    fn add_element(
+        &mut self,
+        node_id: &str,
+        origin: Option<ElementId>,
+        value: Option<T>,
+    ) -> ElementId {
+        let counter = self.elements.keys().map(|id| id.0).max().unwrap_or(0) + 1;
+        let id = (counter, node_id.to_string());
+        self.elements.insert(
+            id.clone(),
+            Element {
+                origin,
+                value,
+                moved_to: None,
+            },
+        );
+        id
+    }
+
+    //id of the element or anchor where the element `id` is shown
+    fn place_of(&self, id: &ElementId) -> ElementId {
+        match &self.elements[id].moved_to {
+            Some(anchor) if self.elements.contains_key(anchor) => anchor.clone(),
+            _ => id.clone(),
+        }
+    }
+
+    //deletes the element at `index` and returns its value
+    pub fn delete(&mut self, index: usize) -> Result<T, IndexOutOfBounds> {
+        let id = self.id_at(index).ok_or(IndexOutOfBounds {
+            index,
+            len: self.len(),
+        })?;
+        let element = self.elements.get_mut(&id).expect("id comes from the list");
+        Ok(element.value.take().expect("visible elements have a value"))
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let id = self.id_at(index)?;
+        self.elements.get(&id)?.value.as_ref()
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        let id = self.id_at(index)?;
+        self.elements.get_mut(&id)?.value.as_mut()
+    }
+
+    //id of the element that is at `index`, not counting deleted ones
+    pub fn id_at(&self, index: usize) -> Option<ElementId> {
+        self.visible_ids().into_iter().nth(index)
+    }
+
+    //values that are not deleted, in list order
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.visible_ids()
+            .into_iter()
+            .filter_map(move |id| self.elements.get(&id)?.value.as_ref())
+    }
+
+    fn visible_ids(&self) -> Vec<ElementId> {
+        let mut arrivals: HashMap<ElementId, Vec<&ElementId>> = HashMap::new();
+        for (id, element) in &self.elements {
+            if element.value.is_some() {
+                arrivals.entry(self.place_of(id)).or_default().push(id);
+            }
+        }
+        for ids in arrivals.values_mut() {
+            ids.sort_by(|a, b| b.cmp(a));
+        }
+        self.order()
+            .into_iter()
+            .filter_map(|place| arrivals.remove(&place))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    //every id, deleted ones included, in list order
+    fn order(&self) -> Vec<ElementId> {
+        let mut children: HashMap<Option<&ElementId>, Vec<&ElementId>> = HashMap::new();
+        for (id, element) in &self.elements {
+            children
+                .entry(element.origin.as_ref())
+                .or_default()
+                .push(id);
+        }
+        for siblings in children.values_mut() {
+            siblings.sort_by(|a, b| b.cmp(a));
+        }
+
+        let mut order = Vec::with_capacity(self.elements.len());
+        let mut visited = HashSet::new();
+        let mut stack: Vec<&ElementId> = children
+            .get(&None)
+            .map(|ids| ids.iter().rev().copied().collect())
+            .unwrap_or_default();
+        while let Some(id) = stack.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            order.push(id.clone());
+            if let Some(ids) = children.get(&Some(id)) {
+                stack.extend(ids.iter().rev().copied());
+            }
+        }
+        order
+    }
+}
+
+impl<T> Merge for Rga<T>
+where
+    T: Merge + Clone,
+{
+    //union of the elements, a delete on either side wins, values that are live on both
+    //sides are merged and the latest move wins
+    fn merge(&mut self, other: &Self) {
+        for (id, theirs) in other.elements.iter() {
+            match self.elements.get_mut(id) {
+                None => {
+                    self.elements.insert(id.clone(), theirs.clone());
+                }
+                Some(ours) => {
+                    match (&mut ours.value, &theirs.value) {
+                        (Some(our_value), Some(their_value)) => our_value.merge(their_value),
+                        (_, None) => ours.value = None,
+                        (None, Some(_)) => {}
+                    }
+                    if theirs.moved_to > ours.moved_to {
+                        ours.moved_to = theirs.moved_to.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        self.elements.iter().all(|(id, ours)| {
+            let Some(theirs) = other.elements.get(id) else {
+                return false;
+            };
+            let value_below = match (&ours.value, &theirs.value) {
+                (Some(our_value), Some(their_value)) => our_value.leq(their_value),
+                (_, None) => true,
+                (None, Some(_)) => false,
+            };
+            value_below && ours.moved_to <= theirs.moved_to
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::g_counter::GCounter;
+
+    fn values(list: &Rga<GCounter>) -> Vec<u64> {
+        list.iter().map(|c| c.value()).collect()
+    }
+
+    fn item(n: u64) -> GCounter {
+        GCounter::new(String::from("node_0"), n)
+    }
+
+    #[test]
+    fn insert_and_delete_by_index() {
+        let mut list = Rga::new();
+        list.insert("node_1", 0, item(1)).unwrap();
+        list.insert("node_1", 1, item(3)).unwrap();
+        list.insert("node_1", 1, item(2)).unwrap();
+        list.insert("node_1", 0, item(0)).unwrap();
+        assert_eq!(values(&list), vec![0, 1, 2, 3]);
+
+        assert_eq!(list.delete(1).unwrap().value(), 1);
+        assert_eq!(values(&list), vec![0, 2, 3]);
+        assert_eq!(
+            list.insert("node_1", 5, item(9)),
+            Err(IndexOutOfBounds { index: 5, len: 3 })
+        );
+    }
+
+    #[test]
+    fn concurrent_inserts_converge() {
+        let mut replica_a = Rga::new();
+        replica_a.insert("node_1", 0, item(1)).unwrap();
+        replica_a.insert("node_1", 1, item(4)).unwrap();
+        let mut replica_b = replica_a.clone();
+
+        //both insert between 1 and 4, b also deletes 4
+        replica_a.insert("node_1", 1, item(2)).unwrap();
+        replica_b.insert("node_2", 1, item(3)).unwrap();
+        replica_b.delete(2).unwrap();
+
+        let mut merged_a = replica_a.clone();
+        merged_a.merge(&replica_b);
+        replica_b.merge(&replica_a);
+        assert_eq!(values(&merged_a), values(&replica_b));
+        assert_eq!(values(&merged_a).len(), 3);
+        assert_eq!(values(&merged_a)[0], 1);
+    }
+
+    #[test]
+    fn insert_after_a_concurrently_deleted_element() {
+        let mut replica_a = Rga::new();
+        replica_a.insert("node_1", 0, item(1)).unwrap();
+        let mut replica_b = replica_a.clone();
+
+        replica_a.delete(0).unwrap();
+        replica_b.insert("node_2", 1, item(2)).unwrap();
+
+        replica_a.merge(&replica_b);
+        assert_eq!(values(&replica_a), vec![2]);
+    }
+
+    #[test]
+    fn concurrent_moves_keep_one_copy() {
+        let mut replica_a = Rga::new();
+        for n in 0..4 {
+            replica_a.insert("node_1", n, item(n as u64)).unwrap();
+        }
+        let mut replica_b = replica_a.clone();
+
+        replica_a.move_to("node_1", 0, 3).unwrap();
+        assert_eq!(values(&replica_a), vec![1, 2, 3, 0]);
+        replica_b.move_to("node_2", 0, 1).unwrap();
+        replica_b.insert("node_2", 2, item(9)).unwrap();
+        assert_eq!(values(&replica_b), vec![1, 0, 9, 2, 3]);
+
+        let mut merged_a = replica_a.clone();
+        merged_a.merge(&replica_b);
+        replica_b.merge(&replica_a);
+        assert_eq!(values(&merged_a), values(&replica_b));
+        assert_eq!(values(&merged_a).iter().filter(|v| **v == 0).count(), 1);
+        assert_eq!(merged_a.len(), 5);
+    }
+}
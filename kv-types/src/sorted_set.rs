@@ -0,0 +1,182 @@
+use super::{
+    or_map::ORMap,
+    pn_counter::{CounterOverflow, PNCounter},
+    CrdtValue, Merge,
+};
+
+//Sorted set, e.g. a leaderboard. Every member has a PN-counter score, and members are kept in
+//an observed-remove map so the score of each member merges on its own and a member that is
+//removed and added again starts over at the new score. Members are ordered by score, then by
+//name for equal scores.
+//
+//Setting a score adds the difference to the current one, so two nodes that concurrently set
+//the same member end up with both changes applied.
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SortedSet {
+    pub members: ORMap,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //sets the score of `member`, adding it if it is not in the set
+    pub fn add(&mut self, node_id: &str, member: &str, score: i64) -> Result<(), CounterOverflow> {
+        let delta = i128::from(score) - i128::from(self.score(member).unwrap_or(0));
+        self.change(node_id, member, delta).map(|_| ())
+    }
+
+    //adds `amt` to the score of `member`, which starts at 0 if it is not in the set, and
+    //returns the new score. A score that would leave i64 is refused, the set is left as it was
+    pub fn increment(
+        &mut self,
+        node_id: &str,
+        member: &str,
+        amt: i64,
+    ) -> Result<i64, CounterOverflow> {
+        self.change(node_id, member, i128::from(amt))
+    }
+
+    //false if `member` was not in the set
+    pub fn remove(&mut self, member: &str) -> bool {
+        self.members.remove(&[member.to_string()])
+    }
+
+    pub fn score(&self, member: &str) -> Option<i64> {
+        match self.members.get(&[member.to_string()])? {
+            CrdtValue::Counter(counter) => Some(counter.value()),
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranked().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    //position of `member` from the lowest score up
+    pub fn rank(&self, member: &str) -> Option<usize> {
+        self.ranked().iter().position(|(name, _)| *name == member)
+    }
+
+    //every member with its score, from the lowest score up
+    pub fn ranked(&self) -> Vec<(&str, i64)> {
+        let mut members: Vec<(&str, i64)> = self
+            .members
+            .entries
+            .iter()
+            .filter_map(|(name, entry)| match &entry.value {
+                CrdtValue::Counter(counter) => Some((name.as_str(), counter.value())),
+                _ => None,
+            })
+            .collect();
+        members.sort_by(|a, b| (a.1, a.0).cmp(&(b.1, b.0)));
+        members
+    }
+
+    //members with a score between `min` and `max` inclusive, from the lowest score up
+    pub fn range_by_score(&self, min: i64, max: i64) -> Vec<(&str, i64)> {
+        self.ranked()
+            .into_iter()
+            .filter(|(_, score)| (min..=max).contains(score))
+            .collect()
+    }
+
+    fn change(&mut self, node_id: &str, member: &str, delta: i128) -> Result<i64, CounterOverflow> {
+        //the op is tried on a copy first, a refused one must not touch the set
+        let mut updated = match self.members.get(&[member.to_string()]) {
+            Some(CrdtValue::Counter(counter)) => counter.clone(),
+            _ => PNCounter::new(node_id.to_string(), 0, 0),
+        };
+        //two scores are never more than u64::MAX apart
+        let amt = u64::try_from(delta.unsigned_abs()).expect("delta of two i64");
+        if delta >= 0 {
+            updated.checked_increment(node_id.to_string(), amt)?;
+        } else {
+            updated.checked_decrement(node_id.to_string(), amt)?;
+        }
+
+        //members only ever hold counters, anything else there is not worth keeping
+        let score = updated.value();
+        let dot = self.members.next_dot(node_id);
+        self.members
+            .assign(dot, member, CrdtValue::Counter(updated));
+        Ok(score)
+    }
+}
+
+impl Merge for SortedSet {
+    fn merge(&mut self, other: &Self) {
+        self.members.merge(&other.members);
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        self.members.leq(&other.members)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_by_score_then_name() {
+        let mut board = SortedSet::new();
+        board.add("node_1", "carol", 30).unwrap();
+        board.add("node_1", "alice", 10).unwrap();
+        board.add("node_1", "bob", 10).unwrap();
+        assert_eq!(board.increment("node_1", "alice", 25), Ok(35));
+        board.add("node_1", "bob", 5).unwrap();
+
+        assert_eq!(
+            board.ranked(),
+            vec![("bob", 5), ("carol", 30), ("alice", 35)]
+        );
+        assert_eq!(board.rank("carol"), Some(1));
+        assert_eq!(board.range_by_score(6, 100).len(), 2);
+        assert!(board.remove("carol"));
+        assert_eq!(board.score("carol"), None);
+    }
+
+    #[test]
+    fn increment_survives_concurrent_remove() {
+        let mut replica_a = SortedSet::new();
+        replica_a.add("node_1", "alice", 10).unwrap();
+        let mut replica_b = replica_a.clone();
+
+        replica_a.increment("node_1", "alice", 5).unwrap();
+        replica_b.increment("node_2", "alice", 7).unwrap();
+        replica_b.remove("alice");
+        replica_b.add("node_2", "bob", 1).unwrap();
+
+        //the increment on a is concurrent with the remove on b, so alice stays, with only
+        //the score a has seen
+        replica_a.merge(&replica_b);
+        assert_eq!(replica_a.ranked(), vec![("bob", 1), ("alice", 15)]);
+    }
+
+    #[test]
+    fn a_score_past_i64_is_refused() {
+        let mut board = SortedSet::new();
+        board.add("node_1", "alice", i64::MAX - 1).unwrap();
+        board.add("node_1", "bob", i64::MIN).unwrap();
+        let before = board.clone();
+
+        assert!(board.increment("node_2", "alice", 2).is_err());
+        assert!(board.increment("node_2", "bob", -1).is_err());
+        assert!(board.add("node_2", "carol", 1).is_ok());
+        assert!(board.add("node_2", "bob", i64::MAX).is_ok());
+
+        //the refused ops left no trace, not even in the context
+        let mut refused_only = before.clone();
+        assert!(refused_only.increment("node_2", "alice", 2).is_err());
+        assert!(refused_only.leq(&before) && before.leq(&refused_only));
+        assert_eq!(board.score("alice"), Some(i64::MAX - 1));
+    }
+}
@@ -0,0 +1,409 @@
+use super::Merge;
+use crate::rga::IndexOutOfBounds;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
+
+//Collaborative text, ordered the same way as the replicated growable array in rga.rs with
+//one element per character. Characters that are inserted together are stored as one block:
+//the block that starts at id (counter, node) holds the characters (counter + i, node), each
+//one inserted right after the one before it, so pasting a paragraph costs one entry instead
+//of one per character. A block is split when a delete or a merge only covers part of it.
+//Splitting does not change any id, so replicas that split their blocks differently still
+//agree on the text. Positions count characters, not bytes.
+
+type NodeId = String;
+//(counter, node id) of a single character
+pub type CharId = (u64, NodeId);
+//a CharId that borrows its node id, for walking the text
+type CharRef<'a> = (u64, &'a NodeId);
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Text {
+    //per node, its blocks by the counter of their first character
+    pub blocks: HashMap<NodeId, BTreeMap<u64, Block>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Block {
+    //character the first one of the block was inserted after, None for the head of the text
+    pub origin: Option<CharId>,
+    pub text: String,
+    //deleted characters stay as tombstones, text inserted after them still needs an origin
+    pub deleted: bool,
+}
+
+//a run of characters of one block that come one after the other in the text
+struct Span<'a> {
+    node_id: &'a NodeId,
+    //counter of the first character of the span
+    start: u64,
+    text: &'a str,
+    deleted: bool,
+}
+
+impl Block {
+    fn len(&self) -> u64 {
+        self.text.chars().count() as u64
+    }
+}
+
+impl Text {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //number of characters that are not deleted
+    pub fn len(&self) -> usize {
+        self.blocks
+            .values()
+            .flat_map(|blocks| blocks.values())
+            .filter(|block| !block.deleted)
+            .map(|block| block.text.chars().count())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    //inserts `text` so that it starts at `index`, `index == len()` appends
+    pub fn insert(
+        &mut self,
+        node_id: &str,
+        index: usize,
+        text: &str,
+    ) -> Result<(), IndexOutOfBounds> {
+        let len = self.len();
+        if index > len {
+            return Err(IndexOutOfBounds { index, len });
+        }
+        if text.is_empty() {
+            return Ok(());
+        }
+        let origin = match index {
+            0 => None,
+            _ => Some(self.id_at(index - 1).expect("index was checked")),
+        };
+
+        let counter = self.next_counter();
+        self.blocks.entry(node_id.to_string()).or_default().insert(
+            counter,
+            Block {
+                origin,
+                text: text.to_string(),
+                deleted: false,
+            },
+        );
+        Ok(())
+    }
+
+    //deletes `count` characters starting at `index`
+    pub fn delete(&mut self, index: usize, count: usize) -> Result<(), IndexOutOfBounds> {
+        let len = self.len();
+        if index + count > len {
+            return Err(IndexOutOfBounds {
+                index: index + count - 1,
+                len,
+            });
+        }
+
+        //(node, first counter, number of characters) of every visible run in the range
+        let mut runs = Vec::new();
+        let mut skip = index;
+        let mut left = count;
+        for span in self.spans().into_iter().filter(|span| !span.deleted) {
+            if left == 0 {
+                break;
+            }
+            let span_len = span.text.chars().count();
+            if skip >= span_len {
+                skip -= span_len;
+                continue;
+            }
+            let taken = (span_len - skip).min(left);
+            runs.push((span.node_id.clone(), span.start + skip as u64, taken as u64));
+            skip = 0;
+            left -= taken;
+        }
+
+        for (node_id, start, taken) in runs {
+            self.split_at(&node_id, start);
+            self.split_at(&node_id, start + taken);
+            for block in self
+                .blocks
+                .get_mut(&node_id)
+                .into_iter()
+                .flat_map(|blocks| {
+                    blocks
+                        .range_mut(start..start + taken)
+                        .map(|(_, block)| block)
+                })
+            {
+                block.deleted = true;
+            }
+        }
+        Ok(())
+    }
+
+    //turns the text into `new_text` with the smallest single edit that does it: the common
+    //start and end are kept and only what is between them is deleted and inserted. Text that
+    //other nodes concurrently insert into the replaced part is kept
+    pub fn update(&mut self, node_id: &str, new_text: &str) {
+        let old: Vec<char> = self.to_string().chars().collect();
+        let new: Vec<char> = new_text.chars().collect();
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        self.delete(prefix, old.len() - prefix - suffix)
+            .expect("range is within the text");
+        let inserted: String = new[prefix..new.len() - suffix].iter().collect();
+        self.insert(node_id, prefix, &inserted)
+            .expect("index is within the text");
+    }
+
+    //id of the character at `index`, not counting deleted ones
+    fn id_at(&self, index: usize) -> Option<CharId> {
+        let mut skip = index;
+        for span in self.spans().into_iter().filter(|span| !span.deleted) {
+            let span_len = span.text.chars().count();
+            if skip < span_len {
+                return Some((span.start + skip as u64, span.node_id.clone()));
+            }
+            skip -= span_len;
+        }
+        None
+    }
+
+    //a counter higher than that of any character this replica has seen
+    fn next_counter(&self) -> u64 {
+        self.blocks
+            .values()
+            .filter_map(|blocks| blocks.iter().next_back())
+            .map(|(start, block)| start + block.len())
+            .max()
+            .unwrap_or(1)
+    }
+
+    //makes sure a block starts at `counter` if a block of `node_id` contains it
+    fn split_at(&mut self, node_id: &str, counter: u64) {
+        let Some(blocks) = self.blocks.get_mut(node_id) else {
+            return;
+        };
+        let Some((&start, block)) = blocks.range_mut(..counter).next_back() else {
+            return;
+        };
+        if start + block.len() <= counter {
+            return;
+        }
+        let at = byte_offset(&block.text, (counter - start) as usize);
+        let rest = Block {
+            origin: Some((counter - 1, node_id.to_string())),
+            text: block.text.split_off(at),
+            deleted: block.deleted,
+        };
+        blocks.insert(counter, rest);
+    }
+
+    //every character, deleted ones included, in text order
+    fn spans(&self) -> Vec<Span<'_>> {
+        //blocks that were inserted after each character, and per node the counters of the
+        //characters that have any
+        let mut children: HashMap<Option<CharRef>, Vec<CharRef>> = HashMap::new();
+        let mut anchors: HashMap<&NodeId, BTreeSet<u64>> = HashMap::new();
+        for (node_id, blocks) in &self.blocks {
+            for (start, block) in blocks {
+                let origin = block.origin.as_ref().map(|(c, n)| (*c, n));
+                if let Some((counter, origin_node)) = origin {
+                    anchors.entry(origin_node).or_default().insert(counter);
+                }
+                children.entry(origin).or_default().push((*start, node_id));
+            }
+        }
+
+        //(first character to visit, block it is in), the highest id is visited first
+        let mut stack: Vec<(u64, &NodeId, u64)> = children
+            .get(&None)
+            .into_iter()
+            .flatten()
+            .map(|(c, n)| (*c, *n, *c))
+            .collect();
+        stack.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+        let mut spans = Vec::new();
+        while let Some((from, node_id, start)) = stack.pop() {
+            let block = &self.blocks[node_id][&start];
+            let last = start + block.len() - 1;
+            //the span ends at the first character something else was inserted after
+            let stop = anchors
+                .get(node_id)
+                .and_then(|counters| counters.range(from..last).next().copied())
+                .unwrap_or(last);
+            let offset = (from - start) as usize;
+            let text = &block.text[byte_offset(&block.text, offset)..];
+            let text = &text[..byte_offset(text, (stop - from + 1) as usize)];
+            spans.push(Span {
+                node_id,
+                start: from,
+                text,
+                deleted: block.deleted,
+            });
+
+            let mut next: Vec<(u64, &NodeId, u64)> = children
+                .get(&Some((stop, node_id)))
+                .into_iter()
+                .flatten()
+                .map(|(c, n)| (*c, *n, *c))
+                .collect();
+            if stop < last {
+                next.push((stop + 1, node_id, start));
+            }
+            next.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+            stack.extend(next);
+        }
+        spans
+    }
+}
+
+//byte offset of the character at `chars` in `text`, its length past the last one
+fn byte_offset(text: &str, chars: usize) -> usize {
+    text.char_indices()
+        .nth(chars)
+        .map(|(at, _)| at)
+        .unwrap_or(text.len())
+}
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for span in self.spans().into_iter().filter(|span| !span.deleted) {
+            f.write_str(span.text)?;
+        }
+        Ok(())
+    }
+}
+
+impl Merge for Text {
+    //union of the characters, a delete on either side wins
+    fn merge(&mut self, other: &Self) {
+        for (node_id, theirs) in &other.blocks {
+            for (&start, block) in theirs {
+                let end = start + block.len();
+                self.split_at(node_id, start);
+                self.split_at(node_id, end);
+                let ours = self.blocks.entry(node_id.clone()).or_default();
+
+                //parts of their block that this replica does not have yet
+                let mut missing = Vec::new();
+                let mut at = start;
+                for (&ours_start, ours_block) in ours.range_mut(start..end) {
+                    if ours_start > at {
+                        missing.push((at, ours_start));
+                    }
+                    at = ours_start + ours_block.len();
+                    ours_block.deleted |= block.deleted;
+                }
+                if at < end {
+                    missing.push((at, end));
+                }
+
+                for (from, to) in missing {
+                    let text = &block.text[byte_offset(&block.text, (from - start) as usize)..];
+                    let text = &text[..byte_offset(text, (to - from) as usize)];
+                    let origin = match from == start {
+                        true => block.origin.clone(),
+                        false => Some((from - 1, node_id.clone())),
+                    };
+                    ours.insert(
+                        from,
+                        Block {
+                            origin,
+                            text: text.to_string(),
+                            deleted: block.deleted,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    //every character of ours is there, and deleted if it is deleted here
+    fn leq(&self, other: &Self) -> bool {
+        self.blocks.iter().all(|(node_id, ours)| {
+            let Some(theirs) = other.blocks.get(node_id) else {
+                return ours.is_empty();
+            };
+            ours.iter().all(|(&start, block)| {
+                let end = start + block.len();
+                let mut at = start;
+                while at < end {
+                    let covering = theirs
+                        .range(..=at)
+                        .next_back()
+                        .filter(|(&their_start, their_block)| their_start + their_block.len() > at);
+                    let Some((&their_start, their_block)) = covering else {
+                        return false;
+                    };
+                    if block.deleted && !their_block.deleted {
+                        return false;
+                    }
+                    at = their_start + their_block.len();
+                }
+                true
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_delete_and_update() {
+        let mut text = Text::new();
+        text.insert("node_1", 0, "hello world").unwrap();
+        text.insert("node_1", 5, ",").unwrap();
+        text.delete(0, 1).unwrap();
+        text.insert("node_1", 0, "H").unwrap();
+        assert_eq!(text.to_string(), "Hello, world");
+        assert_eq!(text.len(), 12);
+
+        text.update("node_1", "Hello, wide world!");
+        assert_eq!(text.to_string(), "Hello, wide world!");
+        assert_eq!(
+            text.delete(10, 9),
+            Err(IndexOutOfBounds { index: 18, len: 18 })
+        );
+
+        //one block per insert, plus the splits of the deleted ranges
+        let blocks = text.blocks["node_1"].len();
+        assert!(blocks <= 6, "{} blocks", blocks);
+    }
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let mut replica_a = Text::new();
+        replica_a.insert("node_1", 0, "the quick fox").unwrap();
+        let mut replica_b = replica_a.clone();
+
+        //a splits the block with an insert, b deletes across it and appends
+        replica_a.insert("node_1", 10, "brown ").unwrap();
+        replica_b.delete(3, 6).unwrap();
+        replica_b.insert("node_2", 7, " jumps").unwrap();
+        assert_eq!(replica_b.to_string(), "the fox jumps");
+
+        let mut merged_a = replica_a.clone();
+        merged_a.merge(&replica_b);
+        replica_b.merge(&replica_a);
+        assert_eq!(merged_a.to_string(), "the brown fox jumps");
+        assert_eq!(replica_b.to_string(), merged_a.to_string());
+    }
+}
@@ -0,0 +1,110 @@
+use super::Merge;
+use std::{collections::HashSet, hash::Hash};
+
+//Two-phase set, for elements that must never come back once removed, e.g. revoked tokens.
+//It is a grow-only set of added elements and a grow-only set of removed ones (tombstones),
+//and an element is in the set if it was added and never removed. Merging is the union of
+//both, so a remove wins over any add, concurrent or later.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TwoPhaseSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    pub added: HashSet<T>,
+    pub removed: HashSet<T>,
+}
+
+impl<T> Default for TwoPhaseSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        TwoPhaseSet {
+            added: HashSet::new(),
+            removed: HashSet::new(),
+        }
+    }
+}
+
+impl<T> TwoPhaseSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //false if the element was removed before, it can not be added again
+    pub fn add(&mut self, element: T) -> bool {
+        if self.removed.contains(&element) {
+            return false;
+        }
+        self.added.insert(element);
+        true
+    }
+
+    //false if the element was not in the set. It is kept as a tombstone either way, so an
+    //add on another node that has not seen it yet does not bring it back
+    pub fn remove(&mut self, element: T) -> bool {
+        let present = self.contains(&element);
+        self.removed.insert(element);
+        present
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.added.contains(element) && !self.removed.contains(element)
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &T> {
+        self.added.difference(&self.removed)
+    }
+}
+
+impl<T> Merge for TwoPhaseSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    fn merge(&mut self, other: &Self) {
+        self.added.extend(other.added.iter().cloned());
+        self.removed.extend(other.removed.iter().cloned());
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        self.added.is_subset(&other.added) && self.removed.is_subset(&other.removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_elements_can_not_be_added_again() {
+        let mut set = TwoPhaseSet::new();
+        assert!(set.add("token:1"));
+        assert!(set.add("token:2"));
+        assert!(set.remove("token:1"));
+        assert!(!set.add("token:1"));
+        assert!(!set.contains(&"token:1"));
+        assert_eq!(set.elements().collect::<Vec<_>>(), vec![&"token:2"]);
+    }
+
+    #[test]
+    fn remove_wins_over_a_concurrent_add() {
+        let mut replica_a = TwoPhaseSet::new();
+        replica_a.add(String::from("token:1"));
+        let mut replica_b = replica_a.clone();
+
+        replica_a.remove(String::from("token:1"));
+        replica_b.add(String::from("token:1"));
+        replica_b.add(String::from("token:2"));
+
+        replica_a.merge(&replica_b);
+        replica_b.merge(&replica_a);
+        assert_eq!(replica_a, replica_b);
+        assert!(!replica_a.contains(&String::from("token:1")));
+        assert!(replica_a.contains(&String::from("token:2")));
+    }
+}
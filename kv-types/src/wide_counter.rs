@@ -0,0 +1,177 @@
+use super::{causal::NodeId, pn_counter::CounterOverflow, Merge};
+use std::collections::HashMap;
+
+//PNCounter with 128 bit entries and an i128 value, for aggregates that can outgrow i64, like
+//money kept in minor units (cents, or millionths for currencies that need more). Merging
+//takes the max per node of both halves, the same as PNCounter.
+//
+//There are no saturating ops: an op that would take the entry of its node past u128::MAX or
+//the value out of i128 is refused. Concurrent ops of other nodes can still take the value
+//further once merged, it reads as the bound of i128 then.
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WideCounter {
+    pub p: HashMap<NodeId, u128>,
+    pub n: HashMap<NodeId, u128>,
+}
+
+impl Merge for WideCounter {
+    fn merge(&mut self, other: &Self) {
+        join(&mut self.p, &other.p);
+        join(&mut self.n, &other.n);
+    }
+
+    fn leq(&self, other: &Self) -> bool {
+        below(&self.p, &other.p) && below(&self.n, &other.n)
+    }
+}
+
+impl WideCounter {
+    //a negative `initial` starts the counter off with a decrement
+    pub fn new(node_id: String, initial: i128) -> Self {
+        let mut counter = WideCounter::default();
+        let half = if initial < 0 {
+            &mut counter.n
+        } else {
+            &mut counter.p
+        };
+        half.insert(node_id, initial.unsigned_abs());
+        counter
+    }
+
+    pub fn increment(&mut self, node_id: String, amt: u128) -> Result<(), CounterOverflow> {
+        let (p, n) = (total(&self.p), total(&self.n));
+        let entry = self.p.get(&node_id).copied().unwrap_or(0);
+        match (entry.checked_add(amt), p.and_then(|p| p.checked_add(amt))) {
+            (Some(entry), Some(p)) if difference(Some(p), n).is_some() => {
+                self.p.insert(node_id, entry);
+                Ok(())
+            }
+            _ => Err(self.overflow(amt, false)),
+        }
+    }
+
+    pub fn decrement(&mut self, node_id: String, amt: u128) -> Result<(), CounterOverflow> {
+        let (p, n) = (total(&self.p), total(&self.n));
+        let entry = self.n.get(&node_id).copied().unwrap_or(0);
+        match (entry.checked_add(amt), n.and_then(|n| n.checked_add(amt))) {
+            (Some(entry), Some(n)) if difference(p, Some(n)).is_some() => {
+                self.n.insert(node_id, entry);
+                Ok(())
+            }
+            _ => Err(self.overflow(amt, true)),
+        }
+    }
+
+    //same as PNCounter::retire, except that an entry that would pass u128::MAX sticks there
+    pub fn retire(&mut self, retired: &str, survivor: &str) -> bool {
+        let p = fold(&mut self.p, retired, survivor);
+        let n = fold(&mut self.n, retired, survivor);
+        p || n
+    }
+
+    pub fn forget(&mut self, retired: &str) -> bool {
+        let p = self.p.remove(retired).is_some();
+        let n = self.n.remove(retired).is_some();
+        p || n
+    }
+
+    pub fn value(&self) -> i128 {
+        match difference(total(&self.p), total(&self.n)) {
+            Some(value) => value,
+            None => {
+                let saturated = |half: &HashMap<NodeId, u128>| {
+                    half.values().fold(0u128, |sum, n| sum.saturating_add(*n))
+                };
+                match saturated(&self.p) >= saturated(&self.n) {
+                    true => i128::MAX,
+                    false => i128::MIN,
+                }
+            }
+        }
+    }
+
+    fn overflow(&self, amount: u128, decrement: bool) -> CounterOverflow {
+        CounterOverflow {
+            value: self.value(),
+            amount,
+            decrement,
+        }
+    }
+}
+
+//None if the sum passes u128::MAX, which only a lot of nodes can get it to
+fn total(half: &HashMap<NodeId, u128>) -> Option<u128> {
+    half.values().try_fold(0u128, |sum, n| sum.checked_add(*n))
+}
+
+//p - n, None if either sum is too large or the difference does not fit i128
+fn difference(p: Option<u128>, n: Option<u128>) -> Option<i128> {
+    let (p, n) = (p?, n?);
+    if p >= n {
+        i128::try_from(p - n).ok()
+    } else {
+        0i128.checked_sub_unsigned(n - p)
+    }
+}
+
+fn join(half: &mut HashMap<NodeId, u128>, other: &HashMap<NodeId, u128>) {
+    for (node, n) in other {
+        let entry = half.entry(node.clone()).or_insert(0);
+        *entry = (*entry).max(*n);
+    }
+}
+
+fn below(half: &HashMap<NodeId, u128>, other: &HashMap<NodeId, u128>) -> bool {
+    half.iter()
+        .all(|(node, n)| *n <= other.get(node).copied().unwrap_or(0))
+}
+
+fn fold(half: &mut HashMap<NodeId, u128>, retired: &str, survivor: &str) -> bool {
+    let Some(n) = half.remove(retired) else {
+        return false;
+    };
+    let entry = half.entry(survivor.to_string()).or_insert(0);
+    *entry = entry.saturating_add(n);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_past_i64_and_merges_like_a_pn_counter() {
+        let mut replica_a = WideCounter::new(String::from("node_1"), i128::from(i64::MAX));
+        replica_a
+            .increment(String::from("node_1"), u128::from(u64::MAX))
+            .unwrap();
+        let mut replica_b = WideCounter::new(String::from("node_2"), -5);
+        replica_b.decrement(String::from("node_2"), 5).unwrap();
+
+        replica_a.merge(&replica_b);
+        replica_b.merge(&replica_a);
+        let expected = i128::from(i64::MAX) + i128::from(u64::MAX) - 10;
+        assert_eq!(replica_a.value(), expected);
+        assert_eq!(replica_b.value(), expected);
+        assert!(replica_a.leq(&replica_b) && replica_b.leq(&replica_a));
+    }
+
+    #[test]
+    fn ops_that_leave_i128_are_refused() {
+        let mut counter = WideCounter::new(String::from("node_1"), i128::MAX - 1);
+        counter.increment(String::from("node_2"), 1).unwrap();
+        let err = counter.increment(String::from("node_2"), 1).unwrap_err();
+        assert_eq!(err.value, i128::MAX);
+        assert_eq!(counter.p["node_2"], 1);
+
+        //down to i128::MIN, which is one further from 0 than i128::MAX
+        counter
+            .decrement(String::from("node_1"), u128::MAX)
+            .unwrap();
+        assert_eq!(counter.value(), i128::MIN);
+        assert!(counter.decrement(String::from("node_2"), 1).is_err());
+        assert!(counter.increment(String::from("node_2"), 1).is_ok());
+    }
+}